@@ -0,0 +1,3 @@
+-- Add migration script here
+-- set when `status` moves to 'unsubscribed' - NULL for everyone else
+ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz NULL;
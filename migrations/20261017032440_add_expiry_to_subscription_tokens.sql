@@ -0,0 +1,5 @@
+-- Add migration script here
+-- existing tokens start their TTL from the time of this migration
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+-- set when the token confirms a subscription - consumed tokens can't be replayed
+ALTER TABLE subscription_tokens ADD COLUMN consumed_at timestamptz NULL;
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- fixed-window counters for `POST /subscriptions/resend_confirmation` - one row per email address / IP
+CREATE TABLE resend_confirmation_throttle(
+  throttle_key TEXT NOT NULL PRIMARY KEY,
+  window_started_at timestamptz NOT NULL,
+  attempts INT NOT NULL
+);
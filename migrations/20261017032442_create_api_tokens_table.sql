@@ -0,0 +1,16 @@
+-- Add migration script here
+-- admin-issued bearer tokens for the HTTP API - only a SHA-256 of the token is stored
+CREATE TABLE api_tokens(
+  api_token_id uuid NOT NULL PRIMARY KEY,
+  user_id uuid NOT NULL REFERENCES users(user_id),
+  name TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  -- first characters of the token, shown in the admin area to tell tokens apart
+  token_prefix TEXT NOT NULL,
+  scopes TEXT[] NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  expires_at timestamptz,
+  last_used_at timestamptz,
+  revoked_at timestamptz
+);
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
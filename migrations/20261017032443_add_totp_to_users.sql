@@ -0,0 +1,15 @@
+-- Add migration script here
+-- optional TOTP second factor for admin logins
+-- secret is AES-256-GCM encrypted (key derived from `hmac_secret`), `totp_enabled_at` is NULL until enrollment is confirmed
+ALTER TABLE users ADD COLUMN totp_secret_encrypted TEXT;
+ALTER TABLE users ADD COLUMN totp_enabled_at timestamptz;
+-- last accepted time step - a code can't be replayed within its validity window
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT;
+
+-- one-time recovery codes, only a SHA-256 of each code is stored
+CREATE TABLE totp_recovery_codes(
+  user_id uuid NOT NULL REFERENCES users(user_id),
+  code_hash TEXT NOT NULL,
+  used_at timestamptz,
+  PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- where password reset links are sent - users without one can't reset their password by email
+ALTER TABLE users ADD COLUMN email TEXT;
+-- bumped on password reset - sessions carry the value they were created with, older ones are rejected
+ALTER TABLE users ADD COLUMN session_generation INTEGER NOT NULL DEFAULT 0;
+
+-- single use, expiring - only a SHA-256 of the emailed token is stored
+CREATE TABLE password_reset_tokens(
+  token_hash TEXT NOT NULL PRIMARY KEY,
+  user_id uuid NOT NULL REFERENCES users(user_id),
+  created_at timestamptz NOT NULL DEFAULT now(),
+  expires_at timestamptz NOT NULL,
+  consumed_at timestamptz
+);
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- what a user may do in the admin area - everyone created before roles existed keeps full access
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+  CHECK (role IN ('owner', 'editor', 'viewer'));
+-- new (invited) users get their role picked explicitly
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+-- deactivated users can't log in and their sessions + API tokens stop working - the row (and what references it) stays
+ALTER TABLE users ADD COLUMN deactivated_at timestamptz;
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- drafts are stored without delivery tasks until someone publishes them - existing issues were all sent already
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NOT NULL DEFAULT 'published'
+  CHECK (status IN ('draft', 'published'));
+ALTER TABLE newsletter_issues ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_published_at_check
+  CHECK ((status = 'published') = (published_at IS NOT NULL));
+-- lists drafts newest first
+ALTER TABLE newsletter_issues ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
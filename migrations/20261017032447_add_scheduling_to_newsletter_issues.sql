@@ -0,0 +1,12 @@
+-- Add migration script here
+-- `scheduled` issues are released into delivery by the scheduler once `scheduled_for` has passed
+ALTER TABLE newsletter_issues DROP CONSTRAINT newsletter_issues_status_check;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+  CHECK (status IN ('draft', 'scheduled', 'published'));
+-- kept after release - records when the issue was meant to go out
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_scheduled_for_check
+  CHECK (status <> 'scheduled' OR scheduled_for IS NOT NULL);
+-- the scheduler looks for due issues every few seconds
+CREATE INDEX newsletter_issues_scheduled_for_idx ON newsletter_issues (scheduled_for)
+  WHERE status = 'scheduled';
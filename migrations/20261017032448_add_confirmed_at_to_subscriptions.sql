@@ -0,0 +1,5 @@
+-- Add migration script here
+-- when the subscriber (last) confirmed - `{{ confirmed_at }}` in newsletter issues
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
+-- best guess for existing subscribers - the confirmation time itself was never recorded
+UPDATE subscriptions SET confirmed_at = subscribed_at WHERE status = 'confirmed';
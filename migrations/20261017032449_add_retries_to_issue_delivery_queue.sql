@@ -0,0 +1,16 @@
+-- Add migration script here
+-- failed sends stay queued with a back off instead of being dropped
+ALTER TABLE issue_delivery_queue
+  ADD COLUMN n_retries SMALLINT NOT NULL DEFAULT 0,
+  ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+
+-- tasks that ran out of retries - kept so a lost delivery is never silent
+CREATE TABLE failed_deliveries(
+  newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+  subscriber_email TEXT NOT NULL,
+  n_retries SMALLINT NOT NULL,
+  last_error TEXT NOT NULL,
+  failed_at timestamptz NOT NULL,
+  PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
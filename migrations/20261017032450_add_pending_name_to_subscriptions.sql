@@ -0,0 +1,3 @@
+-- Add migration script here
+-- name from a repeated sign-up - only replaces `name` once the new confirmation link is used
+ALTER TABLE subscriptions ADD COLUMN pending_name TEXT NULL;
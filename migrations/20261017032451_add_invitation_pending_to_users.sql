@@ -0,0 +1,3 @@
+-- Add migration script here
+-- invited users who haven't picked a password through their link yet - only they can be sent a new invitation
+ALTER TABLE users ADD COLUMN invitation_pending BOOLEAN NOT NULL DEFAULT FALSE;
@@ -1,104 +0,0 @@
-use crate::telemetry::spawn_blocking_with_tracing;
-use anyhow::Context;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
-use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
-
-pub struct Credentials {
-    pub username: String,
-    pub password: Secret<String>,
-}
-
-// -- VALIDATION for AUTH -- //
-
-#[tracing::instrument(name = "Validate credentials", skip(credentials, db_pool))]
-pub async fn validate_credentials(
-    credentials: Credentials,
-    db_pool: &PgPool,
-) -> Result<uuid::Uuid, AuthError> {
-    // initialized fields to ensure no early return on `401` (obscure whether input data exists in db, mask response times in both scenarios to "same")
-    // ie. no statistically significant time diff between 'ok' and 'bad' res from user/outsider perspective
-    let mut user_id = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-            gZiV/M1gPc22ElAH/Jh1Hw$\
-            CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .to_string(),
-    );
-
-    if let Some((stored_user_id, stored_password_hash)) =
-        get_stored_credentials(&credentials.username, db_pool).await?
-    // .map_err(PublishError::UnexpectedError)?
-    {
-        user_id = Some(stored_user_id);
-        expected_password_hash = stored_password_hash;
-    }
-
-    spawn_blocking_with_tracing(move || {
-        verify_password_hash(expected_password_hash, credentials.password)
-    })
-    .await
-    .context("Failed to spawn blocking task")??;
-    // .map_err(PublishError::UnexpectedError)??;
-
-    // only set user_id to `Some` if found credentials (the USER) in store
-    // -> even if default password ends up matching (!?) with provided password, never auth non-existant user
-    // user_id.ok_or_else(|| PublishError::AuthError(anyhow::anyhow!("Unknown username")))
-    user_id
-        .ok_or_else(|| anyhow::anyhow!("Unknown username"))
-        .map_err(AuthError::InvalidCredentials)
-}
-
-// -- HELPERS for AUTH -- //
-
-// verify password hashes
-#[tracing::instrument(
-    name = "Verify password hash",
-    skip(expected_password_hash, password_candidate)
-)]
-fn verify_password_hash(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
-) -> Result<(), AuthError> {
-    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
-        .context("Failed to parse hash in PHC string format")
-        .map_err(AuthError::UnexpectedError)?;
-
-    Argon2::default()
-        .verify_password(
-            password_candidate.expose_secret().as_bytes(),
-            &expected_password_hash,
-        )
-        .context("Invalid password")
-        .map_err(AuthError::InvalidCredentials)
-}
-
-// validate credentials from db
-#[tracing::instrument(name = "Get stored credentials", skip(username, db_pool))]
-async fn get_stored_credentials(
-    username: &str,
-    db_pool: &PgPool,
-) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
-    let row = sqlx::query!(
-        r#"
-        SELECT user_id, password_hash FROM users WHERE username = $1
-        "#,
-        username
-    )
-    .fetch_optional(db_pool)
-    .await
-    .context("failed to perform a query to retrieve stored credentials")?
-    .map(|row| (row.user_id, Secret::new(row.password_hash)));
-
-    Ok(row)
-}
-
-// -- ERRORS for AUTH -- //
-
-#[derive(thiserror::Error, Debug)]
-pub enum AuthError {
-    #[error("Invalid credentials")]
-    InvalidCredentials(#[source] anyhow::Error),
-    #[error(transparent)]
-    UnexpectedError(#[from] anyhow::Error),
-}
@@ -0,0 +1,276 @@
+use super::{AuthError, Permission};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// `z2p_` makes leaked tokens easy to grep for (secret scanners, logs)
+const TOKEN_PREFIX: &str = "z2p_";
+const TOKEN_LENGTH: usize = 40;
+// characters of the token kept in plain text so admins can tell tokens apart
+const DISPLAY_PREFIX_LENGTH: usize = 12;
+
+// what a token is allowed to do - stored as the `as_str` value in `api_tokens.scopes`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    NewslettersPublish,
+    SubscribersRead,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 2] = [ApiScope::NewslettersPublish, ApiScope::SubscribersRead];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::NewslettersPublish => "newsletters:publish",
+            ApiScope::SubscribersRead => "subscribers:read",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known API scope", s))
+    }
+
+    // a token can only carry scopes its owner's role allows
+    pub fn required_permission(&self) -> Permission {
+        match self {
+            ApiScope::NewslettersPublish => Permission::PublishNewsletters,
+            ApiScope::SubscribersRead => Permission::ViewSubscribers,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// a token that passed `validate_api_token` - scope checks are left to the handler
+pub struct AuthenticatedApiToken {
+    pub api_token_id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<ApiScope>,
+}
+
+impl AuthenticatedApiToken {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+// row shown on the admin page - never includes the token itself
+pub struct ApiTokenSummary {
+    pub api_token_id: Uuid,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// -- VALIDATION for API TOKENS -- //
+
+// tokens are 40 random alphanumerics -> a plain SHA-256 is enough (no Argon2 on every API call)
+// and lets us look the token up by its hash
+#[tracing::instrument(name = "Validate API token", skip(token, db_pool))]
+pub async fn validate_api_token(
+    token: Secret<String>,
+    db_pool: &PgPool,
+) -> Result<AuthenticatedApiToken, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        WHERE
+            token_hash = $1 AND
+            revoked_at IS NULL AND
+            (expires_at IS NULL OR expires_at > now()) AND
+            -- a deactivated user's tokens stop working with them (and work again on reactivation)
+            user_id IN (SELECT user_id FROM users WHERE deactivated_at IS NULL)
+        RETURNING api_token_id, user_id, scopes
+        "#,
+        hash_token(&token)
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to perform a query to validate an API token")?
+    .ok_or_else(|| anyhow::anyhow!("Unknown, expired or revoked API token"))
+    .map_err(AuthError::InvalidCredentials)?;
+
+    Ok(AuthenticatedApiToken {
+        api_token_id: row.api_token_id,
+        user_id: row.user_id,
+        // scopes dropped from the code since the token was issued simply don't apply anymore
+        scopes: row
+            .scopes
+            .iter()
+            .filter_map(|scope| ApiScope::parse(scope).ok())
+            .collect(),
+    })
+}
+
+// -- MANAGE API TOKENS -- //
+
+// returns the only copy of the plain text token - it has to be shown to the admin right away
+#[tracing::instrument(name = "Create API token", skip(db_pool))]
+pub async fn create_api_token(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(Uuid, Secret<String>), anyhow::Error> {
+    let api_token_id = Uuid::new_v4();
+    let token = generate_api_token();
+    let token_prefix: String = token
+        .expose_secret()
+        .chars()
+        .take(DISPLAY_PREFIX_LENGTH)
+        .collect();
+    let scopes: Vec<String> = scopes.iter().map(|scope| scope.as_str().into()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (
+            api_token_id,
+            user_id,
+            name,
+            token_hash,
+            token_prefix,
+            scopes,
+            expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        api_token_id,
+        user_id,
+        name,
+        hash_token(&token),
+        token_prefix,
+        &scopes,
+        expires_at
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to store API token")?;
+
+    Ok((api_token_id, token))
+}
+
+// `false` if the token doesn't exist, belongs to someone else or was already revoked
+#[tracing::instrument(name = "Revoke API token", skip(db_pool))]
+pub async fn revoke_api_token(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    api_token_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE api_token_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        api_token_id,
+        user_id
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to revoke API token")?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+// newest first
+#[tracing::instrument(name = "List API tokens", skip(db_pool))]
+pub async fn list_api_tokens(
+    db_pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<ApiTokenSummary>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiTokenSummary,
+        r#"
+        SELECT
+            api_token_id,
+            name,
+            token_prefix,
+            scopes,
+            created_at,
+            expires_at,
+            last_used_at,
+            revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve API tokens")?;
+
+    Ok(tokens)
+}
+
+// -- HELPERS for API TOKENS -- //
+
+fn generate_api_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(TOKEN_LENGTH)
+        .collect();
+    Secret::new(format!("{}{}", TOKEN_PREFIX, random))
+}
+
+fn hash_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_api_token, hash_token, ApiScope};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::{ExposeSecret, Secret};
+
+    #[test]
+    fn scopes_round_trip_through_their_string_form() {
+        for scope in ApiScope::ALL {
+            assert_ok_eq!(ApiScope::parse(scope.as_str()), scope);
+        }
+    }
+
+    #[test]
+    fn unknown_scopes_are_rejected() {
+        assert_err!(ApiScope::parse("newsletters:delete"));
+        assert_err!(ApiScope::parse(""));
+    }
+
+    #[test]
+    fn generated_tokens_are_prefixed_and_unique() {
+        let a = generate_api_token();
+        let b = generate_api_token();
+        assert!(a.expose_secret().starts_with("z2p_"));
+        assert_eq!(a.expose_secret().len(), 44);
+        assert_ne!(a.expose_secret(), b.expose_secret());
+    }
+
+    #[test]
+    fn token_hash_is_deterministic_hex() {
+        let token = Secret::new("z2p_abc".to_string());
+        let hash = hash_token(&token);
+        assert_eq!(hash, hash_token(&token));
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, hash_token(&Secret::new("z2p_abd".to_string())));
+    }
+}
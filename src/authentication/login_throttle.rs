@@ -0,0 +1,350 @@
+use super::{validate_credentials, verify_second_factor, AuthError, Credentials, TotpCipher};
+use crate::client_ip::ClientIp;
+use crate::configuration::{Argon2Settings, LoginThrottleSettings};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+// brute-force protection for every username/password check (login form + `Basic` auth on the API)
+// -> failed attempts are counted per username and per IP in Redis, each failure is answered a little slower
+// and once either counter reaches its threshold, attempts are rejected without checking the password
+pub struct LoginThrottle {
+    // cheap to clone, reconnects on its own
+    redis: ConnectionManager,
+    settings: LoginThrottleSettings,
+}
+
+struct ThrottleKeys {
+    username: String,
+    ip: String,
+}
+
+impl LoginThrottle {
+    pub async fn new(
+        redis_uri: &Secret<String>,
+        settings: LoginThrottleSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let client =
+            redis::Client::open(redis_uri.expose_secret().as_str()).context("Invalid Redis URI")?;
+        let redis = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(Self { redis, settings })
+    }
+
+    // drop-in for `validate_credentials` - a locked out username / IP fails with the same `InvalidCredentials`
+    // as a wrong password so callers show the same generic error either way
+    #[tracing::instrument(
+        name = "Validate credentials with throttling",
+        skip(self, credentials, argon2, db_pool)
+    )]
+    pub async fn validate_credentials(
+        &self,
+        credentials: Credentials,
+        client_ip: ClientIp,
+        argon2: &Argon2Settings,
+        db_pool: &PgPool,
+    ) -> Result<Uuid, AuthError> {
+        let keys = self.keys(&credentials.username, client_ip);
+        // the attempt is counted *before* the password is checked - concurrent guesses can't all slip in
+        // under the threshold while the (slow) hash is computed
+        let attempts = match self.reserve_attempt(&keys).await? {
+            Some(attempts) => attempts,
+            None => {
+                return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                    "Too many failed login attempts - temporarily locked out"
+                )))
+            }
+        };
+
+        let username = credentials.username.clone();
+        match validate_credentials(credentials, argon2, db_pool).await {
+            Ok(user_id) => {
+                self.release_attempt(&keys)
+                    .await
+                    .context("Failed to reset failed login counter")?;
+                Ok(user_id)
+            }
+            Err(AuthError::InvalidCredentials(err)) => {
+                let failures = self
+                    .record_failure(&keys, attempts, &username, client_ip)
+                    .await?;
+                tokio::time::sleep(backoff_delay(
+                    failures,
+                    Duration::from_millis(self.settings.base_delay_milliseconds),
+                    Duration::from_millis(self.settings.max_delay_milliseconds),
+                ))
+                .await;
+                Err(AuthError::InvalidCredentials(err))
+            }
+            Err(err) => {
+                // not the user's fault -> don't hold it against them
+                self.release_attempt(&keys)
+                    .await
+                    .context("Failed to reset failed login counter")?;
+                Err(err)
+            }
+        }
+    }
+
+    fn keys(&self, username: &str, client_ip: ClientIp) -> ThrottleKeys {
+        ThrottleKeys {
+            username: format!(
+                "{}:username:{}",
+                self.settings.key_prefix,
+                username.to_lowercase()
+            ),
+            ip: format!("{}:ip:{}", self.settings.key_prefix, client_ip),
+        }
+    }
+
+    // bumps both counters in one `MULTI` -> `None` if either is past its threshold
+    // (rejected attempts still count, the window isn't extended by them)
+    async fn reserve_attempt(
+        &self,
+        keys: &ThrottleKeys,
+    ) -> Result<Option<Attempts>, anyhow::Error> {
+        let attempts = self.increment(keys).await?;
+        let locked_out = attempts.username > self.settings.max_failures_per_username
+            || attempts.ip > self.settings.max_failures_per_ip;
+        Ok((!locked_out).then_some(attempts))
+    }
+
+    async fn increment(&self, keys: &ThrottleKeys) -> Result<Attempts, anyhow::Error> {
+        let window = self.settings.lockout_seconds as usize;
+        let (username, ip): (u32, u32) = redis::pipe()
+            .atomic()
+            // a fresh counter starts its window here, an existing one keeps its expiry
+            .cmd("SET")
+            .arg(&keys.username)
+            .arg(0)
+            .arg("EX")
+            .arg(window)
+            .arg("NX")
+            .ignore()
+            .incr(&keys.username, 1)
+            .cmd("SET")
+            .arg(&keys.ip)
+            .arg(0)
+            .arg("EX")
+            .arg(window)
+            .arg("NX")
+            .ignore()
+            .incr(&keys.ip, 1)
+            .query_async(&mut self.redis.clone())
+            .await
+            .context("Failed to increment throttle counter")?;
+        Ok(Attempts { username, ip })
+    }
+
+    // a valid login clears the username counter, but only takes back its own attempt from the IP counter
+    // -> it must not wipe the failures of a shared IP
+    async fn release_attempt(&self, keys: &ThrottleKeys) -> Result<(), anyhow::Error> {
+        let window = self.settings.lockout_seconds as usize;
+        redis::pipe()
+            .atomic()
+            .del(&keys.username)
+            .ignore()
+            // guards against the IP counter expiring mid-login -> never left behind without an expiry
+            .cmd("SET")
+            .arg(&keys.ip)
+            .arg(0)
+            .arg("EX")
+            .arg(window)
+            .arg("NX")
+            .ignore()
+            .incr(&keys.ip, -1)
+            .ignore()
+            .query_async::<_, ()>(&mut self.redis.clone())
+            .await?;
+        Ok(())
+    }
+
+    // the attempt is already counted - only logs the lockout and restarts its window
+    // returns the highest of the two counters - drives the progressive delay
+    async fn record_failure(
+        &self,
+        keys: &ThrottleKeys,
+        attempts: Attempts,
+        username: &str,
+        client_ip: ClientIp,
+    ) -> Result<u32, anyhow::Error> {
+        if attempts.username == self.settings.max_failures_per_username {
+            self.restart_window(&keys.username).await?;
+            tracing::warn!(
+                username = %username,
+                lockout_seconds = self.settings.lockout_seconds,
+                "Username locked out after too many failed login attempts"
+            );
+        }
+        if attempts.ip == self.settings.max_failures_per_ip {
+            self.restart_window(&keys.ip).await?;
+            tracing::warn!(
+                client_ip = %client_ip,
+                lockout_seconds = self.settings.lockout_seconds,
+                "IP address locked out after too many failed login attempts"
+            );
+        }
+
+        Ok(attempts.username.max(attempts.ip))
+    }
+
+    // fixed window starting at the first attempt - restarted when the threshold is hit so a lockout always lasts `lockout_seconds`
+    async fn restart_window(&self, key: &str) -> Result<(), anyhow::Error> {
+        self.redis
+            .clone()
+            .expire::<_, ()>(key, self.settings.lockout_seconds as usize)
+            .await
+            .context("Failed to set expiry on failed login counter")
+    }
+}
+
+// -- SECOND FACTOR -- //
+
+pub enum SecondFactorCheck {
+    Valid,
+    Invalid,
+    // no more codes accepted for this user until the lockout window has passed
+    LockedOut,
+}
+
+impl LoginThrottle {
+    // drop-in for `verify_second_factor` - wrong codes are counted per user rather than per pending login,
+    // so entering the password again doesn't buy a fresh set of guesses
+    #[tracing::instrument(
+        name = "Verify second factor with throttling",
+        skip(self, db_pool, cipher, code)
+    )]
+    pub async fn verify_second_factor(
+        &self,
+        db_pool: &PgPool,
+        cipher: &TotpCipher,
+        user_id: Uuid,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<SecondFactorCheck, anyhow::Error> {
+        let key = format!("{}:2fa:{}", self.settings.key_prefix, user_id);
+        // counted before the code is checked - same reasoning as for passwords
+        let (attempts,): (u32,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(&key)
+            .arg(0)
+            .arg("EX")
+            .arg(self.settings.lockout_seconds as usize)
+            .arg("NX")
+            .ignore()
+            .incr(&key, 1)
+            .query_async(&mut self.redis.clone())
+            .await
+            .context("Failed to increment failed authentication code counter")?;
+        if attempts > self.settings.max_second_factor_failures {
+            return Ok(SecondFactorCheck::LockedOut);
+        }
+
+        if verify_second_factor(db_pool, cipher, user_id, code, now).await? {
+            self.redis
+                .clone()
+                .del::<_, ()>(&key)
+                .await
+                .context("Failed to reset failed authentication code counter")?;
+            return Ok(SecondFactorCheck::Valid);
+        }
+        if attempts == self.settings.max_second_factor_failures {
+            self.restart_window(&key).await?;
+            tracing::warn!(
+                lockout_seconds = self.settings.lockout_seconds,
+                "Second factor locked out after too many invalid authentication codes"
+            );
+            return Ok(SecondFactorCheck::LockedOut);
+        }
+        Ok(SecondFactorCheck::Invalid)
+    }
+}
+
+// -- PASSWORD RESET REQUESTS -- //
+
+impl LoginThrottle {
+    // every request counts, not just failures - each one can end up sending an email
+    #[tracing::instrument(name = "Throttle password reset requests", skip(self))]
+    pub async fn password_reset_allowed(
+        &self,
+        username: &str,
+        client_ip: ClientIp,
+    ) -> Result<bool, anyhow::Error> {
+        let keys = ThrottleKeys {
+            username: format!(
+                "{}:reset:username:{}",
+                self.settings.key_prefix,
+                username.to_lowercase()
+            ),
+            ip: format!("{}:reset:ip:{}", self.settings.key_prefix, client_ip),
+        };
+        let requests = self.increment(&keys).await?;
+        Ok(
+            requests.username <= self.settings.max_password_resets_per_username
+                && requests.ip <= self.settings.max_password_resets_per_ip,
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Attempts {
+    username: u32,
+    ip: u32,
+}
+
+// `base * 2^(failures - 1)`, capped at `max`
+fn backoff_delay(failures: u32, base: Duration, max: Duration) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+    let factor = 2u32.saturating_pow(failures - 1);
+    base.saturating_mul(factor).min(max)
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::backoff_delay;
+    use std::time::Duration;
+
+    #[test]
+    fn no_delay_before_the_first_failure() {
+        let delay = backoff_delay(0, Duration::from_millis(250), Duration::from_secs(4));
+        assert_eq!(delay, Duration::ZERO);
+    }
+
+    #[test]
+    fn delay_doubles_with_each_failure() {
+        let base = Duration::from_millis(250);
+        let max = Duration::from_secs(4);
+        let delays: Vec<_> = (1..=4).map(|n| backoff_delay(n, base, max)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(250),
+                Duration::from_millis(500),
+                Duration::from_millis(1000),
+                Duration::from_millis(2000),
+            ]
+        );
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let max = Duration::from_secs(4);
+        assert_eq!(backoff_delay(6, Duration::from_millis(250), max), max);
+        // no overflow for absurd counters
+        assert_eq!(
+            backoff_delay(u32::MAX, Duration::from_millis(250), max),
+            max
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use super::Role;
+use crate::session_state::TypedSession;
+use crate::utils::{err500, see_other};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpMessage};
+use anyhow::Context as _;
+use sqlx::PgPool;
+use std::future::{ready, Future, Ready};
+use std::ops::Deref;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use tracing_actix_web::RootSpan;
+use uuid::Uuid;
+
+// set by `RejectAnonymousUsers` (next to the user's `Role`) - handlers behind it take `web::ReqData<UserId>` instead of re-checking the session
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// `None` if the user doesn't exist (anymore)
+#[tracing::instrument(name = "Get session generation", skip(db_pool))]
+pub async fn get_session_generation(
+    db_pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<i32>, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT session_generation FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to retrieve session generation")?;
+    Ok(row.map(|row| row.session_generation))
+}
+
+// what `RejectAnonymousUsers` needs to know about the session's user
+struct ActiveUser {
+    session_generation: i32,
+    role: Role,
+}
+
+// `None` if the user doesn't exist (anymore) or was deactivated
+#[tracing::instrument(name = "Get active user", skip(db_pool))]
+async fn get_active_user(
+    db_pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<ActiveUser>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT session_generation, role FROM users
+        WHERE user_id = $1 AND deactivated_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to retrieve active user")?;
+
+    row.map(|row| {
+        Ok(ActiveUser {
+            session_generation: row.session_generation,
+            role: Role::parse(&row.role).map_err(anyhow::Error::msg)?,
+        })
+    })
+    .transpose()
+}
+
+// -- REJECT ANONYMOUS USERS -- //
+
+// wraps a scope (ie. `/admin`) -> no (or an outdated) session means `303` to `/login` before the handler ever runs
+// also loads the user's role on every request, so role changes and deactivations apply right away
+pub struct RejectAnonymousUsers;
+
+impl<S, B> Transform<S, ServiceRequest> for RejectAnonymousUsers
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RejectAnonymousUsersMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RejectAnonymousUsersMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RejectAnonymousUsersMiddleware<S> {
+    // `Rc` so the inner service can be moved into the (non-`Send`) response future
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RejectAnonymousUsersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let session = req.extract::<TypedSession>().await?;
+            match session.get_user_id().map_err(err500)? {
+                Some(user_id) => {
+                    // sessions created before a password reset (or for a deleted / deactivated user) are dropped
+                    let db_pool = req
+                        .app_data::<web::Data<PgPool>>()
+                        .ok_or_else(|| err500("`PgPool` is not registered as app data"))?;
+                    let active_user = get_active_user(db_pool, user_id).await.map_err(err500)?;
+                    let role = match active_user {
+                        Some(active_user)
+                            if session.get_session_generation().map_err(err500)?
+                                == Some(active_user.session_generation) =>
+                        {
+                            active_user.role
+                        }
+                        _ => {
+                            session.log_out();
+                            return Ok(req
+                                .into_response(see_other("/login"))
+                                .map_into_right_body());
+                        }
+                    };
+
+                    // `user_id` is declared up front by `AppRootSpanBuilder` - record it on the request's root span
+                    if let Some(root_span) = req.extensions().get::<RootSpan>() {
+                        root_span.record("user_id", &tracing::field::display(user_id));
+                    }
+                    req.extensions_mut().insert(UserId(user_id));
+                    req.extensions_mut().insert(role);
+                    service
+                        .call(req)
+                        .await
+                        .map(ServiceResponse::map_into_left_body)
+                }
+                None => Ok(req.into_response(see_other("/login")).map_into_right_body()),
+            }
+        })
+    }
+}
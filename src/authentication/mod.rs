@@ -0,0 +1,31 @@
+mod api_token;
+mod login_throttle;
+mod middleware;
+mod password;
+mod password_reset;
+mod roles;
+mod two_factor;
+mod users;
+
+pub use api_token::{
+    create_api_token, list_api_tokens, revoke_api_token, validate_api_token, ApiScope,
+    ApiTokenSummary, AuthenticatedApiToken,
+};
+pub use login_throttle::{LoginThrottle, SecondFactorCheck};
+pub use middleware::{get_session_generation, RejectAnonymousUsers, UserId};
+pub use password::{
+    change_password, check_password_policy, validate_credentials, AuthError, Credentials,
+};
+pub use password_reset::{
+    create_password_reset_token, get_user_email, is_password_reset_token_valid, reset_password,
+    set_user_email, PasswordResetRequest,
+};
+pub use roles::{get_user_role, require_permission, Permission, Role};
+pub use two_factor::{
+    begin_two_factor_enrollment, confirm_two_factor_enrollment, disable_two_factor,
+    get_two_factor_status, verify_second_factor, TotpCipher, TotpSecret, TwoFactorStatus,
+};
+pub use users::{
+    change_user_role, create_user, deactivate_user, invite_user, list_users, reactivate_user,
+    reissue_invitation, Invitation, ReissuedInvitation, UserSummary,
+};
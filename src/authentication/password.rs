@@ -0,0 +1,330 @@
+use crate::configuration::Argon2Settings;
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use tracing::Instrument;
+
+// OWASP - long enough to resist guessing, capped so hashing stays cheap (no DoS via huge passwords)
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+// -- VALIDATION for AUTH -- //
+
+// hashes with weaker parameters than `argon2` are upgraded in the background after a successful check
+#[tracing::instrument(name = "Validate credentials", skip(credentials, argon2, db_pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    argon2: &Argon2Settings,
+    db_pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    // initialized fields to ensure no early return on `401` (obscure whether input data exists in db, mask response times in both scenarios to "same")
+    // ie. no statistically significant time diff between 'ok' and 'bad' res from user/outsider perspective
+    let mut user_id = None;
+    let mut expected_password_hash = dummy_password_hash(argon2);
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, db_pool).await?
+    // .map_err(PublishError::UnexpectedError)?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    let stored_password_hash = expected_password_hash.clone();
+    let password = credentials.password.clone();
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking task")??;
+    // .map_err(PublishError::UnexpectedError)??;
+
+    // only set user_id to `Some` if found credentials (the USER) in store
+    // -> even if default password ends up matching (!?) with provided password, never auth non-existant user
+    // user_id.ok_or_else(|| PublishError::AuthError(anyhow::anyhow!("Unknown username")))
+    let user_id = user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username"))
+        .map_err(AuthError::InvalidCredentials)?;
+
+    // the plain text password is only around right now - last chance to rehash with the current parameters
+    if needs_rehash(&stored_password_hash, argon2) {
+        let argon2 = argon2.clone();
+        let db_pool = db_pool.clone();
+        tokio::spawn(
+            async move {
+                if let Err(err) = upgrade_password_hash(
+                    user_id,
+                    stored_password_hash,
+                    password,
+                    &argon2,
+                    &db_pool,
+                )
+                .await
+                {
+                    tracing::error!(
+                        err.cause_chain = ?err,
+                        err.message = %err,
+                        "Failed to upgrade password hash",
+                    );
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+
+    Ok(user_id)
+}
+
+// -- CHANGE PASSWORD -- //
+
+#[tracing::instrument(name = "Change password", skip(password, argon2, db_pool))]
+pub async fn change_password(
+    user_id: uuid::Uuid,
+    password: Secret<String>,
+    argon2: &Argon2Settings,
+    db_pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = hash_password(password, argon2).await?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to change user's password in the database")?;
+
+    Ok(())
+}
+
+// length in chars, not bytes - non-ASCII passwords aren't penalized
+pub fn check_password_policy(password: &Secret<String>) -> Result<(), String> {
+    let length = password.expose_secret().chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        Err(format!(
+            "The new password must be at least {} characters long.",
+            MIN_PASSWORD_LENGTH
+        ))
+    } else if length > MAX_PASSWORD_LENGTH {
+        Err(format!(
+            "The new password must be at most {} characters long.",
+            MAX_PASSWORD_LENGTH
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// hashing is CPU-bound - keep it off the async executor
+pub(crate) async fn hash_password(
+    password: Secret<String>,
+    argon2: &Argon2Settings,
+) -> Result<Secret<String>, anyhow::Error> {
+    let params = argon2.params().context("Invalid Argon2 parameters")?;
+    spawn_blocking_with_tracing(move || compute_password_hash(password, params))
+        .await?
+        .context("Failed to hash password")
+}
+
+fn compute_password_hash(
+    password: Secret<String>,
+    params: Params,
+) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
+
+    Ok(Secret::new(password_hash))
+}
+
+// -- UPGRADE PASSWORD HASH -- //
+
+// `true` if the stored hash is cheaper to brute-force than what we'd compute today
+// (other algorithm, older version or any cost parameter below target) - stronger hashes are left alone
+fn needs_rehash(password_hash: &Secret<String>, argon2: &Argon2Settings) -> bool {
+    let Ok(password_hash) = PasswordHash::new(password_hash.expose_secret()) else {
+        return false;
+    };
+    if password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(&password_hash) {
+        Ok(params) => {
+            params.m_cost() < argon2.memory_kib
+                || params.t_cost() < argon2.iterations
+                || params.p_cost() < argon2.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+// only replaces the hash it was computed for - a password changed in the meantime wins
+#[tracing::instrument(
+    name = "Upgrade password hash",
+    skip(stored_password_hash, password, argon2, db_pool)
+)]
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    stored_password_hash: Secret<String>,
+    password: Secret<String>,
+    argon2: &Argon2Settings,
+    db_pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = hash_password(password, argon2).await?;
+    let res = sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2 AND password_hash = $3
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+        stored_password_hash.expose_secret()
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to store upgraded password hash")?;
+    if res.rows_affected() > 0 {
+        tracing::info!("Upgraded password hash to the current Argon2 parameters");
+    }
+    Ok(())
+}
+
+// -- HELPERS for AUTH -- //
+
+// verified when the username is unknown - same parameters as a freshly computed hash so both take as long
+// (the digest itself is arbitrary, it never matches)
+fn dummy_password_hash(argon2: &Argon2Settings) -> Secret<String> {
+    Secret::new(format!(
+        "$argon2id$v=19$m={},t={},p={}$\
+            gZiV/M1gPc22ElAH/Jh1Hw$\
+            CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+        argon2.memory_kib, argon2.iterations, argon2.parallelism
+    ))
+}
+
+// verify password hashes
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format")
+        .map_err(AuthError::UnexpectedError)?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+// validate credentials from db
+// deactivated users are treated like unknown ones (dummy hash, same timing)
+#[tracing::instrument(name = "Get stored credentials", skip(username, db_pool))]
+async fn get_stored_credentials(
+    username: &str,
+    db_pool: &PgPool,
+) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash FROM users
+        WHERE username = $1 AND deactivated_at IS NULL
+        "#,
+        username
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("failed to perform a query to retrieve stored credentials")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+// -- ERRORS for AUTH -- //
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{dummy_password_hash, needs_rehash};
+    use crate::configuration::Argon2Settings;
+    use argon2::{Params, PasswordHash};
+    use secrecy::{ExposeSecret, Secret};
+
+    fn target() -> Argon2Settings {
+        Argon2Settings {
+            memory_kib: 19456,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+
+    fn phc(algorithm: &str, params: &str) -> Secret<String> {
+        Secret::new(format!(
+            "${}$v=19${}$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            algorithm, params
+        ))
+    }
+
+    #[test]
+    fn weaker_parameters_need_a_rehash() {
+        assert!(needs_rehash(&phc("argon2id", "m=15000,t=2,p=1"), &target()));
+        assert!(needs_rehash(&phc("argon2id", "m=19456,t=1,p=1"), &target()));
+        // right cost, wrong variant
+        assert!(needs_rehash(&phc("argon2i", "m=19456,t=2,p=1"), &target()));
+    }
+
+    #[test]
+    fn current_or_stronger_parameters_are_left_alone() {
+        assert!(!needs_rehash(
+            &phc("argon2id", "m=19456,t=2,p=1"),
+            &target()
+        ));
+        assert!(!needs_rehash(
+            &phc("argon2id", "m=65536,t=3,p=1"),
+            &target()
+        ));
+    }
+
+    #[test]
+    fn dummy_hash_uses_the_target_parameters() {
+        let dummy = dummy_password_hash(&target());
+        let parsed = PasswordHash::new(dummy.expose_secret()).unwrap();
+        let params = Params::try_from(&parsed).unwrap();
+        assert_eq!(
+            (params.m_cost(), params.t_cost(), params.p_cost()),
+            (19456, 2, 1)
+        );
+        assert!(!needs_rehash(&dummy, &target()));
+    }
+}
@@ -0,0 +1,236 @@
+use super::password::hash_password;
+use crate::configuration::Argon2Settings;
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+const TOKEN_LENGTH: usize = 32;
+
+// everything needed to email a reset link
+pub struct PasswordResetRequest {
+    pub email: SubscriberEmail,
+    pub token: Secret<String>,
+}
+
+// -- REQUEST a RESET -- //
+
+// `None` for unknown or deactivated usernames and for users without an email address - callers must answer the same either way
+// issuing a new token invalidates the user's older, unused ones
+#[tracing::instrument(name = "Create password reset token", skip(db_pool))]
+pub async fn create_password_reset_token(
+    db_pool: &PgPool,
+    username: &str,
+    ttl: chrono::Duration,
+) -> Result<Option<PasswordResetRequest>, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT user_id, email FROM users WHERE username = $1 AND deactivated_at IS NULL",
+        username
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to look up user for password reset")?;
+    let Some((user_id, Some(email))) = row.map(|row| (row.user_id, row.email)) else {
+        return Ok(None);
+    };
+    let email = SubscriberEmail::parse(email)
+        .map_err(|err| anyhow::anyhow!(err))
+        .context("Stored email address is invalid")?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let token = store_password_reset_token(&mut transaction, user_id, ttl).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store password reset token")?;
+
+    Ok(Some(PasswordResetRequest { email, token }))
+}
+
+// replaces the user's unused tokens with a new one - also how invited users get to pick their first password
+pub(super) async fn store_password_reset_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    ttl: chrono::Duration,
+) -> Result<Secret<String>, anyhow::Error> {
+    let token = generate_reset_token();
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1 AND consumed_at IS NULL",
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete previous password reset tokens")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        hash_token(&token),
+        user_id,
+        Utc::now() + ttl
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store password reset token")?;
+
+    Ok(token)
+}
+
+// -- RESET -- //
+
+// for the reset form - doesn't consume the token
+#[tracing::instrument(name = "Check password reset token", skip(token, db_pool))]
+pub async fn is_password_reset_token_valid(
+    token: &Secret<String>,
+    db_pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id FROM password_reset_tokens
+        WHERE token_hash = $1 AND consumed_at IS NULL AND expires_at > now()
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to look up password reset token")?;
+
+    Ok(row.is_some())
+}
+
+// consumes the token and sets the new password - `None` if the token is unknown, used or expired
+// also bumps `session_generation`: every session created before the reset is rejected by `RejectAnonymousUsers`
+// (and an invitee who used their link is no longer a pending invitation)
+#[tracing::instrument(name = "Reset password", skip(token, password, argon2, db_pool))]
+pub async fn reset_password(
+    token: &Secret<String>,
+    password: Secret<String>,
+    argon2: &Argon2Settings,
+    db_pool: &PgPool,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let row = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET consumed_at = now()
+        WHERE token_hash = $1 AND consumed_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to consume password reset token")?;
+    let Some(user_id) = row.map(|row| row.user_id) else {
+        return Ok(None);
+    };
+
+    let password_hash = hash_password(password, argon2).await?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1, session_generation = session_generation + 1, invitation_pending = FALSE
+        WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to reset user's password in the database")?;
+    // any other link that's still out there stops working too
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1 AND consumed_at IS NULL",
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete remaining password reset tokens")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset password")?;
+
+    Ok(Some(user_id))
+}
+
+// -- EMAIL ADDRESS -- //
+
+#[tracing::instrument(name = "Get user email", skip(db_pool))]
+pub async fn get_user_email(
+    db_pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!("SELECT email FROM users WHERE user_id = $1", user_id)
+        .fetch_one(db_pool)
+        .await
+        .context("Failed to retrieve user's email address")?;
+    Ok(row.email)
+}
+
+// `None` removes the address (and with it the option to reset the password by email)
+#[tracing::instrument(name = "Set user email", skip(db_pool))]
+pub async fn set_user_email(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    email: Option<&SubscriberEmail>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        email.map(|email| email.as_ref()),
+        user_id
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to update user's email address")?;
+    Ok(())
+}
+
+// -- HELPERS for PASSWORD RESET -- //
+
+fn generate_reset_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    let token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(TOKEN_LENGTH)
+        .collect();
+    Secret::new(token)
+}
+
+// random tokens -> SHA-256 is enough (same as API tokens), a leaked table doesn't leak working links
+fn hash_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_reset_token, hash_token};
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn reset_tokens_are_random_and_only_their_hash_is_stored() {
+        let a = generate_reset_token();
+        let b = generate_reset_token();
+        assert_eq!(a.expose_secret().len(), 32);
+        assert_ne!(a.expose_secret(), b.expose_secret());
+
+        let hash = hash_token(&a);
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains(a.expose_secret().as_str()));
+        assert_eq!(hash, hash_token(&a));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::utils::err403;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// stored as the `as_str` value in `users.role` - set next to `UserId` by `RejectAnonymousUsers`,
+// so admin handlers take `web::ReqData<Role>` and check it with `require_permission`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    Owner,
+    Editor,
+    Viewer,
+}
+
+// what a role allows - handlers ask for a permission, never for a specific role
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Permission {
+    PublishNewsletters,
+    ViewSubscribers,
+    ManageSubscribers,
+    ManageUsers,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Owner, Role::Editor, Role::Viewer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known role", s))
+    }
+
+    // owners can do everything, editors everything but managing other admins, viewers only look
+    pub fn can(&self, permission: Permission) -> bool {
+        match (self, permission) {
+            (Role::Owner, _) => true,
+            (Role::Editor, Permission::ManageUsers) => false,
+            (Role::Editor, _) => true,
+            (Role::Viewer, Permission::ViewSubscribers) => true,
+            (Role::Viewer, _) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// `403` for admin pages the logged in user's role doesn't cover
+pub fn require_permission(role: Role, permission: Permission) -> Result<(), actix_web::Error> {
+    if role.can(permission) {
+        Ok(())
+    } else {
+        Err(err403(format!(
+            "The `{}` role is missing the {:?} permission",
+            role, permission
+        )))
+    }
+}
+
+// `None` for unknown and deactivated users
+#[tracing::instrument(name = "Get user role", skip(db_pool))]
+pub async fn get_user_role(db_pool: &PgPool, user_id: Uuid) -> Result<Option<Role>, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT role FROM users WHERE user_id = $1 AND deactivated_at IS NULL",
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to retrieve user's role")?;
+
+    row.map(|row| Role::parse(&row.role).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn roles_round_trip_through_their_string_form() {
+        for role in Role::ALL {
+            assert_ok_eq!(Role::parse(role.as_str()), role);
+        }
+    }
+
+    #[test]
+    fn unknown_roles_are_rejected() {
+        assert_err!(Role::parse("admin"));
+        assert_err!(Role::parse("Owner"));
+        assert_err!(Role::parse(""));
+    }
+
+    #[test]
+    fn only_owners_can_manage_users() {
+        assert!(Role::Owner.can(Permission::ManageUsers));
+        assert!(!Role::Editor.can(Permission::ManageUsers));
+        assert!(!Role::Viewer.can(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn editors_publish_and_viewers_only_look() {
+        for permission in [
+            Permission::PublishNewsletters,
+            Permission::ViewSubscribers,
+            Permission::ManageSubscribers,
+        ] {
+            assert!(Role::Owner.can(permission));
+            assert!(Role::Editor.can(permission));
+        }
+        assert!(Role::Viewer.can(Permission::ViewSubscribers));
+        assert!(!Role::Viewer.can(Permission::PublishNewsletters));
+        assert!(!Role::Viewer.can(Permission::ManageSubscribers));
+    }
+}
@@ -0,0 +1,609 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, Rng, RngCore};
+use secrecy::{ExposeSecret, Secret};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+// RFC 6238 defaults - what every authenticator app expects from a bare `otpauth://` URI
+const TOTP_STEP_SECONDS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+// accept the previous / next code as well - clocks drift and typing takes time
+const TOTP_SKEW_STEPS: i64 = 1;
+// 160 bits, the HMAC-SHA1 block RFC 4226 recommends
+const SECRET_LENGTH: usize = 20;
+const ISSUER: &str = "zero2prod";
+
+const RECOVERY_CODE_COUNT: usize = 10;
+// no 0/o, 1/l/i - recovery codes get typed in from paper
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+// HKDF `info` - keeps this key independent from every other use of `hmac_secret`
+const ENCRYPTION_KEY_INFO: &[u8] = b"zero2prod totp-secret-encryption";
+const NONCE_LENGTH: usize = 12;
+
+// -- TOTP -- //
+
+// shared secret between the server and the user's authenticator app
+pub struct TotpSecret(Secret<Vec<u8>>);
+
+impl std::fmt::Debug for TotpSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TotpSecret([REDACTED])")
+    }
+}
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        let mut bytes = vec![0u8; SECRET_LENGTH];
+        thread_rng().fill_bytes(&mut bytes);
+        Self(Secret::new(bytes))
+    }
+
+    // the form authenticator apps take for manual entry (padding and case are ignored)
+    pub fn from_base32(encoded: &str) -> Option<Self> {
+        base32_decode(encoded).map(|bytes| Self(Secret::new(bytes)))
+    }
+
+    pub fn to_base32(&self) -> String {
+        base32_encode(self.0.expose_secret())
+    }
+
+    // what the user scans / pastes into their app
+    pub fn otpauth_uri(&self, username: &str) -> String {
+        let label = urlencoding::encode(&format!("{}:{}", ISSUER, username)).into_owned();
+        format!(
+            "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            label,
+            self.to_base32(),
+            ISSUER,
+            TOTP_DIGITS,
+            TOTP_STEP_SECONDS
+        )
+    }
+
+    // the code an authenticator app shows at `at`
+    pub fn code_at(&self, at: DateTime<Utc>) -> String {
+        self.code_for_step(time_step(at))
+    }
+
+    // the time step `code` belongs to - `None` if it doesn't match any step within the skew window
+    fn matching_step(&self, code: &str, at: DateTime<Utc>) -> Option<i64> {
+        let current = time_step(at);
+        (current - TOTP_SKEW_STEPS..=current + TOTP_SKEW_STEPS).find(|step| {
+            *step >= 0 && bool::from(self.code_for_step(*step).as_bytes().ct_eq(code.as_bytes()))
+        })
+    }
+
+    // HOTP (RFC 4226) with the time step as counter
+    fn code_for_step(&self, step: i64) -> String {
+        let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(self.0.expose_secret())
+            .expect("HMAC can take key of any size");
+        mac.update(&(step as u64).to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        // dynamic truncation
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary =
+            u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(TOTP_DIGITS),
+            width = TOTP_DIGITS as usize
+        )
+    }
+}
+
+fn time_step(at: DateTime<Utc>) -> i64 {
+    at.timestamp().max(0) / TOTP_STEP_SECONDS
+}
+
+// -- ENCRYPTION of TOTP SECRETS -- //
+
+// TOTP secrets have to be readable by the server (unlike passwords) -> encrypted at rest
+// key is derived from `hmac_secret` so there's no extra piece of configuration to manage
+pub struct TotpCipher(Aes256Gcm);
+
+impl TotpCipher {
+    pub fn new(hmac_secret: &Secret<String>) -> Self {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, hmac_secret.expose_secret().as_bytes())
+            .expand(ENCRYPTION_KEY_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self(Aes256Gcm::new(&key.into()))
+    }
+
+    // hex(nonce || ciphertext) - the user id is bound as associated data so secrets can't be swapped between rows
+    fn encrypt(&self, secret: &TotpSecret, user_id: Uuid) -> Result<String, anyhow::Error> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .0
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: secret.0.expose_secret(),
+                    aad: user_id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt TOTP secret"))?;
+        Ok(hex::encode([nonce.as_slice(), &ciphertext].concat()))
+    }
+
+    fn decrypt(&self, encrypted: &str, user_id: Uuid) -> Result<TotpSecret, anyhow::Error> {
+        let bytes = hex::decode(encrypted).context("Encrypted TOTP secret is not valid hex")?;
+        if bytes.len() <= NONCE_LENGTH {
+            anyhow::bail!("Encrypted TOTP secret is too short");
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+        let secret = self
+            .0
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: user_id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt TOTP secret"))?;
+        Ok(TotpSecret(Secret::new(secret)))
+    }
+}
+
+// -- MANAGE TWO-FACTOR AUTHENTICATION -- //
+
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub unused_recovery_codes: i64,
+}
+
+#[tracing::instrument(name = "Get two-factor status", skip(db_pool))]
+pub async fn get_two_factor_status(
+    db_pool: &PgPool,
+    user_id: Uuid,
+) -> Result<TwoFactorStatus, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            totp_enabled_at IS NOT NULL AS "enabled!",
+            (
+                SELECT COUNT(*) FROM totp_recovery_codes
+                WHERE user_id = $1 AND used_at IS NULL
+            ) AS "unused_recovery_codes!"
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to retrieve two-factor status")?;
+
+    Ok(TwoFactorStatus {
+        enabled: row.enabled,
+        unused_recovery_codes: row.unused_recovery_codes,
+    })
+}
+
+// stores a fresh secret that only becomes active once `confirm_two_factor_enrollment` sees a valid code for it
+// -> restarting an unfinished enrollment simply replaces the pending secret
+#[tracing::instrument(name = "Begin two-factor enrollment", skip(db_pool, cipher))]
+pub async fn begin_two_factor_enrollment(
+    db_pool: &PgPool,
+    cipher: &TotpCipher,
+    user_id: Uuid,
+) -> Result<TotpSecret, anyhow::Error> {
+    let secret = TotpSecret::generate();
+    let res = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret_encrypted = $2, totp_last_used_step = NULL
+        WHERE user_id = $1 AND totp_enabled_at IS NULL
+        "#,
+        user_id,
+        cipher.encrypt(&secret, user_id)?
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to store pending TOTP secret")?;
+    if res.rows_affected() == 0 {
+        anyhow::bail!("Two-factor authentication is already enabled");
+    }
+
+    Ok(secret)
+}
+
+// `None` if there's no pending enrollment or the code doesn't match
+// on success returns the plain text recovery codes - the only time they're available
+#[tracing::instrument(name = "Confirm two-factor enrollment", skip(db_pool, cipher, code))]
+pub async fn confirm_two_factor_enrollment(
+    db_pool: &PgPool,
+    cipher: &TotpCipher,
+    user_id: Uuid,
+    code: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    let encrypted = sqlx::query!(
+        r#"
+        SELECT totp_secret_encrypted
+        FROM users
+        WHERE user_id = $1 AND totp_enabled_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to retrieve pending TOTP secret")?
+    .and_then(|row| row.totp_secret_encrypted);
+    let Some(encrypted) = encrypted else {
+        return Ok(None);
+    };
+    let secret = cipher.decrypt(&encrypted, user_id)?;
+    let Some(step) = secret.matching_step(code.trim(), now) else {
+        return Ok(None);
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_enabled_at = now(), totp_last_used_step = $2
+        WHERE user_id = $1
+        "#,
+        user_id,
+        step
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enable two-factor authentication")?;
+    store_recovery_codes(&mut transaction, user_id, &recovery_codes).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable two-factor authentication")?;
+
+    Ok(Some(recovery_codes))
+}
+
+// second login step (and re-authentication in the admin area) - takes either a TOTP or a recovery code
+// both are single use: a TOTP can't be replayed for the rest of its window, a recovery code is burnt
+#[tracing::instrument(name = "Verify second factor", skip(db_pool, cipher, code))]
+pub async fn verify_second_factor(
+    db_pool: &PgPool,
+    cipher: &TotpCipher,
+    user_id: Uuid,
+    code: &str,
+    now: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let code = code.trim();
+    let is_totp = code.len() == TOTP_DIGITS as usize && code.bytes().all(|b| b.is_ascii_digit());
+    if !is_totp {
+        return use_recovery_code(db_pool, user_id, code).await;
+    }
+
+    let encrypted = sqlx::query!(
+        r#"
+        SELECT totp_secret_encrypted
+        FROM users
+        WHERE user_id = $1 AND totp_enabled_at IS NOT NULL
+        "#,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to retrieve TOTP secret")?
+    .and_then(|row| row.totp_secret_encrypted);
+    let Some(encrypted) = encrypted else {
+        return Ok(false);
+    };
+    let Some(step) = cipher
+        .decrypt(&encrypted, user_id)?
+        .matching_step(code, now)
+    else {
+        return Ok(false);
+    };
+
+    // only moves forward - a code from the same (or an earlier) step as the last accepted one is a replay
+    let res = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_last_used_step = $2
+        WHERE user_id = $1 AND (totp_last_used_step IS NULL OR totp_last_used_step < $2)
+        "#,
+        user_id,
+        step
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to record used TOTP step")?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(db_pool))]
+pub async fn disable_two_factor(db_pool: &PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret_encrypted = NULL, totp_enabled_at = NULL, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to disable two-factor authentication")?;
+    sqlx::query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete recovery codes")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable two-factor authentication")?;
+    Ok(())
+}
+
+// -- HELPERS for RECOVERY CODES -- //
+
+async fn store_recovery_codes(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    recovery_codes: &[String],
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete old recovery codes")?;
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_recovery_codes (user_id, code_hash)
+        SELECT $1, * FROM UNNEST($2::TEXT[])
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store recovery codes")?;
+    Ok(())
+}
+
+async fn use_recovery_code(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, anyhow::Error> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE totp_recovery_codes
+        SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code)
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to use recovery code")?;
+    Ok(res.rows_affected() > 0)
+}
+
+// `xxxxx-xxxxx`
+fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = (0..RECOVERY_CODE_LENGTH)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char
+                })
+                .collect();
+            format!(
+                "{}-{}",
+                &code[..RECOVERY_CODE_LENGTH / 2],
+                &code[RECOVERY_CODE_LENGTH / 2..]
+            )
+        })
+        .collect()
+}
+
+// high-entropy random codes -> SHA-256 is enough, like API tokens
+// case, dashes and spaces don't matter when typing them back in
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+// -- HELPERS for BASE32 (RFC 4648, no padding) -- //
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8 + 4) / 5);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.trim_end_matches('=').chars() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        base32_decode, base32_encode, generate_recovery_codes, hash_recovery_code, TotpCipher,
+        TotpSecret,
+    };
+    use chrono::{DateTime, TimeZone, Utc};
+    use claims::{assert_err, assert_none, assert_some_eq};
+    use secrecy::{ExposeSecret, Secret};
+    use uuid::Uuid;
+
+    // RFC 6238 appendix B uses this ASCII secret for its SHA1 test vectors
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret(Secret::new(b"12345678901234567890".to_vec()))
+    }
+
+    // fixed clock
+    fn at(unix_time: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(unix_time, 0).unwrap()
+    }
+
+    #[test]
+    fn codes_match_the_rfc_6238_test_vectors() {
+        // the RFC lists 8 digit codes - ours are their last 6 digits
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (unix_time, code) in vectors {
+            assert_eq!(rfc_secret().code_at(at(unix_time)), code);
+        }
+    }
+
+    #[test]
+    fn codes_from_adjacent_time_steps_are_accepted() {
+        let secret = rfc_secret();
+        let now = at(1111111111);
+        let previous = secret.code_at(at(1111111111 - 30));
+        let next = secret.code_at(at(1111111111 + 30));
+        assert_some_eq!(secret.matching_step("050471", now), 1111111111 / 30);
+        assert_some_eq!(secret.matching_step(&previous, now), 1111111111 / 30 - 1);
+        assert_some_eq!(secret.matching_step(&next, now), 1111111111 / 30 + 1);
+    }
+
+    #[test]
+    fn codes_outside_the_skew_window_are_rejected() {
+        let secret = rfc_secret();
+        let stale = secret.code_at(at(1111111111 - 90));
+        assert_none!(secret.matching_step(&stale, at(1111111111)));
+        assert_none!(secret.matching_step("not-a-code", at(1111111111)));
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(
+            base32_encode(b"12345678901234567890"),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+        // RFC 4648 test vectors, padding stripped
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw 6ytb oi").unwrap(), b"foobar");
+        assert_none!(base32_decode("not base32!"));
+    }
+
+    #[test]
+    fn otpauth_uri_carries_the_secret_and_issuer() {
+        let uri = rfc_secret().otpauth_uri("ad min");
+        assert!(uri.starts_with("otpauth://totp/zero2prod%3Aad%20min?"));
+        assert!(uri.contains("secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"));
+        assert!(uri.contains("issuer=zero2prod"));
+    }
+
+    #[test]
+    fn secrets_round_trip_through_encryption() {
+        let cipher = TotpCipher::new(&Secret::new("a".repeat(64)));
+        let user_id = Uuid::new_v4();
+        let secret = TotpSecret::generate();
+
+        let encrypted = cipher.encrypt(&secret, user_id).unwrap();
+
+        assert!(!encrypted.contains(&hex::encode(secret.0.expose_secret())));
+        let decrypted = cipher.decrypt(&encrypted, user_id).unwrap();
+        assert_eq!(decrypted.0.expose_secret(), secret.0.expose_secret());
+    }
+
+    #[test]
+    fn secrets_cannot_be_decrypted_for_another_user_or_with_another_key() {
+        let cipher = TotpCipher::new(&Secret::new("a".repeat(64)));
+        let user_id = Uuid::new_v4();
+        let encrypted = cipher.encrypt(&TotpSecret::generate(), user_id).unwrap();
+
+        assert_err!(cipher.decrypt(&encrypted, Uuid::new_v4()));
+        let other_cipher = TotpCipher::new(&Secret::new("b".repeat(64)));
+        assert_err!(other_cipher.decrypt(&encrypted, user_id));
+    }
+
+    #[test]
+    fn recovery_codes_are_unique_and_forgiving_to_type() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), 10);
+        let mut deduped = codes.clone();
+        deduped.sort();
+        deduped.dedup();
+        assert_eq!(deduped.len(), codes.len());
+
+        let code = &codes[0];
+        assert_eq!(code.len(), 11);
+        assert_eq!(
+            hash_recovery_code(code),
+            hash_recovery_code(&code.to_uppercase().replace('-', " "))
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use super::password::hash_password;
+use super::password_reset::store_password_reset_token;
+use super::Role;
+use crate::configuration::Argon2Settings;
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// row shown on the user management page
+pub struct UserSummary {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: Option<String>,
+    pub role: Role,
+    pub deactivated_at: Option<DateTime<Utc>>,
+    // invited, but the password hasn't been set through the link yet
+    pub invitation_pending: bool,
+}
+
+// the invitee picks their password through a password reset link - `token` goes into that link
+pub struct Invitation {
+    pub user_id: Uuid,
+    pub token: Secret<String>,
+}
+
+// everything the invitation email needs when it's sent again
+pub struct ReissuedInvitation {
+    pub username: String,
+    pub email: SubscriberEmail,
+    pub role: Role,
+    pub token: Secret<String>,
+}
+
+// -- LIST USERS -- //
+
+#[tracing::instrument(name = "List users", skip(db_pool))]
+pub async fn list_users(db_pool: &PgPool) -> Result<Vec<UserSummary>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT user_id, username, email, role, deactivated_at, invitation_pending
+        FROM users
+        ORDER BY username
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve users")?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(UserSummary {
+                user_id: row.user_id,
+                username: row.username,
+                email: row.email,
+                role: Role::parse(&row.role).map_err(anyhow::Error::msg)?,
+                deactivated_at: row.deactivated_at,
+                invitation_pending: row.invitation_pending,
+            })
+        })
+        .collect()
+}
+
+// -- INVITE USER -- //
+
+// `None` if the username is already taken
+// the new user's password is random and never shown to anyone - they can only log in once they've used the link
+#[tracing::instrument(name = "Invite user", skip(argon2, db_pool))]
+pub async fn invite_user(
+    db_pool: &PgPool,
+    username: &str,
+    email: &SubscriberEmail,
+    role: Role,
+    argon2: &Argon2Settings,
+    ttl: chrono::Duration,
+) -> Result<Option<Invitation>, anyhow::Error> {
+    let user_id = Uuid::new_v4();
+    let password_hash = hash_password(Secret::new(Uuid::new_v4().to_string()), argon2).await?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, role, invitation_pending)
+        VALUES ($1, $2, $3, $4, $5, TRUE)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        email.as_ref(),
+        role.as_str()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store invited user")?;
+    if res.rows_affected() == 0 {
+        return Ok(None);
+    }
+    let token = store_password_reset_token(&mut transaction, user_id, ttl).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to invite a user")?;
+
+    Ok(Some(Invitation { user_id, token }))
+}
+
+// fresh link for an invitee whose email never arrived - replaces any unused link
+// `None` for unknown or deactivated users, users without an email address and anyone who already set
+// their password (the link would be an unrequested password reset)
+#[tracing::instrument(name = "Reissue invitation", skip(db_pool))]
+pub async fn reissue_invitation(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    ttl: chrono::Duration,
+) -> Result<Option<ReissuedInvitation>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT username, email, role
+        FROM users
+        WHERE user_id = $1 AND deactivated_at IS NULL AND invitation_pending
+        "#,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to look up invited user")?;
+    let Some((username, Some(email), role)) = row.map(|row| (row.username, row.email, row.role))
+    else {
+        return Ok(None);
+    };
+    let email = SubscriberEmail::parse(email)
+        .map_err(|err| anyhow::anyhow!(err))
+        .context("Stored email address is invalid")?;
+    let role = Role::parse(&role).map_err(anyhow::Error::msg)?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let token = store_password_reset_token(&mut transaction, user_id, ttl).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reissue an invitation")?;
+
+    Ok(Some(ReissuedInvitation {
+        username,
+        email,
+        role,
+        token,
+    }))
+}
+
+// -- CREATE USER -- //
+
+// for bootstrapping the first owner from the command line (`zero2prod create-admin`) - `None` if the username is already taken
+#[tracing::instrument(name = "Create user", skip(password, argon2, db_pool))]
+pub async fn create_user(
+    db_pool: &PgPool,
+    username: &str,
+    password: Secret<String>,
+    role: Role,
+    argon2: &Argon2Settings,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let user_id = Uuid::new_v4();
+    let password_hash = hash_password(password, argon2).await?;
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        role.as_str()
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to store new user")?;
+
+    Ok((res.rows_affected() > 0).then_some(user_id))
+}
+
+// -- MANAGE USERS -- //
+
+// `false` if the user doesn't exist
+#[tracing::instrument(name = "Change user role", skip(db_pool))]
+pub async fn change_user_role(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    role: Role,
+) -> Result<bool, anyhow::Error> {
+    let res = sqlx::query!(
+        "UPDATE users SET role = $1 WHERE user_id = $2",
+        role.as_str(),
+        user_id
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to change user's role")?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+// `false` if the user doesn't exist or is already deactivated
+// sessions and API tokens are rejected from the next request on, unused password reset links are dropped
+#[tracing::instrument(name = "Deactivate user", skip(db_pool))]
+pub async fn deactivate_user(db_pool: &PgPool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let res = sqlx::query!(
+        r#"
+        UPDATE users SET deactivated_at = now()
+        WHERE user_id = $1 AND deactivated_at IS NULL
+        "#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to deactivate user")?;
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1 AND consumed_at IS NULL",
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete password reset tokens of deactivated user")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to deactivate a user")?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+// `false` if the user doesn't exist or isn't deactivated
+#[tracing::instrument(name = "Reactivate user", skip(db_pool))]
+pub async fn reactivate_user(db_pool: &PgPool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE users SET deactivated_at = NULL
+        WHERE user_id = $1 AND deactivated_at IS NOT NULL
+        "#,
+        user_id
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to reactivate user")?;
+
+    Ok(res.rows_affected() > 0)
+}
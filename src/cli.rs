@@ -0,0 +1,256 @@
+use crate::authentication::{check_password_policy, create_user, Role};
+use crate::client_ip::TrustedProxies;
+use crate::configuration::Settings;
+use crate::domain::SubscriberEmail;
+use crate::startup::get_connection_pool;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// `zero2prod [COMMAND]` - no subcommand starts the server, so existing deployments keep working
+#[derive(clap::Parser, Debug)]
+#[command(version, about = "Newsletter delivery service")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Run the HTTP server and the background workers (default)
+    Serve,
+    /// Apply pending database migrations (embedded in the binary)
+    Migrate,
+    /// Create an owner account - the password is prompted for
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+    },
+    /// Send an email through the configured email provider
+    SendTestEmail {
+        #[arg(long)]
+        to: String,
+    },
+    /// Load the configuration and check that Postgres and Redis are reachable
+    CheckConfig,
+}
+
+// -- MIGRATE -- //
+
+// same migrations `sqlx migrate run` applies - compiled into the binary, no sqlx-cli needed in production
+#[tracing::instrument(name = "Run database migrations", skip(db_pool))]
+pub async fn run_migrations(db_pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::migrate!("./migrations")
+        .run(db_pool)
+        .await
+        .context("Failed to run database migrations")
+}
+
+// -- CREATE ADMIN -- //
+
+// the account gets the `owner` role - further admins are invited from `/admin/users`
+#[tracing::instrument(name = "Create admin", skip(configuration, password))]
+pub async fn create_admin(
+    configuration: &Settings,
+    username: &str,
+    password: Secret<String>,
+) -> Result<Uuid, anyhow::Error> {
+    let username = username.trim();
+    if username.is_empty() {
+        anyhow::bail!("The username can't be empty");
+    }
+    check_password_policy(&password).map_err(anyhow::Error::msg)?;
+
+    let db_pool = get_connection_pool(&configuration.database)
+        .await
+        .context("Failed to connect to Postgres")?;
+    create_user(
+        &db_pool,
+        username,
+        password,
+        Role::Owner,
+        &configuration.application.argon2,
+    )
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("A user named `{}` already exists", username))
+}
+
+// -- SEND TEST EMAIL -- //
+
+#[tracing::instrument(name = "Send test email", skip(configuration))]
+pub async fn send_test_email(configuration: &Settings, to: &str) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(to.to_string()).map_err(anyhow::Error::msg)?;
+    configuration
+        .email_client
+        .sender()
+        .map_err(anyhow::Error::msg)
+        .context("Invalid `email_client.sender_email`")?;
+
+    configuration
+        .email_client
+        .clone()
+        .client()
+        .send_email(
+            &recipient,
+            "Test email",
+            "<p>If you can read this, the email client is configured correctly.</p>",
+            "If you can read this, the email client is configured correctly.",
+        )
+        .await
+        .context("Failed to send test email")
+}
+
+// -- CHECK CONFIG -- //
+
+// one line of `check-config` output
+pub struct ConfigCheck {
+    pub name: &'static str,
+    pub outcome: Result<String, anyhow::Error>,
+}
+
+// runs every check (no early exit) so operators see all problems at once
+// - loading the configuration itself is the first check, done by the caller
+pub async fn check_config(configuration: &Settings) -> Vec<ConfigCheck> {
+    vec![
+        ConfigCheck {
+            name: "postgres",
+            outcome: check_postgres(configuration).await,
+        },
+        ConfigCheck {
+            name: "redis",
+            outcome: check_redis(&configuration.redis_uri).await,
+        },
+        ConfigCheck {
+            name: "argon2",
+            outcome: configuration
+                .application
+                .argon2
+                .params()
+                .map(|params| {
+                    format!(
+                        "m={},t={},p={}",
+                        params.m_cost(),
+                        params.t_cost(),
+                        params.p_cost()
+                    )
+                })
+                .map_err(|err| anyhow::anyhow!("Invalid Argon2 parameters: {}", err)),
+        },
+        ConfigCheck {
+            name: "audience timezone",
+            outcome: configuration
+                .application
+                .audience_timezone()
+                .map(|timezone| timezone.name().to_string())
+                .map_err(anyhow::Error::msg),
+        },
+        ConfigCheck {
+            name: "trusted proxies",
+            outcome: TrustedProxies::parse(&configuration.application.trusted_proxies)
+                .map(
+                    |_| match configuration.application.trusted_proxies.is_empty() {
+                        true => "none - `X-Forwarded-For` is ignored".to_string(),
+                        false => configuration.application.trusted_proxies.join(", "),
+                    },
+                )
+                .map_err(anyhow::Error::msg),
+        },
+        ConfigCheck {
+            name: "email sender",
+            outcome: configuration
+                .email_client
+                .sender()
+                .map(|sender| sender.as_ref().to_string())
+                .map_err(anyhow::Error::msg),
+        },
+    ]
+}
+
+// -- HELPERS for CHECK CONFIG -- //
+
+// reachable + every embedded migration applied
+async fn check_postgres(configuration: &Settings) -> Result<String, anyhow::Error> {
+    let db_pool = get_connection_pool(&configuration.database)
+        .await
+        .context("Failed to connect to Postgres")?;
+    let applied: i64 = sqlx::query_scalar("SELECT count(*) FROM _sqlx_migrations WHERE success")
+        .fetch_one(&db_pool)
+        .await
+        .context("Failed to read applied migrations - has `zero2prod migrate` been run?")?;
+    let embedded = sqlx::migrate!("./migrations").iter().count();
+    match embedded as i64 - applied {
+        0 => Ok(format!("connected, {} migrations applied", applied)),
+        pending if pending > 0 => Err(anyhow::anyhow!(
+            "{} pending migration(s) - run `zero2prod migrate`",
+            pending
+        )),
+        _ => Err(anyhow::anyhow!(
+            "the database has migrations this binary doesn't know about"
+        )),
+    }
+}
+
+async fn check_redis(redis_uri: &Secret<String>) -> Result<String, anyhow::Error> {
+    let client =
+        redis::Client::open(redis_uri.expose_secret().as_str()).context("Invalid Redis URI")?;
+    let mut conn = client
+        .get_async_connection()
+        .await
+        .context("Failed to connect to Redis")?;
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut conn)
+        .await
+        .context("Redis did not answer `PING`")?;
+    Ok("connected".into())
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{Cli, Command};
+    use claims::assert_err;
+    use clap::Parser;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("zero2prod").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn no_subcommand_means_serve() {
+        assert_eq!(parse(&[]).unwrap().command, None);
+        assert_eq!(parse(&["serve"]).unwrap().command, Some(Command::Serve));
+    }
+
+    #[test]
+    fn subcommands_take_their_arguments_as_flags() {
+        assert_eq!(
+            parse(&["create-admin", "--username", "admin"])
+                .unwrap()
+                .command,
+            Some(Command::CreateAdmin {
+                username: "admin".into()
+            })
+        );
+        assert_eq!(
+            parse(&["send-test-email", "--to", "ops@example.com"])
+                .unwrap()
+                .command,
+            Some(Command::SendTestEmail {
+                to: "ops@example.com".into()
+            })
+        );
+        assert_eq!(
+            parse(&["check-config"]).unwrap().command,
+            Some(Command::CheckConfig)
+        );
+    }
+
+    #[test]
+    fn missing_arguments_and_unknown_subcommands_are_rejected() {
+        assert_err!(parse(&["create-admin"]));
+        assert_err!(parse(&["send-test-email"]));
+        assert_err!(parse(&["drop-database"]));
+    }
+}
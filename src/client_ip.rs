@@ -0,0 +1,162 @@
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+
+// address per-IP throttles are keyed on - `X-Forwarded-For` is set by whoever sends the request,
+// so it's only believed when the request comes straight from one of our own proxies
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+impl std::fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromRequest for ClientIp {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let peer = match req.peer_addr() {
+            Some(peer) => peer.ip(),
+            None => {
+                return ready(Err(actix_web::error::ErrorInternalServerError(
+                    "Request has no peer address",
+                )))
+            }
+        };
+        let ip = match req.app_data::<web::Data<TrustedProxies>>() {
+            Some(trusted_proxies) => trusted_proxies.client_ip(peer, req),
+            None => peer,
+        };
+        ready(Ok(ClientIp(ip)))
+    }
+}
+
+// `application.trusted_proxies` - addresses or CIDR ranges (`10.0.0.0/8`) of the load balancers in front of the app
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+impl TrustedProxies {
+    pub fn parse(ranges: &[String]) -> Result<Self, String> {
+        ranges
+            .iter()
+            .map(|range| {
+                let invalid = || format!("{} is not a valid IP address or CIDR range.", range);
+                let (address, prefix) = match range.split_once('/') {
+                    Some((address, prefix)) => (address, Some(prefix)),
+                    None => (range.as_str(), None),
+                };
+                let address: IpAddr = address.trim().parse().map_err(|_| invalid())?;
+                let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+                let prefix = match prefix {
+                    Some(prefix) => prefix.trim().parse().map_err(|_| invalid())?,
+                    None => max_prefix,
+                };
+                if prefix > max_prefix {
+                    return Err(invalid());
+                }
+                Ok((address, prefix))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|(network, prefix)| match (network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                same_prefix(&network.octets(), &ip.octets(), *prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                same_prefix(&network.octets(), &ip.octets(), *prefix)
+            }
+            _ => false,
+        })
+    }
+
+    // proxies append to `X-Forwarded-For` -> walk it from the right and take the first hop that isn't one of ours
+    // (anything further left was written by the client)
+    fn client_ip(&self, peer: IpAddr, req: &HttpRequest) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+        let forwarded_for = req
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+            .collect::<Vec<_>>();
+        forwarded_for
+            .into_iter()
+            .rev()
+            .find(|hop| !self.contains(*hop))
+            .unwrap_or(peer)
+    }
+}
+
+fn same_prefix(network: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let full_bytes = usize::from(prefix / 8);
+    let remaining_bits = prefix % 8;
+    if network[..full_bytes] != ip[..full_bytes] {
+        return false;
+    }
+    if remaining_bits == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - remaining_bits);
+    network[full_bytes] & mask == ip[full_bytes] & mask
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::TrustedProxies;
+    use actix_web::test::TestRequest;
+    use claims::assert_err;
+
+    fn proxies(ranges: &[&str]) -> TrustedProxies {
+        let ranges: Vec<String> = ranges.iter().map(|r| r.to_string()).collect();
+        TrustedProxies::parse(&ranges).unwrap()
+    }
+
+    #[test]
+    fn addresses_and_ranges_are_matched() {
+        let trusted = proxies(&["10.0.0.0/8", "192.168.1.7", "fd00::/8"]);
+        assert!(trusted.contains("10.20.30.40".parse().unwrap()));
+        assert!(trusted.contains("192.168.1.7".parse().unwrap()));
+        assert!(!trusted.contains("192.168.1.8".parse().unwrap()));
+        assert!(!trusted.contains("11.0.0.1".parse().unwrap()));
+        assert!(trusted.contains("fd12::1".parse().unwrap()));
+        assert!(!trusted.contains("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn invalid_ranges_are_rejected() {
+        for range in ["not-an-ip", "10.0.0.0/33", "10.0.0.0/x", "::/129"] {
+            assert_err!(TrustedProxies::parse(&[range.to_string()]), "{}", range);
+        }
+    }
+
+    #[test]
+    fn forwarded_for_is_only_believed_from_trusted_proxies() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+        let req = TestRequest::default()
+            .insert_header(("X-Forwarded-For", "6.6.6.6, 203.0.113.9, 10.0.0.2"))
+            .to_http_request();
+
+        // spoofed entry on the left is skipped, so is our own inner proxy on the right
+        assert_eq!(
+            trusted.client_ip("10.0.0.1".parse().unwrap(), &req),
+            "203.0.113.9".parse::<std::net::IpAddr>().unwrap()
+        );
+        // straight from the internet - the header is ignored
+        assert_eq!(
+            trusted.client_ip("198.51.100.1".parse().unwrap(), &req),
+            "198.51.100.1".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+}
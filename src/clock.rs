@@ -0,0 +1,16 @@
+use chrono::{DateTime, Utc};
+
+// source of "now" for time-dependent features (scheduled issues) -
+// injected (`web::Data<dyn Clock>`, scheduler loop) so tests can move time forward instead of sleeping
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
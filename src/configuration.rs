@@ -1,8 +1,13 @@
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{deserialize_bool_from_anything, deserialize_number_from_string};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
 use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    ElasticEmailClient, EmailSender, OutboxClient, PostmarkClient, RetryPolicy, SendGridClient,
+    SmtpClient,
+};
+use std::sync::Arc;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
@@ -12,17 +17,145 @@ pub struct Settings {
     pub email_client: EmailClientSettings,
     // URI marked secret because it may embed password
     pub redis_uri: Secret<String>,
+    // not read from the yaml files - set from `APP_ENVIRONMENT` in `get_configuration`
+    pub environment: Environment,
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    pub provider: EmailProvider,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub retry: EmailRetrySettings,
+    // only read when `provider` is `smtp`
+    pub smtp: Option<SmtpSettings>,
+    // only read when `provider` is `outbox`
+    pub outbox: Option<OutboxSettings>,
+}
+
+// which backend `EmailClientSettings::client` builds - `base_url` / `authorization_token` are interpreted per provider
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailProvider {
+    ElasticEmail,
+    Postmark,
+    #[serde(rename = "sendgrid")]
+    SendGrid,
+    Smtp,
+    // local development only - writes messages to disk instead of sending them
+    Outbox,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct OutboxSettings {
+    // relative paths resolve against the working directory (ie. the repo root for `cargo run`)
+    pub directory: String,
+}
+
+// relay used by the `smtp` provider - `base_url` / `authorization_token` are ignored in that case
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub tls: SmtpTls,
+    // internal relays often accept unauthenticated mail - leave both unset to skip AUTH
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub auth_mechanism: SmtpAuthMechanism,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pool_max_size: u32,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    // plain connection upgraded via `STARTTLS` (usually port 587)
+    StartTls,
+    // TLS from the first byte (usually port 465)
+    Implicit,
+    // unencrypted - only for relays on a trusted network (or tests)
+    None,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpAuthMechanism {
+    Plain,
+    Login,
+}
+
+// backoff for transient email API failures (timeouts, 429, 5xx)
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailRetrySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    pub jitter: bool,
+}
+
+impl EmailRetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            jitter: self.jitter,
+        }
+    }
 }
 
 impl EmailClientSettings {
+    // shared by the HTTP server and the delivery worker so both build the client the same way
+    pub fn client(self) -> Arc<dyn EmailSender> {
+        let sender_email = self.sender().expect("Invalid sender email address");
+        let timeout = self.timeout();
+        let retry_policy = self.retry.policy();
+        match self.provider {
+            EmailProvider::ElasticEmail => Arc::new(ElasticEmailClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+                retry_policy,
+            )),
+            EmailProvider::Postmark => Arc::new(PostmarkClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+                retry_policy,
+            )),
+            EmailProvider::SendGrid => Arc::new(SendGridClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+                retry_policy,
+            )),
+            EmailProvider::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("Missing `email_client.smtp` settings for the `smtp` provider");
+                Arc::new(
+                    SmtpClient::new(&smtp, sender_email, timeout)
+                        .expect("Invalid SMTP relay configuration"),
+                )
+            }
+            EmailProvider::Outbox => {
+                let outbox = self
+                    .outbox
+                    .expect("Missing `email_client.outbox` settings for the `outbox` provider");
+                Arc::new(OutboxClient::new(outbox.directory.into(), sender_email))
+            }
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -72,8 +205,120 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    // how long a confirmation link stays valid - stale tokens are purged by `token_purge_worker`
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_ttl_hours: i64,
+    // how long an emailed password reset link stays valid
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub password_reset_token_ttl_minutes: i64,
+    // how long the link in an invitation email (sent from `/admin/users`) stays valid
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub invitation_token_ttl_hours: i64,
+    // IANA timezone (ie. `Europe/Berlin`) that scheduled send times are entered and shown in - follows its DST rules
+    pub audience_timezone: String,
+    // load balancers allowed to set `X-Forwarded-For` (addresses or CIDR ranges) - see `client_ip::ClientIp`
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    pub resend_confirmation_throttle: ThrottleSettings,
+    pub api: ApiSettings,
+    pub login_throttle: LoginThrottleSettings,
+    pub argon2: Argon2Settings,
+}
+
+// Argon2id cost for newly computed password hashes - stored hashes with weaker parameters are upgraded on login
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct Argon2Settings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+impl Argon2Settings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+    }
+}
+
+// authentication options for the JSON API (`POST /newsletters`)
+#[derive(serde::Deserialize, Clone)]
+pub struct ApiSettings {
+    // `Basic` username/password is still accepted next to `Bearer` API tokens - turn off once clients have migrated
+    #[serde(deserialize_with = "deserialize_bool_from_anything")]
+    pub allow_basic_auth: bool,
+}
+
+// fixed window rate limit - at most `max_attempts_per_*` requests every `window_minutes`
+#[derive(serde::Deserialize, Clone)]
+pub struct ThrottleSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_email: i32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_ip: i32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_minutes: i64,
+}
+
+// failed login counters (per username + per IP) kept in Redis - see `authentication::LoginThrottle`
+#[derive(serde::Deserialize, Clone)]
+pub struct LoginThrottleSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_username: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_ip: u32,
+    // wrong authentication codes per user - across logins, not per pending login
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_second_factor_failures: u32,
+    // `/login/forgot` requests (each one may send an email) - counted over the same window
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_password_resets_per_username: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_password_resets_per_ip: u32,
+    // failures are counted over this window - reaching a threshold locks logins out for the same duration
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_seconds: u64,
+    // delay after the n-th failure is `base_delay * 2^(n-1)`, capped at `max_delay`
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    // namespace for the Redis keys
+    pub key_prefix: String,
+}
+
+impl ThrottleSettings {
+    pub fn window(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.window_minutes)
+    }
+}
+
+impl ApplicationSettings {
+    pub fn subscription_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.subscription_token_ttl_hours)
+    }
+
+    pub fn password_reset_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.password_reset_token_ttl_minutes)
+    }
+
+    pub fn invitation_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.invitation_token_ttl_hours)
+    }
+
+    pub fn audience_timezone(&self) -> Result<chrono_tz::Tz, String> {
+        self.audience_timezone.parse().map_err(|_| {
+            format!(
+                "{} is not a known IANA timezone (ie. `Europe/Berlin`).",
+                self.audience_timezone
+            )
+        })
+    }
 }
 
+#[derive(serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum Environment {
     Local,
     Production,
@@ -117,6 +362,8 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let settings = config::Config::builder()
         .add_source(config::File::from(config_dir.join("base.yaml")))
         .add_source(config::File::from(config_dir.join(env_filename)))
+        // expose the detected env to the app (ie. dev-only routes)
+        .set_override("environment", env.as_str())?
         // allows adding settings from env vars (prefix of APP and __ for separator) -- enables override of any value in our Settings
         .add_source(
             config::Environment::with_prefix("APP")
@@ -0,0 +1,314 @@
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+
+// `{{ name }}` / `{{ name | filter }}` placeholders in email content, filled in per recipient
+// - `{{{{` is a literal `{{` (code samples, other template languages, ...)
+// - only the placeholders of the kind of email being sent are accepted, anything else is rejected when the template is parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placeholder {
+    pub name: &'static str,
+    pub kind: PlaceholderKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceholderKind {
+    Text,
+    Date,
+}
+
+// what newsletter issues (title + both bodies) may use
+pub const NEWSLETTER_PLACEHOLDERS: &[Placeholder] = &[
+    Placeholder {
+        name: "name",
+        kind: PlaceholderKind::Text,
+    },
+    Placeholder {
+        name: "unsubscribe_url",
+        kind: PlaceholderKind::Text,
+    },
+    Placeholder {
+        name: "confirmed_at",
+        kind: PlaceholderKind::Date,
+    },
+];
+
+// what the subscription confirmation email uses
+pub const CONFIRMATION_PLACEHOLDERS: &[Placeholder] = &[Placeholder {
+    name: "confirmation_url",
+    kind: PlaceholderKind::Text,
+}];
+
+pub enum TemplateValue {
+    Text(String),
+    Date(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Filter {
+    // `October 17, 2026`
+    Date,
+}
+
+#[derive(Debug)]
+enum Part {
+    Literal(String),
+    Placeholder {
+        name: &'static str,
+        filter: Option<Filter>,
+    },
+}
+
+#[derive(Debug)]
+pub struct EmailTemplate(Vec<Part>);
+
+impl EmailTemplate {
+    // error messages only ever echo names made of `[a-z0-9_]` - they end up in (unescaped) flash messages
+    pub fn parse(source: &str, placeholders: &[Placeholder]) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            literal.push_str(&rest[..start]);
+            if let Some(after_escape) = rest[start..].strip_prefix("{{{{") {
+                literal.push_str("{{");
+                rest = after_escape;
+                continue;
+            }
+            let after_open = &rest[start + 2..];
+            let end = after_open.find("}}").ok_or_else(|| {
+                format!("A `{{{{` is never closed with `}}}}` - {}.", LITERAL_HINT)
+            })?;
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(parse_placeholder(&after_open[..end], placeholders)?);
+            rest = &after_open[end + 2..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self(parts))
+    }
+
+    pub fn uses(&self, name: &str) -> bool {
+        self.0
+            .iter()
+            .any(|part| matches!(part, Part::Placeholder { name: used, .. } if *used == name))
+    }
+
+    // values are HTML-escaped - a subscriber's name can't inject markup
+    pub fn render_html(&self, values: &[(&str, TemplateValue)]) -> String {
+        self.render(values, encode_minimal)
+    }
+
+    pub fn render_text(&self, values: &[(&str, TemplateValue)]) -> String {
+        self.render(values, str::to_string)
+    }
+
+    // placeholders without a value render empty - callers pass every placeholder of their kind of email
+    fn render(&self, values: &[(&str, TemplateValue)], escape: fn(&str) -> String) -> String {
+        let mut output = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Literal(literal) => output.push_str(literal),
+                Part::Placeholder { name, filter } => {
+                    let value = values.iter().find(|(key, _)| key == name);
+                    let rendered = match (value, filter) {
+                        (Some((_, TemplateValue::Text(text))), _) => text.clone(),
+                        (Some((_, TemplateValue::Date(date))), Some(Filter::Date)) => {
+                            date.format("%B %-d, %Y").to_string()
+                        }
+                        (Some((_, TemplateValue::Date(date))), None) => {
+                            date.format("%Y-%m-%d").to_string()
+                        }
+                        (None, _) => String::new(),
+                    };
+                    output.push_str(&escape(&rendered));
+                }
+            }
+        }
+        output
+    }
+}
+
+// every error about a `{{` that isn't meant as a placeholder points at the escape
+const LITERAL_HINT: &str = "write `{{{{` for a literal `{{`";
+
+// `inner` is what's between `{{` and `}}`
+fn parse_placeholder(inner: &str, placeholders: &[Placeholder]) -> Result<Part, String> {
+    let mut segments = inner.split('|').map(str::trim);
+    let name = segments.next().unwrap_or_default();
+    let filter = segments.next();
+    if segments.next().is_some() {
+        return Err("Placeholders take at most one filter.".into());
+    }
+    if !is_identifier(name) {
+        return Err(format!(
+            "Placeholders look like `{{{{ name }}}}` or `{{{{ confirmed_at | date }}}}` - {}.",
+            LITERAL_HINT
+        ));
+    }
+    let placeholder = placeholders
+        .iter()
+        .find(|placeholder| placeholder.name == name)
+        .ok_or_else(|| {
+            let known: Vec<_> = placeholders
+                .iter()
+                .map(|placeholder| format!("`{{{{ {} }}}}`", placeholder.name))
+                .collect();
+            format!(
+                "Unknown placeholder `{{{{ {} }}}}` - use one of {}, or {}.",
+                name,
+                known.join(", "),
+                LITERAL_HINT
+            )
+        })?;
+
+    let filter = match filter {
+        None => None,
+        Some("date") if placeholder.kind == PlaceholderKind::Date => Some(Filter::Date),
+        Some("date") => {
+            return Err(format!(
+                "The `date` filter doesn't apply to `{{{{ {} }}}}`.",
+                name
+            ))
+        }
+        Some(filter) if is_identifier(filter) => {
+            return Err(format!("Unknown filter `{}`.", filter))
+        }
+        Some(_) => return Err("Filters look like `{{ confirmed_at | date }}`.".into()),
+    };
+
+    Ok(Part::Placeholder {
+        name: placeholder.name,
+        filter,
+    })
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailTemplate, TemplateValue, NEWSLETTER_PLACEHOLDERS};
+    use claims::{assert_err, assert_ok};
+
+    fn values() -> Vec<(&'static str, TemplateValue)> {
+        vec![
+            ("name", TemplateValue::Text("Ursula <3".into())),
+            (
+                "unsubscribe_url",
+                TemplateValue::Text("https://example.com/u?a=1&b=2".into()),
+            ),
+            (
+                "confirmed_at",
+                TemplateValue::Date("2026-10-17T09:30:00Z".parse().unwrap()),
+            ),
+        ]
+    }
+
+    #[test]
+    fn placeholders_are_filled_in_and_escaped_for_html() {
+        let template = assert_ok!(EmailTemplate::parse(
+            "<p>Hi {{ name }}!</p><a href=\"{{unsubscribe_url}}\">Bye</a>",
+            NEWSLETTER_PLACEHOLDERS
+        ));
+        assert_eq!(
+            template.render_html(&values()),
+            "<p>Hi Ursula &lt;3!</p><a href=\"https://example.com/u?a=1&amp;b=2\">Bye</a>"
+        );
+    }
+
+    #[test]
+    fn plain_text_is_not_escaped() {
+        let template = assert_ok!(EmailTemplate::parse(
+            "Hi {{ name }}, {{ unsubscribe_url }}",
+            NEWSLETTER_PLACEHOLDERS
+        ));
+        assert_eq!(
+            template.render_text(&values()),
+            "Hi Ursula <3, https://example.com/u?a=1&b=2"
+        );
+    }
+
+    #[test]
+    fn dates_can_be_formatted() {
+        let template = assert_ok!(EmailTemplate::parse(
+            "{{ confirmed_at | date }} / {{ confirmed_at }}",
+            NEWSLETTER_PLACEHOLDERS
+        ));
+        assert_eq!(
+            template.render_text(&values()),
+            "October 17, 2026 / 2026-10-17"
+        );
+    }
+
+    #[test]
+    fn content_without_placeholders_is_left_alone() {
+        let source = "<p>Just { some } braces }}</p>";
+        let template = assert_ok!(EmailTemplate::parse(source, NEWSLETTER_PLACEHOLDERS));
+        assert_eq!(template.render_html(&values()), source);
+    }
+
+    #[test]
+    fn doubled_braces_are_a_literal_open() {
+        let template = assert_ok!(EmailTemplate::parse(
+            "<code>{{{{ user.name }}</code> for {{ name }} - {{{{{{{{ }}}}",
+            NEWSLETTER_PLACEHOLDERS
+        ));
+        assert_eq!(
+            template.render_html(&values()),
+            "<code>{{ user.name }}</code> for Ursula &lt;3 - {{{{ }}}}"
+        );
+        assert_eq!(
+            template.render_text(&[]),
+            "<code>{{ user.name }}</code> for  - {{{{ }}}}"
+        );
+    }
+
+    #[test]
+    fn errors_mention_the_escape() {
+        for source in ["Hi {{ user.name }}", "Hi {{ nickname }}", "Hi {{ name"] {
+            let err = assert_err!(EmailTemplate::parse(source, NEWSLETTER_PLACEHOLDERS));
+            assert!(err.contains("`{{{{`"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let test_cases = vec![
+            ("Hi {{ nickname }}", "unknown placeholder"),
+            ("Hi {{ confirmation_url }}", "placeholder of another email"),
+            ("Hi {{ name", "unclosed placeholder"),
+            ("Hi {{ }}", "empty placeholder"),
+            ("Hi {{ <script> }}", "invalid name"),
+            ("Hi {{ name | upper }}", "unknown filter"),
+            ("Hi {{ name | date }}", "date filter on text"),
+            ("Hi {{ confirmed_at | date | date }}", "two filters"),
+        ];
+        for (source, description) in test_cases {
+            assert_err!(
+                EmailTemplate::parse(source, NEWSLETTER_PLACEHOLDERS),
+                "{} was accepted",
+                description
+            );
+        }
+    }
+
+    #[test]
+    fn placeholder_use_is_reported() {
+        let template = assert_ok!(EmailTemplate::parse(
+            "<a href=\"{{ unsubscribe_url }}\">Bye</a>",
+            NEWSLETTER_PLACEHOLDERS
+        ));
+        assert!(template.uses("unsubscribe_url"));
+        assert!(!template.uses("name"));
+    }
+}
@@ -0,0 +1,490 @@
+use std::collections::{HashMap, HashSet};
+
+use ammonia::UrlRelative;
+use pulldown_cmark::{html, Event, HeadingLevel, LinkType, Parser, Tag};
+
+// Markdown newsletter content - CommonMark, rendered by `pulldown-cmark`
+// - the html goes through `ammonia`: an allow-list of tags, `http(s)`/`mailto` links only,
+//   scripts / event handlers / author `style`s are stripped
+// - links to anything else (`javascript:`, relative paths, ...) render as their label
+// - `{{ placeholders }}` are left as they are - they're filled in per recipient afterwards
+pub struct RenderedMarkdown {
+    pub html_content: String,
+    pub text_content: String,
+}
+
+pub fn render_markdown(source: &str) -> RenderedMarkdown {
+    // `{{ unsubscribe_url }}` isn't a valid link destination (it has spaces)
+    // - a stand-in URL takes its place while rendering and is swapped back afterwards
+    let source = replace_unsubscribe_placeholder(source);
+
+    let html_content = format!(
+        r#"<div style="{}">{}</div>"#,
+        BODY_STYLE,
+        render_html(&source).trim()
+    );
+    let text_content = render_text(&source);
+
+    RenderedMarkdown {
+        html_content: html_content.replace(UNSUBSCRIBE_STAND_IN, UNSUBSCRIBE_PLACEHOLDER),
+        text_content: text_content.replace(UNSUBSCRIBE_STAND_IN, UNSUBSCRIBE_PLACEHOLDER),
+    }
+}
+
+const UNSUBSCRIBE_PLACEHOLDER: &str = "{{ unsubscribe_url }}";
+const UNSUBSCRIBE_STAND_IN: &str = "https://unsubscribe-url.invalid";
+
+// any spacing - `{{unsubscribe_url}}`, `{{ unsubscribe_url }}`, ...
+fn replace_unsubscribe_placeholder(source: &str) -> String {
+    let mut replaced = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end + 2,
+            None => break,
+        };
+        replaced.push_str(&rest[..start]);
+        match rest[start + 2..end - 2].trim() {
+            "unsubscribe_url" => replaced.push_str(UNSUBSCRIBE_STAND_IN),
+            _ => replaced.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+// -- STYLESHEET -- //
+
+// email clients drop `<style>` blocks and `class`es - every element carries its styles inline
+const BODY_STYLE: &str =
+    "font-family: Helvetica, Arial, sans-serif; font-size: 16px; line-height: 1.5; color: #222222;";
+const P_STYLE: &str = "margin: 0 0 16px;";
+const A_STYLE: &str = "color: #1a73e8; text-decoration: underline;";
+const CODE_STYLE: &str = "font-family: Menlo, Consolas, monospace; font-size: 14px; background-color: #f4f4f4; padding: 2px 4px;";
+const PRE_STYLE: &str = "font-family: Menlo, Consolas, monospace; font-size: 14px; background-color: #f4f4f4; padding: 12px; margin: 0 0 16px; white-space: pre-wrap;";
+const BLOCKQUOTE_STYLE: &str =
+    "margin: 0 0 16px; padding-left: 12px; border-left: 4px solid #dddddd; color: #555555;";
+const LIST_STYLE: &str = "margin: 0 0 16px; padding-left: 24px;";
+const LI_STYLE: &str = "margin: 0 0 4px;";
+const HR_STYLE: &str = "border: none; border-top: 1px solid #dddddd; margin: 24px 0;";
+
+fn heading_style(level: usize) -> &'static str {
+    match level {
+        1 => "font-size: 28px; line-height: 1.25; margin: 24px 0 16px;",
+        2 => "font-size: 24px; line-height: 1.25; margin: 24px 0 16px;",
+        3 => "font-size: 20px; line-height: 1.25; margin: 24px 0 12px;",
+        _ => "font-size: 16px; line-height: 1.25; margin: 16px 0 8px;",
+    }
+}
+
+// -- LINKS -- //
+
+// `javascript:`, `data:`, relative paths, ... are dropped - `<ada@example.com>` autolinks get their `mailto:` when rendered
+fn is_safe_link(link_type: LinkType, url: &str) -> bool {
+    let lowercase = url.to_ascii_lowercase();
+    link_type == LinkType::Email
+        || ["http://", "https://", "mailto:"]
+            .iter()
+            .any(|scheme| lowercase.starts_with(scheme))
+}
+
+// -- HTML -- //
+
+fn render_html(source: &str) -> String {
+    // unsafe links lose their `<a>` here - `ammonia` would only drop the `href`
+    let events = Parser::new(source).filter(|event| match event {
+        Event::Start(Tag::Link(link_type, url, _)) | Event::End(Tag::Link(link_type, url, _)) => {
+            is_safe_link(*link_type, url)
+        }
+        _ => true,
+    });
+    let mut unsanitized = String::new();
+    html::push_html(&mut unsanitized, events);
+    sanitizer().clean(&unsanitized).to_string()
+}
+
+// raw html in the markdown is passed through by `pulldown-cmark` - this is what keeps it in check
+fn sanitizer() -> ammonia::Builder<'static> {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .tags(HashSet::from([
+            "h1",
+            "h2",
+            "h3",
+            "h4",
+            "h5",
+            "h6",
+            "p",
+            "br",
+            "em",
+            "strong",
+            "code",
+            "pre",
+            "a",
+            "ul",
+            "ol",
+            "li",
+            "blockquote",
+            "hr",
+        ]))
+        .tag_attributes(HashMap::from([
+            ("a", HashSet::from(["href"])),
+            ("ol", HashSet::from(["start"])),
+        ]))
+        .generic_attributes(HashSet::new())
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .url_relative(UrlRelative::Deny)
+        .link_rel(None)
+        // any `style` in the source is dropped above - these are the only ones left
+        .set_tag_attribute_value("p", "style", P_STYLE)
+        .set_tag_attribute_value("a", "style", A_STYLE)
+        .set_tag_attribute_value("code", "style", CODE_STYLE)
+        .set_tag_attribute_value("pre", "style", PRE_STYLE)
+        .set_tag_attribute_value("blockquote", "style", BLOCKQUOTE_STYLE)
+        .set_tag_attribute_value("ul", "style", LIST_STYLE)
+        .set_tag_attribute_value("ol", "style", LIST_STYLE)
+        .set_tag_attribute_value("li", "style", LI_STYLE)
+        .set_tag_attribute_value("hr", "style", HR_STYLE);
+    for (level, tag) in ["h1", "h2", "h3", "h4", "h5", "h6"].into_iter().enumerate() {
+        builder.set_tag_attribute_value(tag, "style", heading_style(level + 1));
+    }
+    builder
+}
+
+// -- PLAIN TEXT -- //
+
+fn render_text(source: &str) -> String {
+    let mut renderer = TextRenderer {
+        containers: vec![TextContainer::default()],
+        lists: Vec::new(),
+        inline: String::new(),
+        open_links: Vec::new(),
+        links: Vec::new(),
+    };
+    for event in Parser::new(source) {
+        renderer.event(event);
+    }
+    renderer.finish()
+}
+
+// blocks are rendered bottom-up - every open container (the document, quotes, list items)
+// collects its rendered children and is joined / prefixed once it closes
+struct TextRenderer {
+    containers: Vec<TextContainer>,
+    lists: Vec<TextList>,
+    // the paragraph / heading / code block being rendered - or the text of a tight list item
+    inline: String,
+    // where the label of each open link starts in `inline`
+    open_links: Vec<usize>,
+    // the footnotes - the same URL keeps its number
+    links: Vec<String>,
+}
+
+#[derive(Default)]
+struct TextContainer {
+    item: bool,
+    blocks: Vec<String>,
+}
+
+// `start` is `Some` for ordered lists - tight lists (no blank lines between items) have no paragraphs
+struct TextList {
+    start: Option<u64>,
+    tight: bool,
+    items: Vec<String>,
+}
+
+impl TextRenderer {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(Tag::Paragraph) => {
+                self.flush_inline();
+                // a paragraph directly inside an item - the list is loose
+                if self.containers.last().map_or(false, |c| c.item) {
+                    if let Some(list) = self.lists.last_mut() {
+                        list.tight = false;
+                    }
+                }
+            }
+            Event::Start(Tag::Heading(..)) | Event::Start(Tag::CodeBlock(_)) => self.flush_inline(),
+            Event::End(Tag::Paragraph) => {
+                let text = std::mem::take(&mut self.inline);
+                self.push_block(text);
+            }
+            Event::End(Tag::Heading(level, ..)) => {
+                let text = std::mem::take(&mut self.inline);
+                let underline = match level {
+                    HeadingLevel::H1 => Some('='),
+                    HeadingLevel::H2 => Some('-'),
+                    _ => None,
+                };
+                let block = match underline {
+                    Some(c) => format!("{}\n{}", text, c.to_string().repeat(text.chars().count())),
+                    None => text,
+                };
+                self.push_block(block);
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                let code = std::mem::take(&mut self.inline);
+                self.push_block(prefix_lines(code.trim_end_matches('\n'), "    ", "    "));
+            }
+            Event::Start(Tag::BlockQuote) => {
+                self.flush_inline();
+                self.containers.push(TextContainer::default());
+            }
+            Event::Start(Tag::Item) => {
+                self.flush_inline();
+                self.containers.push(TextContainer {
+                    item: true,
+                    blocks: Vec::new(),
+                });
+            }
+            Event::End(Tag::BlockQuote) => {
+                self.flush_inline();
+                let blocks = self.containers.pop().unwrap_or_default().blocks;
+                self.push_block(prefix_lines(&blocks.join("\n\n"), "> ", "> "));
+            }
+            Event::Start(Tag::List(start)) => {
+                self.flush_inline();
+                self.lists.push(TextList {
+                    start,
+                    tight: true,
+                    items: Vec::new(),
+                });
+            }
+            Event::End(Tag::Item) => {
+                self.flush_inline();
+                let blocks = self.containers.pop().unwrap_or_default().blocks;
+                if let Some(list) = self.lists.last_mut() {
+                    let item = blocks.join(if list.tight { "\n" } else { "\n\n" });
+                    list.items.push(item);
+                }
+            }
+            Event::End(Tag::List(_)) => {
+                if let Some(list) = self.lists.pop() {
+                    let items: Vec<_> = list
+                        .items
+                        .iter()
+                        .enumerate()
+                        .map(|(i, item)| {
+                            let marker = match list.start {
+                                Some(start) => format!("{}. ", start + i as u64),
+                                None => "- ".to_string(),
+                            };
+                            let indent = " ".repeat(marker.len());
+                            prefix_lines(item, &marker, &indent)
+                        })
+                        .collect();
+                    self.push_block(items.join(if list.tight { "\n" } else { "\n\n" }));
+                }
+            }
+            Event::Start(Tag::Link(..)) => self.open_links.push(self.inline.len()),
+            Event::End(Tag::Link(link_type, url, _)) => {
+                let start = self.open_links.pop().unwrap_or(self.inline.len());
+                let label = &self.inline[start..];
+                // autolinks already show their URL
+                if is_safe_link(link_type, &url)
+                    && label != &*url
+                    && format!("mailto:{}", label) != *url
+                {
+                    let number = match self.links.iter().position(|link| *link == *url) {
+                        Some(i) => i + 1,
+                        None => {
+                            self.links.push(url.to_string());
+                            self.links.len()
+                        }
+                    };
+                    self.inline.push_str(&format!(" [{}]", number));
+                }
+            }
+            Event::Text(text) | Event::Code(text) => self.inline.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => self.inline.push('\n'),
+            Event::Rule => {
+                self.flush_inline();
+                self.push_block("----------".to_string());
+            }
+            // raw html only makes it into the html part (sanitized)
+            _ => {}
+        }
+    }
+
+    // text sitting directly in a tight list item becomes a block of its own
+    fn flush_inline(&mut self) {
+        if !self.inline.is_empty() {
+            let text = std::mem::take(&mut self.inline);
+            self.push_block(text);
+        }
+    }
+
+    fn push_block(&mut self, block: String) {
+        if let Some(container) = self.containers.last_mut() {
+            container.blocks.push(block);
+        }
+    }
+
+    // links become numbered footnotes below the text - `[1] https://...`
+    fn finish(mut self) -> String {
+        self.flush_inline();
+        let mut text = self
+            .containers
+            .pop()
+            .unwrap_or_default()
+            .blocks
+            .join("\n\n");
+        if !self.links.is_empty() {
+            text.push_str("\n\n");
+            for (i, url) in self.links.iter().enumerate() {
+                text.push_str(&format!("[{}] {}\n", i + 1, url));
+            }
+        }
+        text.trim_end().to_string()
+    }
+}
+
+// `first` goes before the first line, `rest` before every other (non-empty) line
+fn prefix_lines(text: &str, first: &str, rest: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| match (i, line.is_empty()) {
+            (0, _) => format!("{}{}", first, line),
+            (_, true) => rest.trim_end().to_string(),
+            (_, false) => format!("{}{}", rest, line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{render_markdown, A_STYLE, BODY_STYLE, P_STYLE};
+
+    fn html(source: &str) -> String {
+        let html = render_markdown(source).html_content;
+        // drop the inline styles - the tests are about structure
+        let mut stripped = String::new();
+        let mut rest = html.as_str();
+        while let Some(start) = rest.find(" style=\"") {
+            stripped.push_str(&rest[..start]);
+            let after = &rest[start + 8..];
+            rest = &after[after.find('"').unwrap() + 1..];
+        }
+        stripped.push_str(rest);
+        stripped
+            .strip_prefix("<div>")
+            .and_then(|s| s.strip_suffix("</div>"))
+            .unwrap()
+            .to_string()
+    }
+
+    fn text(source: &str) -> String {
+        render_markdown(source).text_content
+    }
+
+    #[test]
+    fn every_element_carries_inline_styles() {
+        let html = render_markdown("# Hi\n\nSome [link](https://example.com)").html_content;
+        assert!(html.starts_with(r#"<div style="font-family: "#));
+        assert!(html.contains(r#"<h1 style=""#));
+        assert!(html.contains(r#"<p style=""#));
+        assert!(html.contains(r#"<a href="https://example.com" style=""#));
+    }
+
+    #[test]
+    fn blocks_are_rendered() {
+        assert_eq!(
+            html("# Title\n\nFirst line\nsecond line\n\n---\n\n> quoted\n\n```\nlet x = 1 < 2;\n```"),
+            "<h1>Title</h1>\n<p>First line\nsecond line</p>\n<hr>\n<blockquote>\n<p>quoted</p>\n</blockquote>\n<pre><code>let x = 1 &lt; 2;\n</code></pre>"
+        );
+        assert_eq!(
+            html("Title\n=====\nSub\n---"),
+            "<h1>Title</h1>\n<h2>Sub</h2>"
+        );
+    }
+
+    #[test]
+    fn lists_are_rendered() {
+        assert_eq!(
+            html("- one\n- two\n  - nested\n\n3. three\n4. four"),
+            "<ul>\n<li>one</li>\n<li>two\n<ul>\n<li>nested</li>\n</ul>\n</li>\n</ul>\n<ol start=\"3\">\n<li>three</li>\n<li>four</li>\n</ol>"
+        );
+        assert_eq!(
+            html("- one\n\n- two"),
+            "<ul>\n<li>\n<p>one</p>\n</li>\n<li>\n<p>two</p>\n</li>\n</ul>"
+        );
+        assert_eq!(
+            text("- one\n- two\n  more\n\n1. first\n2. second"),
+            "- one\n- two\n  more\n\n1. first\n2. second"
+        );
+        assert_eq!(
+            text("- one\n\n- two\n  - nested\n  - twice"),
+            "- one\n\n- two\n\n  - nested\n  - twice"
+        );
+    }
+
+    #[test]
+    fn inlines_are_rendered() {
+        assert_eq!(
+            html("*em* **strong** _em_ __strong__ `a < b` snake_case_name 2 * 3 * 4"),
+            "<p><em>em</em> <strong>strong</strong> <em>em</em> <strong>strong</strong> <code>a &lt; b</code> snake_case_name 2 * 3 * 4</p>"
+        );
+        assert_eq!(html("line  \nbreak"), "<p>line<br>\nbreak</p>");
+        assert_eq!(html(r"\*not em\*"), "<p>*not em*</p>");
+    }
+
+    #[test]
+    fn raw_html_is_sanitized() {
+        assert_eq!(
+            html(
+                "<script>alert(1)</script>\n\n<b onclick=\"x\">hi</b> <img src=x onerror=alert(1)>"
+            ),
+            "<p>hi </p>"
+        );
+        // author styles are replaced, handlers and unsafe URLs dropped
+        assert_eq!(
+            render_markdown(r#"<p style="position: fixed" onclick="x">a <a href="javascript:alert(1)">b</a></p>"#)
+                .html_content,
+            format!(
+                r#"<div style="{}"><p style="{}">a <a style="{}">b</a></p></div>"#,
+                BODY_STYLE, P_STYLE, A_STYLE
+            )
+        );
+    }
+
+    #[test]
+    fn only_safe_links_are_kept() {
+        assert_eq!(
+            html("[ok](https://example.com/?a=1&b=2) [bad](javascript:alert(1)) [rel](/admin)"),
+            "<p><a href=\"https://example.com/?a=1&amp;b=2\">ok</a> bad rel</p>"
+        );
+        assert_eq!(
+            html("<https://example.com> <ada@example.com>"),
+            "<p><a href=\"https://example.com\">https://example.com</a> <a href=\"mailto:ada@example.com\">ada@example.com</a></p>"
+        );
+    }
+
+    #[test]
+    fn links_become_footnotes_in_plain_text() {
+        assert_eq!(
+            text("Read [the post](https://example.com/post) or [this](https://example.com/post), see <https://example.com>.\n\n[Unsubscribe]({{ unsubscribe_url }})"),
+            "Read the post [1] or this [1], see https://example.com.\n\nUnsubscribe [2]\n\n[1] https://example.com/post\n[2] {{ unsubscribe_url }}"
+        );
+    }
+
+    #[test]
+    fn placeholders_are_left_for_the_template() {
+        assert_eq!(
+            html("Hi {{ name }}, since {{ confirmed_at | date }} - [bye]({{ unsubscribe_url }})"),
+            "<p>Hi {{ name }}, since {{ confirmed_at | date }} - <a href=\"{{ unsubscribe_url }}\">bye</a></p>"
+        );
+    }
+
+    #[test]
+    fn plain_text_is_readable() {
+        assert_eq!(
+            text("# Title\n\nSome *emphasis* and `code`.\n\n> quoted\n> twice\n\n```\ncode\n```\n\n***"),
+            "Title\n=====\n\nSome emphasis and code.\n\n> quoted\n> twice\n\n    code\n\n----------"
+        );
+    }
+}
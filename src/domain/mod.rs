@@ -1,7 +1,16 @@
+mod email_template;
+mod markdown;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod unsubscribe_token;
 
+pub use email_template::{
+    EmailTemplate, Placeholder, PlaceholderKind, TemplateValue, CONFIRMATION_PLACEHOLDERS,
+    NEWSLETTER_PLACEHOLDERS,
+};
+pub use markdown::{render_markdown, RenderedMarkdown};
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use unsubscribe_token::UnsubscribeToken;
@@ -0,0 +1,98 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+// per-subscriber token for unsubscribe links - HMAC-SHA256 of the subscriber's id keyed with `hmac_secret`
+// nothing is stored (no backfill for existing subscribers) and a token can't be forged for someone else's id
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, hmac_secret: &Secret<String>) -> Self {
+        let tag = mac(subscriber_id, hmac_secret).finalize().into_bytes();
+        Self(hex::encode(tag))
+    }
+
+    // returns valid instance of `UnsubscribeToken` only if `token` was issued for `subscriber_id`
+    pub fn parse(
+        token: String,
+        subscriber_id: Uuid,
+        hmac_secret: &Secret<String>,
+    ) -> Result<UnsubscribeToken, String> {
+        let tag = hex::decode(&token).map_err(|_| format!("{} is not a valid token", token))?;
+        // constant time comparison
+        mac(subscriber_id, hmac_secret)
+            .verify_slice(&tag)
+            .map_err(|_| format!("{} is not a valid token", token))?;
+
+        Ok(Self(token))
+    }
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn mac(subscriber_id: Uuid, hmac_secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes()).unwrap();
+    // prefixed so a tag can't be replayed against anything else signed with the same secret
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::UnsubscribeToken;
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret".into())
+    }
+
+    #[test]
+    fn a_generated_token_is_valid_for_its_subscriber() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret());
+        assert_ok!(UnsubscribeToken::parse(
+            token.as_ref().into(),
+            subscriber_id,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_is_rejected_for_another_subscriber() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret());
+        assert_err!(UnsubscribeToken::parse(
+            token.as_ref().into(),
+            Uuid::new_v4(),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &Secret::new("other".into()));
+        assert_err!(UnsubscribeToken::parse(
+            token.as_ref().into(),
+            subscriber_id,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_non_hex_token_is_rejected() {
+        assert_err!(UnsubscribeToken::parse(
+            "not-hex".into(),
+            Uuid::new_v4(),
+            &secret()
+        ));
+    }
+}
@@ -0,0 +1,447 @@
+use super::http::{HttpTransport, RetryPolicy};
+use super::{EmailHeaders, EmailSender};
+use crate::domain::SubscriberEmail;
+use secrecy::{ExposeSecret, Secret};
+
+pub struct ElasticEmailClient {
+    transport: HttpTransport,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>, // we don't want to log our api key on accident!
+}
+
+impl ElasticEmailClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self {
+            transport: HttpTransport::new(timeout, retry_policy),
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for ElasticEmailClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &EmailHeaders,
+    ) -> Result<(), anyhow::Error> {
+        // let url = &self.base_url;
+        // update for integration with Elastic Email API
+        let url = format!("{}/emails/transactional", self.base_url);
+        let req_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers: headers
+                .iter()
+                .map(|(name, value)| Header { name, value })
+                .collect(),
+        };
+
+        self.transport
+            .send_with_retries(|http_client| {
+                http_client
+                    .post(&url)
+                    .header(
+                        "X-ElasticEmail-ApiKey",
+                        self.authorization_token.expose_secret(),
+                    )
+                    // able to use .json() method with json feature flag enabled with `reqwest` crate
+                    .json(&req_body)
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+// used due to field names requirement (ie `LikeThis`)
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    // to store ref's (of str slices) we add lifetime param 'a
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    // omitted entirely when empty -> plain sends keep the original body shape
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Header<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{ElasticEmailClient, EmailHeaders, EmailSender, RetryPolicy};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, body_partial_json};
+    #[allow(unused_imports)] // since not using 'path' explicitly right now
+    use wiremock::matchers::{header, header_exists, method, path};
+    use wiremock::Request;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use claims::{assert_err, assert_ok};
+
+    // custom matcher for confirming request body's data shape / fields correct
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, req: &Request) -> bool {
+            // parse body as json value
+            let res: Result<serde_json::Value, _> = serde_json::from_slice(&req.body);
+
+            if let Ok(body) = res {
+                // dbg!(&body);
+                // check all mandatory fields populated
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                // parsing failed - don't match req
+                false
+            }
+        }
+    }
+
+    // helpers for gen'ing mock request data
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> ElasticEmailClient {
+        ElasticEmailClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            RetryPolicy::no_retries(),
+        )
+    }
+
+    // short, deterministic delays so retry tests stay fast
+    fn retrying_email_client(base_url: String, max_attempts: u32) -> ElasticEmailClient {
+        ElasticEmailClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            RetryPolicy {
+                max_attempts,
+                base_delay: std::time::Duration::from_millis(10),
+                max_delay: std::time::Duration::from_secs(2),
+                jitter: false,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_expected_req() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-ElasticEmail-ApiKey"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/emails/transactional"))
+            // change from zero2prod re: using Elastic Email v. Postmark
+            .and(method("POST"))
+            // using custom body matcher for req checking via wiremock::Match
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        // NOTE: Mock expectations are checked on `drop`
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        // bare minimum needed to trigger path we want to test in `send_email`
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let res = ResponseTemplate::new(200)
+            // 3 min time delay:
+            .set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(res)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_on_500_until_success() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_after_max_attempts() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_on_400() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_timeouts() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri(), 2);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_honors_retry_after_on_429() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri(), 2);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let start = std::time::Instant::now();
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(res);
+        // waited for the provider's delay rather than our 10ms backoff
+        assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_if_retry_after_exceeds_max_delay() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_forwards_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(body_partial_json(serde_json::json!({
+            "Headers": [
+                { "Name": "List-Unsubscribe", "Value": "<https://example.com/unsubscribe>" },
+                { "Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click" }
+            ]
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+        // Act
+        let res = email_client
+            .send_email_with_headers(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &EmailHeaders::list_unsubscribe("https://example.com/unsubscribe"),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(res);
+    }
+}
@@ -0,0 +1,196 @@
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use std::time::Duration;
+use tracing::Instrument;
+
+// shared by the HTTP API backends: owns the `reqwest` client and the retry loop,
+// each provider only has to describe how a single request is built
+pub(crate) struct HttpTransport {
+    http_client: Client,
+    retry_policy: RetryPolicy,
+}
+
+impl HttpTransport {
+    pub(crate) fn new(timeout: Duration, retry_policy: RetryPolicy) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            retry_policy,
+        }
+    }
+
+    // `build_request` is called once per attempt - `RequestBuilder` is consumed by `send`
+    pub(crate) async fn send_with_retries<F>(&self, build_request: F) -> Result<(), reqwest::Error>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let mut attempt = 1;
+        loop {
+            // each attempt gets its own span -> retries show up individually in the logs
+            let span = tracing::info_span!(
+                "Email delivery attempt",
+                attempt,
+                http.status_code = tracing::field::Empty
+            );
+            let err = match try_send(build_request(&self.http_client))
+                .instrument(span)
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+
+            // only transient failures are worth another attempt - 4xx means the request itself is wrong
+            let (source, retry_after) = match err {
+                AttemptError::Transient {
+                    source,
+                    retry_after,
+                } if attempt < self.retry_policy.max_attempts => (source, retry_after),
+                AttemptError::Transient { source, .. } | AttemptError::Permanent(source) => {
+                    return Err(source)
+                }
+            };
+            let delay = match retry_after {
+                // provider asked us to hold off longer than we're willing to wait - give up instead of retrying early
+                Some(retry_after) if retry_after > self.retry_policy.max_delay => {
+                    return Err(source)
+                }
+                Some(retry_after) => retry_after,
+                None => self.retry_policy.backoff(attempt),
+            };
+            tracing::warn!(
+                err.message = %source,
+                attempt,
+                delay_milliseconds = delay.as_millis() as u64,
+                "Transient failure sending email, retrying"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+// single request to the email API, classifying failures as transient or permanent
+async fn try_send(request: RequestBuilder) -> Result<(), AttemptError> {
+    let res = request.send().await.map_err(|err| {
+        if err.is_timeout() || err.is_connect() {
+            AttemptError::Transient {
+                source: err,
+                retry_after: None,
+            }
+        } else {
+            AttemptError::Permanent(err)
+        }
+    })?;
+
+    let status = res.status();
+    tracing::Span::current().record("http.status_code", status.as_u16());
+    let retry_after = parse_retry_after(res.headers());
+
+    match res.error_for_status() {
+        Ok(_) => Ok(()),
+        Err(err) if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() => {
+            Err(AttemptError::Transient {
+                source: err,
+                retry_after,
+            })
+        }
+        Err(err) => Err(AttemptError::Permanent(err)),
+    }
+}
+
+// -- RETRY POLICY -- //
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // total number of requests made, including the first one
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    // single attempt - same behavior as before retries existed
+    pub fn no_retries() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: false,
+        }
+    }
+
+    // exponential backoff (base * 2^(attempt - 1)) capped at `max_delay` -
+    // with jitter, pick a random point in the upper half so concurrent senders don't retry in lockstep
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let capped = exponential.min(self.max_delay);
+        if self.jitter {
+            rand::thread_rng().gen_range(capped / 2..=capped)
+        } else {
+            capped
+        }
+    }
+}
+
+enum AttemptError {
+    Transient {
+        source: reqwest::Error,
+        retry_after: Option<Duration>,
+    },
+    Permanent(reqwest::Error),
+}
+
+// `Retry-After` in delay-seconds form (HTTP-date form is ignored -> fall back to our own backoff)
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_grows_exponentially_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: false,
+        };
+
+        assert_eq!(policy.backoff(1).as_millis(), 100);
+        assert_eq!(policy.backoff(2).as_millis(), 200);
+        assert_eq!(policy.backoff(3).as_millis(), 400);
+        assert_eq!(policy.backoff(4).as_millis(), 500);
+    }
+
+    #[test]
+    fn jittered_backoff_stays_within_upper_half() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+        };
+
+        for _ in 0..100 {
+            let delay = policy.backoff(3).as_millis();
+            assert!((200..=400).contains(&delay));
+        }
+    }
+}
@@ -0,0 +1,74 @@
+mod elastic_email;
+mod http;
+mod outbox;
+mod postmark;
+mod sendgrid;
+mod smtp;
+
+pub use elastic_email::ElasticEmailClient;
+pub use http::RetryPolicy;
+pub use outbox::{list_outbox_messages, OutboxClient, OutboxMessage};
+pub use postmark::PostmarkClient;
+pub use sendgrid::SendGridClient;
+pub use smtp::SmtpClient;
+
+use crate::domain::SubscriberEmail;
+
+// common interface over every email backend -
+// handlers / the delivery worker only ever see `dyn EmailSender`, so switching providers is a config change
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.send_email_with_headers(
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            &EmailHeaders::default(),
+        )
+        .await
+    }
+
+    // backends only implement this one - `send_email` is the common case with no extra headers
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &EmailHeaders,
+    ) -> Result<(), anyhow::Error>;
+}
+
+// extra headers for a single message - forwarded verbatim by every backend
+#[derive(Clone, Debug, Default)]
+pub struct EmailHeaders(Vec<(String, String)>);
+
+impl EmailHeaders {
+    // RFC 8058 one-click unsubscribe - mail clients `POST` `List-Unsubscribe=One-Click` to `url`
+    pub fn list_unsubscribe(url: &str) -> Self {
+        Self(vec![
+            ("List-Unsubscribe".into(), format!("<{}>", url)),
+            (
+                "List-Unsubscribe-Post".into(),
+                "List-Unsubscribe=One-Click".into(),
+            ),
+        ])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
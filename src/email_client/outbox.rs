@@ -0,0 +1,208 @@
+use super::smtp::build_message;
+use super::{EmailHeaders, EmailSender};
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use chrono::Utc;
+use lettre::message::Mailbox;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// local development backend - nothing leaves the machine, every message lands in `directory`
+// as an `.eml` (openable in any mail client) next to a `.json` file the admin outbox page reads
+pub struct OutboxClient {
+    directory: PathBuf,
+    sender: SubscriberEmail,
+}
+
+impl OutboxClient {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Self {
+        Self { directory, sender }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct OutboxMessage {
+    pub id: Uuid,
+    // RFC 3339 - file names are prefixed with the same timestamp so they sort chronologically
+    pub created_at: String,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    // links found in the text body (ie. the confirmation link) so they can be clicked without opening the `.eml`
+    pub links: Vec<String>,
+    pub eml_file: String,
+}
+
+#[async_trait::async_trait]
+impl EmailSender for OutboxClient {
+    #[tracing::instrument(name = "Write email to outbox", skip_all)]
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &EmailHeaders,
+    ) -> Result<(), anyhow::Error> {
+        let from: Mailbox = self
+            .sender
+            .as_ref()
+            .parse()
+            .context("Sender email is not a valid mailbox")?;
+        let message = build_message(
+            from,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        let file_stem = format!("{}-{}", now.format("%Y%m%dT%H%M%S%.3fZ"), id);
+        let eml_file = format!("{}.eml", file_stem);
+        let links = extract_links(text_content);
+
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("Failed to create outbox directory")?;
+        tokio::fs::write(self.directory.join(&eml_file), message.formatted())
+            .await
+            .context("Failed to write `.eml` to outbox")?;
+
+        let metadata = OutboxMessage {
+            id,
+            created_at: now.to_rfc3339(),
+            from: self.sender.to_string(),
+            to: recipient.to_string(),
+            subject: subject.to_owned(),
+            links,
+            eml_file,
+        };
+        tokio::fs::write(
+            self.directory.join(format!("{}.json", file_stem)),
+            serde_json::to_vec_pretty(&metadata).context("Failed to serialize outbox metadata")?,
+        )
+        .await
+        .context("Failed to write metadata to outbox")?;
+
+        // surfaced in the logs so confirming a local subscription doesn't require opening the file
+        for link in &metadata.links {
+            tracing::info!(
+                recipient = %recipient,
+                subject = %subject,
+                link = %link,
+                "Email written to outbox"
+            );
+        }
+        Ok(())
+    }
+}
+
+// newest first - reads the `.json` metadata written alongside each `.eml`
+pub async fn list_outbox_messages(directory: &Path) -> Result<Vec<OutboxMessage>, anyhow::Error> {
+    let mut entries = match tokio::fs::read_dir(directory).await {
+        Ok(entries) => entries,
+        // nothing has been sent yet
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err).context("Failed to read outbox directory"),
+    };
+
+    let mut paths = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().map_or(false, |ext| ext == "json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths.reverse();
+
+    let mut messages = Vec::with_capacity(paths.len());
+    for path in paths {
+        let bytes = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let message = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Invalid outbox metadata in {}", path.display()))?;
+        messages.push(message);
+    }
+
+    Ok(messages)
+}
+
+fn extract_links(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter(|word| word.starts_with("http://") || word.starts_with("https://"))
+        // drop trailing punctuation from prose like "Visit <link>."
+        .map(|word| {
+            word.trim_end_matches(|c: char| ".,;:!?)".contains(c))
+                .to_owned()
+        })
+        .collect()
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_links, list_outbox_messages, OutboxClient};
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailSender;
+    use claims::assert_ok;
+
+    fn outbox_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("zero2prod-outbox-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_eml_and_metadata() {
+        // Arrange
+        let directory = outbox_dir();
+        let client = OutboxClient::new(directory.clone(), email("sender@example.com"));
+
+        // Act
+        let res = client
+            .send_email(
+                &email("recipient@example.com"),
+                "Welcome!",
+                "<p>Hi</p>",
+                "Visit http://127.0.0.1/subscriptions/confirm?subscription_token=abc to confirm.",
+            )
+            .await;
+
+        // Assert
+        assert_ok!(res);
+        let messages = list_outbox_messages(&directory).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert_eq!(message.to, "recipient@example.com");
+        assert_eq!(message.subject, "Welcome!");
+        assert_eq!(
+            message.links,
+            vec!["http://127.0.0.1/subscriptions/confirm?subscription_token=abc"]
+        );
+        let eml = std::fs::read_to_string(directory.join(&message.eml_file)).unwrap();
+        assert!(eml.contains("To: recipient@example.com"));
+        assert!(eml.contains("multipart/alternative"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn listing_a_missing_outbox_returns_no_messages() {
+        let messages = list_outbox_messages(&outbox_dir()).await.unwrap();
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn links_are_extracted_without_trailing_punctuation() {
+        let links = extract_links("Go to https://example.com/a. Or http://example.com/b, maybe");
+        assert_eq!(links, vec!["https://example.com/a", "http://example.com/b"]);
+    }
+}
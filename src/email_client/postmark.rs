@@ -0,0 +1,190 @@
+use super::http::{HttpTransport, RetryPolicy};
+use super::{EmailHeaders, EmailSender};
+use crate::domain::SubscriberEmail;
+use secrecy::{ExposeSecret, Secret};
+
+pub struct PostmarkClient {
+    transport: HttpTransport,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>,
+}
+
+impl PostmarkClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self {
+            transport: HttpTransport::new(timeout, retry_policy),
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &EmailHeaders,
+    ) -> Result<(), anyhow::Error> {
+        let url = format!("{}/email", self.base_url);
+        let req_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers: headers
+                .iter()
+                .map(|(name, value)| Header { name, value })
+                .collect(),
+        };
+
+        self.transport
+            .send_with_retries(|http_client| {
+                http_client
+                    .post(&url)
+                    .header(
+                        "X-Postmark-Server-Token",
+                        self.authorization_token.expose_secret(),
+                    )
+                    .header("Accept", "application/json")
+                    .json(&req_body)
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+// Postmark's `/email` endpoint - same PascalCase shape as Elastic Email's, different path + auth header
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    // omitted entirely when empty -> plain sends keep the original body shape
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Header<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, PostmarkClient, RetryPolicy};
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, req: &Request) -> bool {
+            let res: Result<serde_json::Value, _> = serde_json::from_slice(&req.body);
+
+            if let Ok(body) = res {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> PostmarkClient {
+        PostmarkClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            RetryPolicy::no_retries(),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_expected_req() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(header("Accept", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_server_returns_422() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(res);
+    }
+}
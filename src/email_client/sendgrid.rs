@@ -0,0 +1,246 @@
+use super::http::{HttpTransport, RetryPolicy};
+use super::{EmailHeaders, EmailSender};
+use crate::domain::SubscriberEmail;
+use secrecy::{ExposeSecret, Secret};
+use std::collections::BTreeMap;
+
+pub struct SendGridClient {
+    transport: HttpTransport,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>,
+}
+
+impl SendGridClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self {
+            transport: HttpTransport::new(timeout, retry_policy),
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SendGridClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &EmailHeaders,
+    ) -> Result<(), anyhow::Error> {
+        let url = format!("{}/v3/mail/send", self.base_url);
+        let req_body = SendEmailRequest {
+            personalizations: [Personalization {
+                to: [Address {
+                    email: recipient.as_ref(),
+                }],
+            }],
+            from: Address {
+                email: self.sender.as_ref(),
+            },
+            subject,
+            // SendGrid requires `text/plain` to come before `text/html`
+            content: [
+                Content {
+                    content_type: "text/plain",
+                    value: text_content,
+                },
+                Content {
+                    content_type: "text/html",
+                    value: html_content,
+                },
+            ],
+            headers: headers.iter().collect(),
+        };
+
+        self.transport
+            .send_with_retries(|http_client| {
+                http_client
+                    .post(&url)
+                    .bearer_auth(self.authorization_token.expose_secret())
+                    .json(&req_body)
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+// SendGrid v3 `mail/send` body - nested, snake_case, recipients grouped into personalizations
+#[derive(serde::Serialize)]
+struct SendEmailRequest<'a> {
+    personalizations: [Personalization<'a>; 1],
+    from: Address<'a>,
+    subject: &'a str,
+    content: [Content<'a>; 2],
+    // name -> value object, omitted when empty
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(serde::Serialize)]
+struct Personalization<'a> {
+    to: [Address<'a>; 1],
+}
+
+#[derive(serde::Serialize)]
+struct Address<'a> {
+    email: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct Content<'a> {
+    #[serde(rename = "type")]
+    content_type: &'a str,
+    value: &'a str,
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailHeaders, EmailSender, RetryPolicy, SendGridClient};
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    // checks the nested v3 shape, including content ordering
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, req: &Request) -> bool {
+            let res: Result<serde_json::Value, _> = serde_json::from_slice(&req.body);
+
+            if let Ok(body) = res {
+                body["personalizations"][0]["to"][0]["email"].is_string()
+                    && body["from"]["email"].is_string()
+                    && body["subject"].is_string()
+                    && body["content"][0]["type"] == "text/plain"
+                    && body["content"][0]["value"].is_string()
+                    && body["content"][1]["type"] == "text/html"
+                    && body["content"][1]["value"].is_string()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String, token: String) -> SendGridClient {
+        SendGridClient::new(
+            base_url,
+            email(),
+            Secret::new(token),
+            std::time::Duration::from_millis(200),
+            RetryPolicy::no_retries(),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_expected_req() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let token: String = Faker.fake();
+        let email_client = email_client(mock_server.uri(), token.clone());
+
+        Mock::given(header(
+            "Authorization",
+            format!("Bearer {}", token).as_str(),
+        ))
+        .and(header("Content-Type", "application/json"))
+        .and(path("/v3/mail/send"))
+        .and(method("POST"))
+        .and(SendEmailBodyMatcher)
+        // SendGrid answers a queued message with `202 Accepted`
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_server_returns_400() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri(), Faker.fake());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let res = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_forwards_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri(), Faker.fake());
+
+        Mock::given(body_partial_json(serde_json::json!({
+            "headers": {
+                "List-Unsubscribe": "<https://example.com/unsubscribe>",
+                "List-Unsubscribe-Post": "List-Unsubscribe=One-Click"
+            }
+        })))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+        // Act
+        let res = email_client
+            .send_email_with_headers(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &EmailHeaders::list_unsubscribe("https://example.com/unsubscribe"),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(res);
+    }
+}
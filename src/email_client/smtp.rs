@@ -0,0 +1,382 @@
+use super::{EmailHeaders, EmailSender};
+use crate::configuration::{SmtpAuthMechanism, SmtpSettings, SmtpTls};
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::PoolConfig;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::ExposeSecret;
+
+pub struct SmtpClient {
+    // `AsyncSmtpTransport` keeps a pool of open connections -> consecutive sends skip the handshake
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: Mailbox,
+}
+
+impl SmtpClient {
+    pub fn new(
+        settings: &SmtpSettings,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = match settings.tls {
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)
+                    .context("Failed to configure STARTTLS for the SMTP relay")?
+            }
+            SmtpTls::Implicit => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)
+                .context("Failed to configure TLS for the SMTP relay")?,
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            }
+        };
+        let mut builder = builder
+            .port(settings.port)
+            .timeout(Some(timeout))
+            .pool_config(PoolConfig::new().max_size(settings.pool_max_size));
+
+        if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+            let mechanism = match settings.auth_mechanism {
+                SmtpAuthMechanism::Plain => Mechanism::Plain,
+                SmtpAuthMechanism::Login => Mechanism::Login,
+            };
+            builder = builder
+                .credentials(Credentials::new(
+                    username.clone(),
+                    password.expose_secret().clone(),
+                ))
+                .authentication(vec![mechanism]);
+        }
+
+        let sender = sender
+            .as_ref()
+            .parse()
+            .context("Sender email is not a valid mailbox")?;
+
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpClient {
+    #[tracing::instrument(name = "Send email via SMTP", skip_all)]
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &EmailHeaders,
+    ) -> Result<(), anyhow::Error> {
+        let message = build_message(
+            self.sender.clone(),
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+
+        self.transport
+            .send(message)
+            .await
+            .context("SMTP relay rejected the message")?;
+        Ok(())
+    }
+}
+
+// same html / text pair as the HTTP backends, as `multipart/alternative` (text first, html preferred) -
+// shared with the outbox backend so the `.eml` it writes is exactly what would go over the wire
+pub(super) fn build_message(
+    sender: Mailbox,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &EmailHeaders,
+) -> Result<Message, anyhow::Error> {
+    let recipient: Mailbox = recipient
+        .as_ref()
+        .parse()
+        .context("Recipient email is not a valid mailbox")?;
+    let mut builder = Message::builder()
+        .from(sender)
+        .to(recipient)
+        .subject(subject);
+    for (name, value) in headers.iter() {
+        let name = HeaderName::new_from_ascii(name.to_owned())
+            .with_context(|| format!("Invalid email header name: {}", name))?;
+        builder = builder.raw_header(HeaderValue::new(name, value.to_owned()));
+    }
+
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            text_content.to_owned(),
+            html_content.to_owned(),
+        ))
+        .context("Failed to build email message")
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{SmtpAuthMechanism, SmtpSettings, SmtpTls};
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailHeaders, EmailSender, SmtpClient};
+    use base64::Engine;
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::Sentence;
+    use fake::Fake;
+    use secrecy::Secret;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // minimal in-process SMTP server - speaks just enough of RFC 5321 for `lettre` and records what it's sent
+    #[derive(Default)]
+    struct Received {
+        connections: usize,
+        // decoded `AUTH` credentials as (username, password)
+        auth: Vec<(String, String)>,
+        rcpt_to: Vec<String>,
+        messages: Vec<String>,
+    }
+
+    struct SmtpStub {
+        port: u16,
+        received: Arc<Mutex<Received>>,
+    }
+
+    impl SmtpStub {
+        async fn start(reject_recipients: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let received = Arc::new(Mutex::new(Received::default()));
+            let state = received.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    state.lock().unwrap().connections += 1;
+                    tokio::spawn(handle_connection(stream, state.clone(), reject_recipients));
+                }
+            });
+
+            Self { port, received }
+        }
+    }
+
+    async fn handle_connection(
+        stream: tokio::net::TcpStream,
+        state: Arc<Mutex<Received>>,
+        reject_recipients: bool,
+    ) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"220 stub ESMTP\r\n").await.unwrap();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            let command = line.to_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") {
+                b"250-stub\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n"
+            } else if let Some(initial_response) = line.strip_prefix("AUTH PLAIN ") {
+                let decoded = base64::engine::general_purpose::STANDARD
+                    .decode(initial_response)
+                    .unwrap();
+                let mut parts = decoded.split(|b| *b == 0).skip(1);
+                let username = String::from_utf8(parts.next().unwrap().to_vec()).unwrap();
+                let password = String::from_utf8(parts.next().unwrap().to_vec()).unwrap();
+                state.lock().unwrap().auth.push((username, password));
+                b"235 2.7.0 Authentication successful\r\n"
+            } else if command.starts_with("AUTH LOGIN") {
+                let decode = |s: String| {
+                    String::from_utf8(base64::engine::general_purpose::STANDARD.decode(s).unwrap())
+                        .unwrap()
+                };
+                writer.write_all(b"334 VXNlcm5hbWU6\r\n").await.unwrap();
+                let username = decode(lines.next_line().await.unwrap().unwrap());
+                writer.write_all(b"334 UGFzc3dvcmQ6\r\n").await.unwrap();
+                let password = decode(lines.next_line().await.unwrap().unwrap());
+                state.lock().unwrap().auth.push((username, password));
+                b"235 2.7.0 Authentication successful\r\n"
+            } else if command.starts_with("MAIL FROM") {
+                b"250 OK\r\n"
+            } else if command.starts_with("RCPT TO") {
+                if reject_recipients {
+                    b"550 5.1.1 No such user\r\n"
+                } else {
+                    state.lock().unwrap().rcpt_to.push(line.clone());
+                    b"250 OK\r\n"
+                }
+            } else if command == "DATA" {
+                writer
+                    .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                    .await
+                    .unwrap();
+                let mut data = String::new();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "." {
+                        break;
+                    }
+                    data.push_str(&line);
+                    data.push('\n');
+                }
+                state.lock().unwrap().messages.push(data);
+                b"250 OK\r\n"
+            } else if command == "QUIT" {
+                writer.write_all(b"221 Bye\r\n").await.unwrap();
+                break;
+            } else {
+                // RSET / NOOP (used by the pool to check idle connections)
+                b"250 OK\r\n"
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn settings(port: u16, auth_mechanism: SmtpAuthMechanism) -> SmtpSettings {
+        SmtpSettings {
+            host: "127.0.0.1".into(),
+            port,
+            tls: SmtpTls::None,
+            username: Some("relay-user".into()),
+            password: Some(Secret::new("relay-password".into())),
+            auth_mechanism,
+            pool_max_size: 2,
+        }
+    }
+
+    fn smtp_client(settings: &SmtpSettings) -> SmtpClient {
+        SmtpClient::new(settings, email(), std::time::Duration::from_secs(2)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_multipart_alternative_message() {
+        // Arrange
+        let stub = SmtpStub::start(false).await;
+        let client = smtp_client(&settings(stub.port, SmtpAuthMechanism::Plain));
+        let subject: String = Sentence(1..2).fake();
+        let recipient = email();
+
+        // Act
+        let res = client
+            .send_email(&recipient, &subject, "<p>Html body</p>", "Text body")
+            .await;
+
+        // Assert
+        assert_ok!(res);
+        let received = stub.received.lock().unwrap();
+        assert_eq!(
+            received.rcpt_to,
+            vec![format!("RCPT TO:<{}>", recipient.as_ref())]
+        );
+        assert_eq!(received.messages.len(), 1);
+        let message = &received.messages[0];
+        assert!(message.contains("multipart/alternative"));
+        assert!(message.contains("text/plain"));
+        assert!(message.contains("Text body"));
+        assert!(message.contains("text/html"));
+        assert!(message.contains("<p>Html body</p>"));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_plain() {
+        // Arrange
+        let stub = SmtpStub::start(false).await;
+        let client = smtp_client(&settings(stub.port, SmtpAuthMechanism::Plain));
+
+        // Act
+        let res = client.send_email(&email(), "Subject", "html", "text").await;
+
+        // Assert
+        assert_ok!(res);
+        assert_eq!(
+            stub.received.lock().unwrap().auth,
+            vec![("relay-user".to_string(), "relay-password".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_login() {
+        // Arrange
+        let stub = SmtpStub::start(false).await;
+        let client = smtp_client(&settings(stub.port, SmtpAuthMechanism::Login));
+
+        // Act
+        let res = client.send_email(&email(), "Subject", "html", "text").await;
+
+        // Assert
+        assert_ok!(res);
+        assert_eq!(
+            stub.received.lock().unwrap().auth,
+            vec![("relay-user".to_string(), "relay-password".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn consecutive_sends_reuse_a_pooled_connection() {
+        // Arrange
+        let stub = SmtpStub::start(false).await;
+        let client = smtp_client(&settings(stub.port, SmtpAuthMechanism::Plain));
+
+        // Act
+        for _ in 0..5 {
+            assert_ok!(client.send_email(&email(), "Subject", "html", "text").await);
+        }
+
+        // Assert
+        let received = stub.received.lock().unwrap();
+        assert_eq!(received.messages.len(), 5);
+        // connections go back to the pool asynchronously, so a send may occasionally race ahead and open a new one
+        assert!(received.connections < received.messages.len());
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_relay_rejects_recipient() {
+        // Arrange
+        let stub = SmtpStub::start(true).await;
+        let client = smtp_client(&settings(stub.port, SmtpAuthMechanism::Plain));
+
+        // Act
+        let res = client.send_email(&email(), "Subject", "html", "text").await;
+
+        // Assert
+        assert_err!(res);
+        assert!(stub.received.lock().unwrap().messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_adds_headers_to_message() {
+        // Arrange
+        let stub = SmtpStub::start(false).await;
+        let client = smtp_client(&settings(stub.port, SmtpAuthMechanism::Plain));
+
+        // Act
+        let res = client
+            .send_email_with_headers(
+                &email(),
+                "Subject",
+                "html",
+                "text",
+                &EmailHeaders::list_unsubscribe("https://example.com/unsubscribe"),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(res);
+        let received = stub.received.lock().unwrap();
+        let message = &received.messages[0];
+        assert!(message.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(message.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+    }
+}
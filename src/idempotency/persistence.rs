@@ -0,0 +1,154 @@
+use super::IdempotencyKey;
+use actix_web::body::to_bytes;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// mirrors the `header_pair` composite type created in the idempotency migration
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+// sqlx needs to be told the name of the array type to encode / decode `header_pair[]`
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+pub enum NextAction {
+    // first time we see this key - caller does the work and passes the transaction back to `save_response`
+    StartProcessing(Transaction<'static, Postgres>),
+    // key was already used - replay what we sent the first time
+    ReturnSavedResponse(HttpResponse),
+    // key is held by a request that hasn't saved its response yet
+    RequestInFlight,
+}
+
+// -- TRY PROCESSING -- //
+
+// the INSERT takes a row lock that's only released when the first request's transaction commits ->
+// concurrent duplicates block here and then fall through to the saved response
+pub async fn try_processing(
+    db_pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (
+            user_id,
+            idempotency_key,
+            created_at
+        )
+        VALUES ($1, $2, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    );
+    let n_inserted_rows = transaction.execute(query).await?.rows_affected();
+
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        match get_saved_response(db_pool, idempotency_key, user_id).await? {
+            Some(saved_response) => Ok(NextAction::ReturnSavedResponse(saved_response)),
+            None => Ok(NextAction::RequestInFlight),
+        }
+    }
+}
+
+// -- PERSISTENCE HELPERS -- //
+
+async fn get_saved_response(
+    db_pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers!: Vec<HeaderPairRecord>",
+            response_body as "response_body!"
+        FROM idempotency
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2 AND
+            response_status_code IS NOT NULL
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(db_pool)
+    .await?;
+
+    if let Some(row) = saved_response {
+        let status_code = StatusCode::from_u16(row.response_status_code.try_into()?)?;
+        let mut res = HttpResponse::build(status_code);
+        for HeaderPairRecord { name, value } in row.response_headers {
+            res.append_header((name, value));
+        }
+        Ok(Some(res.body(row.response_body)))
+    } else {
+        Ok(None)
+    }
+}
+
+// -- SAVE RESPONSE -- //
+
+// stores the response in the same transaction as the work it describes, then commits both
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    // body has to be buffered to be stored - split it off then reassemble the response afterwards
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body)
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers = {
+        let mut h = Vec::with_capacity(response_head.headers().len());
+        for (name, value) in response_head.headers().iter() {
+            let name = name.as_str().to_owned();
+            let value = value.as_bytes().to_owned();
+            h.push(HeaderPairRecord { name, value });
+        }
+        h
+    };
+
+    // `query_unchecked!` - the macro can't check our custom composite type at compile time
+    let query = sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+
+    // `map_into_boxed_body` converts the concrete body type back into what handlers return
+    let http_response = response_head.set_body(body).map_into_boxed_body();
+    Ok(http_response)
+}
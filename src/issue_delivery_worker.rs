@@ -0,0 +1,339 @@
+use crate::configuration::Settings;
+use crate::domain::{SubscriberEmail, TemplateValue};
+use crate::email_client::{EmailHeaders, EmailSender};
+use crate::newsletter_issues::{personalize_issue, PersonalizedIssue};
+use crate::routes::unsubscribe_link;
+use crate::startup::get_connection_pool;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+// outcome of a single pass over the queue - lets the loop decide whether to keep going or back off
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+// -- WORKER ENTRYPOINT -- //
+
+// builds its own db pool + email client from config so it can run alongside (or separately from) the HTTP server
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let conn_pool = get_connection_pool(&configuration.database).await?;
+    let email_client = configuration.email_client.client();
+    worker_loop(
+        conn_pool,
+        email_client,
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+    )
+    .await
+}
+
+async fn worker_loop(
+    db_pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    // for the personalised unsubscribe link in each email
+    base_url: String,
+    hmac_secret: Secret<String>,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&db_pool, email_client.as_ref(), &base_url, &hmac_secret).await {
+            // nothing to deliver - sleep before polling again
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            // transient failure (ie. db connection) - short back off then retry
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+// -- TASK EXECUTION -- //
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=tracing::field::Empty,
+        subscriber_email=tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    db_pool: &PgPool,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(db_pool).await?;
+    if task.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (transaction, task) = task.unwrap();
+    let (issue_id, email) = (task.newsletter_issue_id, task.subscriber_email.clone());
+
+    Span::current()
+        .record("newsletter_issue_id", &display(issue_id))
+        .record("subscriber_email", &display(&email));
+
+    // audience was snapshotted at publish time - anyone who unsubscribed since then is skipped
+    let subscriber = match get_confirmed_subscriber(db_pool, &email).await? {
+        Some(subscriber) => subscriber,
+        None => {
+            tracing::info!("Subscriber is no longer confirmed. Skipping.");
+            delete_task(transaction, issue_id, &email).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    match SubscriberEmail::parse(email.clone()) {
+        Ok(email) => {
+            let issue = get_issue(db_pool, issue_id).await?;
+            let unsubscribe_link = unsubscribe_link(base_url, subscriber.id, hmac_secret);
+            let issue = personalize_issue(
+                &issue.title,
+                &issue.text_content,
+                &issue.html_content,
+                &[
+                    ("name", TemplateValue::Text(subscriber.name)),
+                    (
+                        "unsubscribe_url",
+                        TemplateValue::Text(unsubscribe_link.clone()),
+                    ),
+                    (
+                        "confirmed_at",
+                        // always set for confirmed subscribers - renders empty otherwise
+                        subscriber
+                            .confirmed_at
+                            .map(TemplateValue::Date)
+                            .unwrap_or_else(|| TemplateValue::Text(String::new())),
+                    ),
+                ],
+            );
+            let (html_content, text_content) = issue_email_content(&issue, &unsubscribe_link);
+            // a failed send goes back into the queue with a back off -> the rest of the queue isn't blocked,
+            // and the subscriber still gets the issue once the provider recovers
+            if let Err(err) = email_client
+                .send_email_with_headers(
+                    &email,
+                    &issue.title,
+                    &html_content,
+                    &text_content,
+                    &EmailHeaders::list_unsubscribe(&unsubscribe_link),
+                )
+                .await
+            {
+                tracing::error!(
+                    err.cause_chain = ?err,
+                    err.message = %err,
+                    n_retries = task.n_retries,
+                    "Failed to deliver issue to a confirmed subscriber.",
+                );
+                retry_task(transaction, &task, &err).await?;
+                return Ok(ExecutionOutcome::TaskCompleted);
+            }
+        }
+        Err(err) => {
+            tracing::error!(
+                err.cause_chain = ?err,
+                err.message = %err,
+                "Skipping a confirmed subscriber. \
+                Their stored contact details are invalid",
+            );
+        }
+    }
+    delete_task(transaction, issue_id, &email).await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// what every subscriber receives - the issue plus an unsubscribe footer, unless the author
+// already placed `{{ unsubscribe_url }}` in that body themselves
+// (also rendered by the admin preview and test sends, so they match the real thing)
+pub fn issue_email_content(issue: &PersonalizedIssue, unsubscribe_link: &str) -> (String, String) {
+    let html_content = if issue.html_links_to_unsubscribe {
+        issue.html_content.clone()
+    } else {
+        format!(
+            "{}<br />\
+            <p><a href=\"{}\">Unsubscribe</a> from this newsletter.</p>",
+            issue.html_content, unsubscribe_link
+        )
+    };
+    let text_content = if issue.text_links_to_unsubscribe {
+        issue.text_content.clone()
+    } else {
+        format!(
+            "{}\n\nUnsubscribe from this newsletter: {}",
+            issue.text_content, unsubscribe_link
+        )
+    };
+    (html_content, text_content)
+}
+
+// -- HELPERS for TASK EXECUTION -- //
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+// failed sends are retried this many times (1, 2, 4, 8, 16 minutes apart) before the task is given up on
+const MAX_RETRIES: i16 = 5;
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+// row stays locked for the lifetime of the returned transaction -
+// `SKIP LOCKED` lets concurrent workers pick up other rows instead of waiting on this one
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    db_pool: &PgPool,
+) -> Result<Option<(PgTransaction, DeliveryTask)>, anyhow::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let task = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+// puts the task back with an exponential back off - once it's out of retries it's moved to `failed_deliveries`
+#[tracing::instrument(skip_all)]
+async fn retry_task(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    err: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    if task.n_retries < MAX_RETRIES {
+        let query = sqlx::query!(
+            r#"
+            UPDATE issue_delivery_queue
+            SET
+                n_retries = n_retries + 1,
+                execute_after = now() + make_interval(mins => power(2, n_retries)::int)
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email = $2
+            "#,
+            task.newsletter_issue_id,
+            task.subscriber_email
+        );
+        transaction.execute(query).await?;
+        transaction.commit().await?;
+        return Ok(());
+    }
+
+    tracing::error!("Giving up on delivering issue to a confirmed subscriber - out of retries.");
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO failed_deliveries (
+            newsletter_issue_id, subscriber_email, n_retries, last_error, failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO NOTHING
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.n_retries,
+        format!("{:#}", err)
+    );
+    transaction.execute(query).await?;
+    delete_task(
+        transaction,
+        task.newsletter_issue_id,
+        &task.subscriber_email,
+    )
+    .await
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+// what `{{ ... }}` placeholders in the issue are filled with
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+    confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    db_pool: &PgPool,
+    email: &str,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+        SELECT id, name, confirmed_at
+        FROM subscriptions
+        WHERE
+            email = $1 AND
+            status = 'confirmed'
+        "#,
+        email
+    )
+    .fetch_optional(db_pool)
+    .await?;
+
+    Ok(subscriber)
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(db_pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(db_pool)
+    .await?;
+
+    Ok(issue)
+}
@@ -0,0 +1,48 @@
+use crate::clock::Clock;
+use crate::configuration::Settings;
+use crate::newsletter_issues::release_due_issues;
+use crate::startup::get_connection_pool;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+// -- SCHEDULER ENTRYPOINT -- //
+
+// moves scheduled issues into the delivery queue once they're due - `issue_delivery_worker` does the sending
+pub async fn run_scheduler_until_stopped(
+    configuration: Settings,
+    clock: Arc<dyn Clock>,
+) -> Result<(), anyhow::Error> {
+    let conn_pool = get_connection_pool(&configuration.database).await?;
+    scheduler_loop(conn_pool, clock).await
+}
+
+async fn scheduler_loop(db_pool: PgPool, clock: Arc<dyn Clock>) -> Result<(), anyhow::Error> {
+    loop {
+        // a failed pass (ie. db connection) is retried next round - due issues stay `scheduled` until released
+        if let Err(err) = try_release_due_issues(&db_pool, clock.as_ref()).await {
+            tracing::error!(
+                err.cause_chain = ?err,
+                err.message = %err,
+                "Failed to release scheduled newsletter issues",
+            );
+        }
+        // an issue goes out at most this late
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    }
+}
+
+// -- RELEASE -- //
+
+// returns the number of issues released
+pub async fn try_release_due_issues(
+    db_pool: &PgPool,
+    clock: &dyn Clock,
+) -> Result<usize, anyhow::Error> {
+    let released = release_due_issues(db_pool, clock.now()).await?;
+    for issue_id in &released {
+        tracing::info!(newsletter_issue_id = %issue_id, "Released scheduled newsletter issue");
+    }
+
+    Ok(released.len())
+}
@@ -1,10 +1,19 @@
 // use argon2::Algorithm;
 
 pub mod authentication;
+pub mod cli;
+pub mod client_ip;
+pub mod clock;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod newsletter_issues;
 pub mod routes;
 pub mod session_state;
 pub mod startup;
 pub mod telemetry;
+pub mod token_purge_worker;
+pub mod utils;
@@ -1,17 +1,125 @@
-use zero2prod::configuration::get_configuration;
-use zero2prod::startup::Application;
+use clap::Parser;
+use secrecy::{ExposeSecret, Secret};
+use std::fmt::{Debug, Display};
+use std::sync::Arc;
+use tokio::task::JoinError;
+use zero2prod::cli::{check_config, create_admin, run_migrations, send_test_email, Cli, Command};
+use zero2prod::clock::SystemClock;
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::issue_scheduler::run_scheduler_until_stopped;
+use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::token_purge_worker::run_purge_worker_until_stopped;
 
 #[tokio::main]
 // async fn main() -> Result<(), std::io::Error> {
 async fn main() -> anyhow::Result<()> {
-    // initialize telemetry
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve);
+
+    // initialize telemetry - one-off commands only log warnings so their own output stays readable
+    let default_level = if command == Command::Serve {
+        "info"
+    } else {
+        "warn"
+    };
+    let subscriber = get_subscriber("zero2prod".into(), default_level.into(), std::io::stdout);
     init_subscriber(subscriber);
 
-    // build configuration and spin up app
+    // build configuration and run the command
     let configuration = get_configuration().expect("Failed to read configuration file");
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    match command {
+        Command::Serve => serve(configuration).await?,
+        Command::Migrate => {
+            let db_pool = get_connection_pool(&configuration.database).await?;
+            run_migrations(&db_pool).await?;
+            println!("Database migrations are up to date");
+        }
+        Command::CreateAdmin { username } => {
+            let password = prompt_new_password()?;
+            let user_id = create_admin(&configuration, &username, password).await?;
+            println!("Created owner `{}` ({})", username.trim(), user_id);
+        }
+        Command::SendTestEmail { to } => {
+            send_test_email(&configuration, &to).await?;
+            println!("Test email sent to {}", to);
+        }
+        Command::CheckConfig => {
+            println!(
+                "ok      configuration ({})",
+                configuration.environment.as_str()
+            );
+            let mut failed = false;
+            for check in check_config(&configuration).await {
+                match check.outcome {
+                    Ok(details) => println!("ok      {} ({})", check.name, details),
+                    Err(err) => {
+                        failed = true;
+                        println!("FAILED  {}: {:#}", check.name, err);
+                    }
+                }
+            }
+            if failed {
+                anyhow::bail!("Configuration check failed");
+            }
+        }
+    }
+
     Ok(())
 }
+
+async fn serve(configuration: Settings) -> anyhow::Result<()> {
+    // shared by the admin pages (validating send times) and the scheduler (releasing due issues)
+    let clock = Arc::new(SystemClock);
+    let application = Application::build(configuration.clone(), clock.clone()).await?;
+    // HTTP server and delivery worker run as separate tasks -> neither blocks the other
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configuration.clone(), clock));
+    let purge_task = tokio::spawn(run_purge_worker_until_stopped(configuration));
+
+    // whichever task exits first (ok or err) brings the whole process down
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+        o = scheduler_task => report_exit("Issue scheduler", o),
+        o = purge_task => report_exit("Token purge worker", o),
+    };
+
+    Ok(())
+}
+
+// asked twice (no echo) - a typo would otherwise lock the operator out of the only account
+fn prompt_new_password() -> anyhow::Result<Secret<String>> {
+    let password = Secret::new(rpassword::prompt_password("Password: ")?);
+    let password_check = Secret::new(rpassword::prompt_password("Repeat password: ")?);
+    if password.expose_secret() != password_check.expose_secret() {
+        anyhow::bail!("The passwords don't match");
+    }
+    Ok(password)
+}
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(err)) => {
+            tracing::error!(
+                err.cause_chain = ?err,
+                err.message = %err,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(err) => {
+            tracing::error!(
+                err.cause_chain = ?err,
+                err.message = %err,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use crate::domain::{EmailTemplate, TemplateValue, NEWSLETTER_PLACEHOLDERS};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// `draft` and `scheduled` issues have no delivery tasks - publishing one (by hand or via the scheduler) enqueues them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueStatus {
+    Draft,
+    Scheduled,
+    Published,
+}
+
+impl IssueStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled => "scheduled",
+            IssueStatus::Published => "published",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "draft" => Ok(IssueStatus::Draft),
+            "scheduled" => Ok(IssueStatus::Scheduled),
+            "published" => Ok(IssueStatus::Published),
+            other => Err(format!("{} is not a valid issue status.", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for IssueStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub struct NewsletterIssue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub status: IssueStatus,
+    pub created_at: DateTime<Utc>,
+    pub scheduled_for: Option<DateTime<Utc>>,
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+// row of the drafts / scheduled issues lists on `/admin/newsletters`
+pub struct IssueSummary {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+    pub scheduled_for: Option<DateTime<Utc>>,
+}
+
+// -- PUBLISH -- //
+
+// issue + one delivery task per confirmed subscriber, in the caller's transaction
+// - shared by the API and the admin form (`/admin/newsletters`)
+pub async fn publish_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, anyhow::Error> {
+    let issue_id = insert_newsletter_issue(
+        transaction,
+        title,
+        text_content,
+        html_content,
+        IssueStatus::Published,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    enqueue_delivery_tasks(transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+
+    Ok(issue_id)
+}
+
+// -- DRAFTS -- //
+
+pub async fn create_draft(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, anyhow::Error> {
+    insert_newsletter_issue(
+        transaction,
+        title,
+        text_content,
+        html_content,
+        IssueStatus::Draft,
+    )
+    .await
+    .context("Failed to store newsletter issue draft")
+}
+
+// `false` if the issue doesn't exist or was published already - a scheduled issue can still be sent right away
+// the row lock from the `UPDATE` makes concurrent publishes of the same draft enqueue it only once
+#[tracing::instrument(name = "Publish draft", skip(transaction))]
+pub async fn publish_draft(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'published', published_at = now()
+        WHERE newsletter_issue_id = $1 AND status IN ('draft', 'scheduled')
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to mark draft as published")?;
+    if res.rows_affected() == 0 {
+        return Ok(false);
+    }
+    enqueue_delivery_tasks(transaction, newsletter_issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Get newsletter issue", skip(db_pool))]
+pub async fn get_issue(
+    db_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content, status, created_at, scheduled_for, published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to retrieve newsletter issue")?;
+
+    row.map(|row| {
+        Ok(NewsletterIssue {
+            newsletter_issue_id,
+            title: row.title,
+            text_content: row.text_content,
+            html_content: row.html_content,
+            status: IssueStatus::parse(&row.status).map_err(anyhow::Error::msg)?,
+            created_at: row.created_at,
+            scheduled_for: row.scheduled_for,
+            published_at: row.published_at,
+        })
+    })
+    .transpose()
+}
+
+// newest first
+#[tracing::instrument(name = "List drafts", skip(db_pool))]
+pub async fn list_drafts(db_pool: &PgPool) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let drafts = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, created_at, scheduled_for
+        FROM newsletter_issues
+        WHERE status = 'draft'
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve drafts")?;
+
+    Ok(drafts)
+}
+
+// -- SCHEDULING -- //
+
+// next to go out first
+#[tracing::instrument(name = "List scheduled issues", skip(db_pool))]
+pub async fn list_scheduled_issues(db_pool: &PgPool) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, created_at, scheduled_for
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY scheduled_for
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve scheduled issues")?;
+
+    Ok(issues)
+}
+
+// schedules a draft or moves an already scheduled issue - `false` if the issue doesn't exist or was published already
+#[tracing::instrument(name = "Schedule issue", skip(db_pool))]
+pub async fn schedule_issue(
+    db_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    scheduled_for: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'scheduled', scheduled_for = $2
+        WHERE newsletter_issue_id = $1 AND status IN ('draft', 'scheduled')
+        "#,
+        newsletter_issue_id,
+        scheduled_for
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to schedule newsletter issue")?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+// back to a draft - `false` if the issue isn't scheduled (anymore)
+#[tracing::instrument(name = "Cancel scheduled issue", skip(db_pool))]
+pub async fn cancel_scheduled_issue(
+    db_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'draft', scheduled_for = NULL
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to cancel scheduled newsletter issue")?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+// publishes every scheduled issue that is due at `now` - returns their ids
+// `SKIP LOCKED` lets a second scheduler (another instance) pass over issues this one is releasing
+#[tracing::instrument(name = "Release due issues", skip(db_pool))]
+pub async fn release_due_issues(
+    db_pool: &PgPool,
+    now: DateTime<Utc>,
+) -> Result<Vec<Uuid>, anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let issue_ids = sqlx::query_scalar!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'published', published_at = $1
+        WHERE newsletter_issue_id IN (
+            SELECT newsletter_issue_id
+            FROM newsletter_issues
+            WHERE status = 'scheduled' AND scheduled_for <= $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING newsletter_issue_id
+        "#,
+        now
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to mark due issues as published")?;
+    for issue_id in &issue_ids {
+        enqueue_delivery_tasks(&mut transaction, *issue_id)
+            .await
+            .context("Failed to enqueue delivery tasks")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to release due issues")?;
+
+    Ok(issue_ids)
+}
+
+// -- PERSONALIZATION -- //
+
+// one subscriber's copy of an issue
+pub struct PersonalizedIssue {
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    // the author placed `{{ unsubscribe_url }}` in that body themselves
+    pub text_links_to_unsubscribe: bool,
+    pub html_links_to_unsubscribe: bool,
+}
+
+// run before an issue is stored (API + admin form) - an unknown placeholder is a typo the author should hear about,
+// not something subscribers find in their inbox
+pub fn validate_issue_content(
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<(), String> {
+    for (source, part) in [
+        (title, "title"),
+        (text_content, "plain text content"),
+        (html_content, "HTML content"),
+    ] {
+        EmailTemplate::parse(source, NEWSLETTER_PLACEHOLDERS)
+            .map_err(|err| format!("The {} is invalid: {}", part, err))?;
+    }
+    Ok(())
+}
+
+// `values` holds every `NEWSLETTER_PLACEHOLDERS` entry - the title and plain text are rendered unescaped
+pub fn personalize_issue(
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    values: &[(&str, TemplateValue)],
+) -> PersonalizedIssue {
+    let parse = |source| EmailTemplate::parse(source, NEWSLETTER_PLACEHOLDERS);
+    match (parse(title), parse(text_content), parse(html_content)) {
+        (Ok(title), Ok(text_content), Ok(html_content)) => PersonalizedIssue {
+            title: title.render_text(values),
+            text_content: text_content.render_text(values),
+            html_content: html_content.render_html(values),
+            text_links_to_unsubscribe: text_content.uses("unsubscribe_url"),
+            html_links_to_unsubscribe: html_content.uses("unsubscribe_url"),
+        },
+        // only issues stored before placeholders were validated get here - send them as they were written
+        _ => {
+            tracing::warn!("Newsletter issue isn't a valid template. Sending it verbatim.");
+            PersonalizedIssue {
+                title: title.to_string(),
+                text_content: text_content.to_string(),
+                html_content: html_content.to_string(),
+                text_links_to_unsubscribe: false,
+                html_links_to_unsubscribe: false,
+            }
+        }
+    }
+}
+
+// -- HELPERS -- //
+
+// store issue content so the worker can look it up per delivery task
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    status: IssueStatus,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            status,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, CASE WHEN $5 = 'published' THEN now() END)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        status.as_str()
+    );
+    transaction.execute(query).await?;
+
+    Ok(newsletter_issue_id)
+}
+
+// one queue row per confirmed subscriber - snapshot of the audience at publish time
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{personalize_issue, validate_issue_content, IssueStatus};
+    use crate::domain::TemplateValue;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn statuses_round_trip_through_their_column_value() {
+        for status in [
+            IssueStatus::Draft,
+            IssueStatus::Scheduled,
+            IssueStatus::Published,
+        ] {
+            assert_eq!(IssueStatus::parse(status.as_str()), Ok(status));
+        }
+        assert_err!(IssueStatus::parse("sent"));
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected_in_every_part() {
+        assert_ok!(validate_issue_content(
+            "Hi {{ name }}",
+            "Since {{ confirmed_at | date }}",
+            "<a href=\"{{ unsubscribe_url }}\">Bye</a>"
+        ));
+        assert_err!(validate_issue_content("Hi {{ nmae }}", "", ""));
+        assert_err!(validate_issue_content("", "Hi {{ email }}", ""));
+        assert_err!(validate_issue_content("", "", "Hi {{ name"));
+    }
+
+    #[test]
+    fn invalid_stored_issues_are_sent_verbatim() {
+        let values = [("name", TemplateValue::Text("Ursula".into()))];
+        let issue = personalize_issue(
+            "Hi {{ name }}",
+            "{{ nickname }}",
+            "<p>{{ name }}</p>",
+            &values,
+        );
+        assert_eq!(issue.title, "Hi {{ name }}");
+        assert_eq!(issue.text_content, "{{ nickname }}");
+        assert_eq!(issue.html_content, "<p>{{ name }}</p>");
+    }
+}
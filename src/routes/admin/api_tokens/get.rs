@@ -0,0 +1,111 @@
+use crate::authentication::{list_api_tokens, ApiScope, Role, UserId};
+use crate::utils::err500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+// -- API TOKENS -- //
+
+// lists the logged in user's tokens + form to issue a new one
+pub async fn api_tokens_page(
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let tokens = list_api_tokens(&db_pool, *user_id).await.map_err(err500)?;
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+
+    let now = Utc::now();
+    let mut rows = String::new();
+    for token in &tokens {
+        let status = match (token.revoked_at, token.expires_at) {
+            (Some(_), _) => "revoked",
+            (None, Some(expires_at)) if expires_at <= now => "expired",
+            _ => "active",
+        };
+        // only active tokens can be revoked
+        let action = if status == "active" {
+            format!(
+                r#"<form action="/admin/api_tokens/{}/revoke" method="post"><input type="submit" value="Revoke"></form>"#,
+                token.api_token_id
+            )
+        } else {
+            String::new()
+        };
+        writeln!(
+            rows,
+            "<tr><td>{}</td><td><code>{}&hellip;</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&token.name),
+            encode_minimal(&token.token_prefix),
+            encode_minimal(&token.scopes.join(", ")),
+            format_timestamp(Some(token.created_at)),
+            format_timestamp(token.expires_at),
+            format_timestamp(token.last_used_at),
+            status,
+            action,
+        )
+        .unwrap();
+    }
+
+    let mut scope_inputs = String::new();
+    let role = role.into_inner();
+    for scope in ApiScope::ALL
+        .into_iter()
+        .filter(|scope| role.can(scope.required_permission()))
+    {
+        writeln!(
+            scope_inputs,
+            r#"<label><input type="checkbox" name="scopes" value="{scope}"> {scope}</label><br>"#
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API Tokens</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Name</th><th>Token</th><th>Scopes</th><th>Created</th><th>Expires</th><th>Last used</th><th>Status</th><th></th></tr>
+        {rows}
+    </table>
+    <h2>New API token</h2>
+    <form action="/admin/api_tokens" method="post">
+        <label>Name
+            <input type="text" placeholder="What is this token for?" name="name">
+        </label>
+        <br>
+        {scope_inputs}
+        <label>Expires in (days)
+            <input type="number" min="1" max="365" placeholder="Never" name="expires_in_days">
+        </label>
+        <br>
+        <button type="submit">Create token</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+fn format_timestamp(timestamp: Option<DateTime<Utc>>) -> String {
+    timestamp
+        .map(|timestamp| timestamp.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| "-".into())
+}
@@ -0,0 +1,235 @@
+use crate::authentication::{self, ApiScope, Role, UserId};
+use crate::utils::{err500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_EXPIRY_DAYS: i64 = 365;
+
+// -- CREATE API TOKEN -- //
+
+// raw key/value pairs - `scopes` is a repeated checkbox field, which `web::Form<struct>` can't collect into a `Vec`
+pub async fn create_api_token(
+    form: web::Form<Vec<(String, String)>>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let role = role.into_inner();
+    let new_token = match NewApiToken::parse(form.into_inner()) {
+        Ok(new_token) => new_token,
+        Err(msg) => {
+            FlashMessage::error(msg).send();
+            return Ok(see_other("/admin/api_tokens"));
+        }
+    };
+    if let Some(scope) = new_token
+        .scopes
+        .iter()
+        .find(|scope| !role.can(scope.required_permission()))
+    {
+        FlashMessage::error(format!("Your role doesn't allow the {} scope.", scope)).send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+
+    let (_, token) = authentication::create_api_token(
+        &db_pool,
+        *user_id,
+        &new_token.name,
+        &new_token.scopes,
+        new_token.expires_at,
+    )
+    .await
+    .map_err(err500)?;
+
+    // no redirect here - the plain text token is rendered exactly once and never stored (not even in a flash cookie)
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API Token Created</title>
+</head>
+<body>
+    <p>API token <b>{}</b> created. Copy it now - it won't be shown again:</p>
+    <p><code id="api-token">{}</code></p>
+    <p>Send it as <code>Authorization: Bearer &lt;token&gt;</code>.</p>
+    <p><a href="/admin/api_tokens">&lt;- Back</a></p>
+</body>
+</html>"#,
+            encode_minimal(&new_token.name),
+            token.expose_secret(),
+        )))
+}
+
+// -- REVOKE API TOKEN -- //
+
+pub async fn revoke_api_token(
+    path: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let revoked = authentication::revoke_api_token(&db_pool, *user_id, path.into_inner())
+        .await
+        .map_err(err500)?;
+
+    if revoked {
+        FlashMessage::info("The API token has been revoked.").send();
+    } else {
+        FlashMessage::error("Unknown or already revoked API token.").send();
+    }
+    Ok(see_other("/admin/api_tokens"))
+}
+
+// -- HELPERS for CREATE API TOKEN -- //
+
+#[derive(Debug)]
+struct NewApiToken {
+    name: String,
+    scopes: Vec<ApiScope>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl NewApiToken {
+    fn parse(fields: Vec<(String, String)>) -> Result<Self, String> {
+        let mut name = String::new();
+        let mut scopes = vec![];
+        let mut expires_in_days = String::new();
+        for (key, value) in fields {
+            match key.as_str() {
+                "name" => name = value,
+                "scopes" => {
+                    let scope = ApiScope::parse(&value)?;
+                    if !scopes.contains(&scope) {
+                        scopes.push(scope);
+                    }
+                }
+                "expires_in_days" => expires_in_days = value,
+                _ => {}
+            }
+        }
+
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err("The token needs a name.".into());
+        }
+        if name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "The token name must be at most {} characters long.",
+                MAX_NAME_LENGTH
+            ));
+        }
+        if scopes.is_empty() {
+            return Err("Select at least one scope.".into());
+        }
+
+        // empty -> the token never expires
+        let expires_at = match expires_in_days.trim() {
+            "" => None,
+            days => match days.parse::<i64>() {
+                Ok(days) if (1..=MAX_EXPIRY_DAYS).contains(&days) => {
+                    Some(Utc::now() + chrono::Duration::days(days))
+                }
+                _ => {
+                    return Err(format!(
+                        "The expiry must be a number of days between 1 and {}.",
+                        MAX_EXPIRY_DAYS
+                    ))
+                }
+            },
+        };
+
+        Ok(Self {
+            name,
+            scopes,
+            expires_at,
+        })
+    }
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::NewApiToken;
+    use crate::authentication::ApiScope;
+    use claims::{assert_err, assert_none, assert_ok, assert_some};
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn repeated_scopes_are_collected() {
+        let new_token = assert_ok!(NewApiToken::parse(fields(&[
+            ("name", "CI"),
+            ("scopes", "newsletters:publish"),
+            ("scopes", "subscribers:read"),
+            ("scopes", "newsletters:publish"),
+            ("expires_in_days", ""),
+        ])));
+        assert_eq!(
+            new_token.scopes,
+            vec![ApiScope::NewslettersPublish, ApiScope::SubscribersRead]
+        );
+        assert_none!(new_token.expires_at);
+    }
+
+    #[test]
+    fn expiry_is_set_from_days() {
+        let new_token = assert_ok!(NewApiToken::parse(fields(&[
+            ("name", "CI"),
+            ("scopes", "newsletters:publish"),
+            ("expires_in_days", "30"),
+        ])));
+        assert_some!(new_token.expires_at);
+    }
+
+    #[test]
+    fn invalid_forms_are_rejected() {
+        let test_cases = vec![
+            (fields(&[("scopes", "newsletters:publish")]), "missing name"),
+            (
+                fields(&[("name", "   "), ("scopes", "newsletters:publish")]),
+                "blank name",
+            ),
+            (fields(&[("name", "CI")]), "no scopes"),
+            (
+                fields(&[("name", "CI"), ("scopes", "everything")]),
+                "unknown scope",
+            ),
+            (
+                fields(&[
+                    ("name", "CI"),
+                    ("scopes", "newsletters:publish"),
+                    ("expires_in_days", "0"),
+                ]),
+                "zero day expiry",
+            ),
+            (
+                fields(&[
+                    ("name", "CI"),
+                    ("scopes", "newsletters:publish"),
+                    ("expires_in_days", "abc"),
+                ]),
+                "non-numeric expiry",
+            ),
+        ];
+        for (fields, description) in test_cases {
+            assert_err!(NewApiToken::parse(fields), "{} was accepted", description);
+        }
+    }
+}
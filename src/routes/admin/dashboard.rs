@@ -1,35 +1,41 @@
-use crate::session_state::TypedSession;
+use crate::authentication::{Permission, Role, UserId};
+use crate::utils::err500;
 
 use actix_web::http::header::ContentType;
-use actix_web::http::header::LOCATION;
 use actix_web::{web, HttpResponse};
 use anyhow::Context;
 use sqlx::PgPool;
+use std::fmt::Write;
 use uuid::Uuid;
 
-// return opaque `500` for user but preserve err root cause (logging)
-fn err500<T>(err: T) -> actix_web::Error
-where
-    T: std::fmt::Debug + std::fmt::Display + 'static,
-{
-    actix_web::error::ErrorInternalServerError(err)
-}
-
 // -- ADMIN DASHBOARD -- //
 
 pub async fn admin_dashboard(
-    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
     db_pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // deserialization may fail -> return opaque err above in that case
-    // let username = if let Some(user_id) = session.get::<Uuid>("user_id").map_err(err500)? {
-    let username = if let Some(user_id) = session.get_user_id().map_err(err500)? {
-        get_username(user_id, &db_pool).await.map_err(err500)?
-    } else {
-        return Ok(HttpResponse::SeeOther()
-            .insert_header((LOCATION, "/login"))
-            .finish());
-    };
+    // session check (+ redirect to `/login`) happens in `RejectAnonymousUsers`
+    let user_id = user_id.into_inner();
+    let role = role.into_inner();
+    let username = get_username(*user_id, &db_pool).await.map_err(err500)?;
+
+    // only link to what the role allows - the pages check the permission again
+    let mut role_actions = String::new();
+    if role.can(Permission::PublishNewsletters) {
+        writeln!(
+            role_actions,
+            r#"<li><a href="/admin/newsletters">Newsletter issues</a></li>"#
+        )
+        .unwrap();
+    }
+    if role.can(Permission::ManageUsers) {
+        writeln!(
+            role_actions,
+            r#"<li><a href="/admin/users">Manage users</a></li>"#
+        )
+        .unwrap();
+    }
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -43,6 +49,20 @@ pub async fn admin_dashboard(
         <body>
         <p>
         Welcome {username}!</p>
+        <p>Your role: {role}</p>
+        <p>Available actions:</p>
+        <ol>
+            {role_actions}
+            <li><a href="/admin/password">Change password</a></li>
+            <li><a href="/admin/email">Account email</a></li>
+            <li><a href="/admin/api_tokens">API tokens</a></li>
+            <li><a href="/admin/two_factor">Two-factor authentication</a></li>
+            <li>
+                <form name="logoutForm" action="/admin/logout" method="post">
+                    <input type="submit" value="Logout">
+                </form>
+            </li>
+        </ol>
         </body>
         </html>
         "#
@@ -52,7 +72,7 @@ pub async fn admin_dashboard(
 // -- HELPERS for ADMIN DASHBOARD -- //
 
 #[tracing::instrument(name = "Get username", skip(db_pool))]
-async fn get_username(user_id: Uuid, db_pool: &PgPool) -> Result<String, anyhow::Error> {
+pub async fn get_username(user_id: Uuid, db_pool: &PgPool) -> Result<String, anyhow::Error> {
     let row = sqlx::query!(
         r#"
         SELECT username FROM users
@@ -0,0 +1,64 @@
+use crate::authentication::{get_user_email, UserId};
+use crate::utils::err500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+// the address password reset links are sent to
+pub async fn email_form(
+    user_id: web::ReqData<UserId>,
+    db_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let email = get_user_email(&db_pool, *user_id)
+        .await
+        .map_err(err500)?
+        .map(|email| encode_minimal(&email))
+        .unwrap_or_default();
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Account email</title>
+</head>
+<body>
+    {msg_html}
+    <p>Password reset links are sent to this address. Leave it empty to turn off password resets by email.</p>
+    <form action="/admin/email" method="post">
+        <label>Email
+            <input
+                type="text"
+                placeholder="Enter email address"
+                name="email"
+                value="{email}"
+            >
+        </label>
+        <br>
+        <label>Current password
+            <input
+                type="password"
+                placeholder="Enter current password"
+                name="current_password"
+            >
+        </label>
+        <br>
+        <button type="submit">Save</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
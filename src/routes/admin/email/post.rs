@@ -0,0 +1,145 @@
+use crate::authentication::{
+    get_user_email, set_user_email, AuthError, Credentials, LoginThrottle, UserId,
+};
+use crate::client_ip::ClientIp;
+use crate::configuration::Argon2Settings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::routes::admin::dashboard::get_username;
+use crate::utils::{err500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use htmlescape::encode_minimal;
+use secrecy::Secret;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+    current_password: Secret<String>,
+}
+
+// -- CHANGE EMAIL -- //
+
+// password reset links go to this address - changing it takes the current password (like `/admin/password`),
+// otherwise a hijacked session could point it elsewhere and take the account over via `/login/forgot`
+pub async fn change_email(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    login_throttle: web::Data<LoginThrottle>,
+    argon2: web::Data<Argon2Settings>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let FormData {
+        email,
+        current_password,
+    } = form.0;
+    let email = email.trim().to_string();
+
+    // empty -> the address is removed
+    let new_email = if email.is_empty() {
+        None
+    } else {
+        match SubscriberEmail::parse(email) {
+            Ok(email) => Some(email),
+            Err(_) => {
+                FlashMessage::error("Please enter a valid email address.").send();
+                return Ok(see_other("/admin/email"));
+            }
+        }
+    };
+
+    let username = get_username(user_id, &db_pool).await.map_err(err500)?;
+    let credentials = Credentials {
+        username: username.clone(),
+        password: current_password,
+    };
+    // same counters as the login form - a hijacked session can't be used to brute-force the password
+    if let Err(err) = login_throttle
+        .validate_credentials(credentials, client_ip, &argon2, &db_pool)
+        .await
+    {
+        return match err {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/email"))
+            }
+            AuthError::UnexpectedError(_) => Err(err500(err)),
+        };
+    }
+
+    let previous_email = get_user_email(&db_pool, user_id).await.map_err(err500)?;
+    set_user_email(&db_pool, user_id, new_email.as_ref())
+        .await
+        .map_err(err500)?;
+
+    // the previous address is told about it - a change the owner didn't make doesn't go unnoticed
+    // (the change itself stands if the notice can't be sent)
+    let unchanged = |previous: &String| {
+        new_email.as_ref().map_or(false, |new_email| {
+            new_email.as_ref().eq_ignore_ascii_case(previous)
+        })
+    };
+    if let Some(previous_email) = previous_email.filter(|previous| !unchanged(previous)) {
+        if let Err(err) = send_email_changed_notice(
+            email_client.as_ref(),
+            previous_email,
+            &username,
+            new_email.is_some(),
+        )
+        .await
+        {
+            tracing::error!(
+                err.cause_chain = ?err,
+                err.message = %err,
+                "Failed to notify the previous email address of the change",
+            );
+        }
+    }
+
+    match new_email {
+        Some(_) => FlashMessage::info("Your email address has been updated.").send(),
+        None => FlashMessage::info("Your email address has been removed.").send(),
+    }
+    Ok(see_other("/admin/email"))
+}
+
+// -- HELPERS for CHANGE EMAIL -- //
+
+// doesn't mention the new address - only that it's no longer this one
+async fn send_email_changed_notice(
+    email_client: &dyn EmailSender,
+    previous_email: String,
+    username: &str,
+    replaced: bool,
+) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(previous_email).map_err(anyhow::Error::msg)?;
+    let change = if replaced {
+        "was changed to a different address"
+    } else {
+        "was removed"
+    };
+    let text_content = format!(
+        "The email address of the account \"{}\" {} - password reset links are no longer sent here.\n\
+        If this wasn't you, ask an owner to deactivate the account.",
+        username, change
+    );
+    let html_content = format!(
+        "The email address of the account \"{}\" {} - password reset links are no longer sent here.<br />\
+        If this wasn't you, ask an owner to deactivate the account.",
+        encode_minimal(username),
+        change
+    );
+
+    email_client
+        .send_email(
+            &recipient,
+            "Your account email address was changed",
+            &html_content,
+            &text_content,
+        )
+        .await
+}
@@ -1,3 +1,19 @@
+mod api_tokens;
 mod dashboard;
+mod email;
+mod logout;
+mod newsletters;
+mod outbox;
+mod password;
+mod two_factor;
+mod users;
 
+pub use api_tokens::*;
 pub use dashboard::admin_dashboard;
+pub use email::*;
+pub use logout::log_out;
+pub use newsletters::*;
+pub use outbox::admin_outbox;
+pub use password::*;
+pub use two_factor::*;
+pub use users::*;
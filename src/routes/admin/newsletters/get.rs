@@ -0,0 +1,217 @@
+use super::{format_send_time, sample_issue_content};
+use crate::authentication::{require_permission, Permission, Role};
+use crate::newsletter_issues::{get_issue, list_drafts, list_scheduled_issues, IssueStatus};
+use crate::startup::{ApplicationBaseUrl, AudienceTimezone};
+use crate::utils::err500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+// -- PUBLISH NEWSLETTER FORM -- //
+
+// new issues are saved as drafts - the drafts / scheduled lists link to their previews
+pub async fn publish_newsletter_form(
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+    audience_timezone: web::Data<AudienceTimezone>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::PublishNewsletters)?;
+    let drafts = list_drafts(&db_pool).await.map_err(err500)?;
+    let scheduled = list_scheduled_issues(&db_pool).await.map_err(err500)?;
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+    let mut drafts_html = String::new();
+    for draft in &drafts {
+        writeln!(
+            drafts_html,
+            r#"<li><a href="/admin/newsletters/{}">{}</a> (saved {})</li>"#,
+            draft.newsletter_issue_id,
+            encode_minimal(&draft.title),
+            draft.created_at.format("%Y-%m-%d %H:%M UTC"),
+        )
+        .unwrap();
+    }
+    if drafts_html.is_empty() {
+        drafts_html.push_str("<li>No drafts.</li>");
+    }
+    let mut scheduled_html = String::new();
+    for issue in &scheduled {
+        writeln!(
+            scheduled_html,
+            r#"<li><a href="/admin/newsletters/{}">{}</a> (goes out {})</li>"#,
+            issue.newsletter_issue_id,
+            encode_minimal(&issue.title),
+            issue
+                .scheduled_for
+                .map(|send_time| format_send_time(send_time, audience_timezone.0))
+                .unwrap_or_default(),
+        )
+        .unwrap();
+    }
+    if scheduled_html.is_empty() {
+        scheduled_html.push_str("<li>Nothing scheduled.</li>");
+    }
+    // fresh key per page load - a double click or a resubmitted form saves the draft only once
+    let idempotency_key = Uuid::new_v4();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter Issues</title>
+</head>
+<body>
+    {msg_html}
+    <p>Scheduled:</p>
+    <ul>
+        {scheduled_html}
+    </ul>
+    <p>Drafts:</p>
+    <ul>
+        {drafts_html}
+    </ul>
+    <p>New issue:</p>
+    <form action="/admin/newsletters" method="post">
+        <label>Title
+            <input
+                type="text"
+                placeholder="Enter the issue title"
+                name="title"
+            >
+        </label>
+        <br>
+        <label>HTML content
+            <textarea
+                placeholder="Enter the content as HTML"
+                name="html_content"
+                rows="20"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        <label>Plain text content
+            <textarea
+                placeholder="Enter the content as plain text"
+                name="text_content"
+                rows="20"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+        <button type="submit">Save draft</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+// -- PREVIEW -- //
+
+// both parts as subscribers will get them - test send for any issue, publish / schedule only until it's published
+pub async fn newsletter_issue_preview(
+    path: web::Path<Uuid>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    audience_timezone: web::Data<AudienceTimezone>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::PublishNewsletters)?;
+    let Some(issue) = get_issue(&db_pool, path.into_inner())
+        .await
+        .map_err(err500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+    let sample = sample_issue_content(&issue, &base_url.0);
+    let audience_timezone = audience_timezone.0;
+    let status = match (issue.status, issue.scheduled_for, issue.published_at) {
+        (IssueStatus::Published, _, Some(published_at)) => format!(
+            "published on {}",
+            format_send_time(published_at, audience_timezone)
+        ),
+        (IssueStatus::Scheduled, Some(scheduled_for), _) => format!(
+            "scheduled for {}",
+            format_send_time(scheduled_for, audience_timezone)
+        ),
+        (status, _, _) => status.to_string(),
+    };
+    let mut publish_forms = String::new();
+    if issue.status != IssueStatus::Published {
+        write!(
+            publish_forms,
+            r#"<form action="/admin/newsletters/{id}/publish" method="post">
+        <button type="submit">Publish to all confirmed subscribers now</button>
+    </form>
+    <form action="/admin/newsletters/{id}/schedule" method="post">
+        <label>Send at (UTC{offset})
+            <input type="datetime-local" name="scheduled_for">
+        </label>
+        <button type="submit">Schedule</button>
+    </form>"#,
+            id = issue.newsletter_issue_id,
+            offset = audience_timezone,
+        )
+        .unwrap();
+    }
+    if issue.status == IssueStatus::Scheduled {
+        write!(
+            publish_forms,
+            r#"
+    <form action="/admin/newsletters/{}/cancel" method="post">
+        <button type="submit">Cancel schedule</button>
+    </form>"#,
+            issue.newsletter_issue_id,
+        )
+        .unwrap();
+    }
+
+    // the issue's HTML is rendered in a sandboxed frame - no scripts, no access to the admin session
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Preview</title>
+</head>
+<body>
+    {msg_html}
+    <p>Title: {title}</p>
+    <p>Status: {status}</p>
+    <p>HTML:</p>
+    <iframe sandbox srcdoc="{html}" width="600" height="400"></iframe>
+    <p>Plain text:</p>
+    <pre>{text}</pre>
+    <form action="/admin/newsletters/{id}/test" method="post">
+        <button type="submit">Send test to me</button>
+    </form>
+    {publish_forms}
+    <p><a href="/admin/newsletters">&lt;- Back</a></p>
+</body>
+</html>"#,
+            title = encode_minimal(&sample.title),
+            html = encode_minimal(&sample.html_content),
+            text = encode_minimal(&sample.text_content),
+            id = issue.newsletter_issue_id,
+        )))
+}
@@ -0,0 +1,50 @@
+mod get;
+mod post;
+
+pub use get::{newsletter_issue_preview, publish_newsletter_form};
+pub use post::{
+    cancel_scheduled_newsletter_issue, publish_newsletter_draft, save_newsletter_draft,
+    schedule_newsletter_issue, send_test_newsletter_issue,
+};
+
+use crate::domain::TemplateValue;
+use crate::issue_delivery_worker::issue_email_content;
+use crate::newsletter_issues::{personalize_issue, NewsletterIssue, PersonalizedIssue};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+// previews and test sends aren't addressed to a subscriber - placeholders get sample values
+// and the footer links to the bare unsubscribe page
+fn sample_issue_content(issue: &NewsletterIssue, base_url: &str) -> PersonalizedIssue {
+    let unsubscribe_link = format!("{}/subscriptions/unsubscribe", base_url);
+    let personalized = personalize_issue(
+        &issue.title,
+        &issue.text_content,
+        &issue.html_content,
+        &[
+            ("name", TemplateValue::Text("Ada Lovelace".into())),
+            (
+                "unsubscribe_url",
+                TemplateValue::Text(unsubscribe_link.clone()),
+            ),
+            ("confirmed_at", TemplateValue::Date(Utc::now())),
+        ],
+    );
+    let (html_content, text_content) = issue_email_content(&personalized, &unsubscribe_link);
+    PersonalizedIssue {
+        title: personalized.title,
+        text_content,
+        html_content,
+        text_links_to_unsubscribe: true,
+        html_links_to_unsubscribe: true,
+    }
+}
+
+// send times are entered and shown in the audience's timezone - stored in UTC
+// (the abbreviation + offset in effect at that time, ie. `CEST (UTC+02:00)`)
+fn format_send_time(send_time: DateTime<Utc>, audience_timezone: Tz) -> String {
+    send_time
+        .with_timezone(&audience_timezone)
+        .format("%Y-%m-%d %H:%M %Z (UTC%:z)")
+        .to_string()
+}
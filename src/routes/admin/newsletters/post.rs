@@ -0,0 +1,434 @@
+use super::{format_send_time, sample_issue_content};
+use crate::authentication::{get_user_email, require_permission, Permission, Role, UserId};
+use crate::clock::Clock;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::newsletter_issues::{
+    cancel_scheduled_issue, create_draft, get_issue, publish_draft, schedule_issue,
+    validate_issue_content,
+};
+use crate::startup::{ApplicationBaseUrl, AudienceTimezone};
+use crate::utils::{err400, err500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// subject line of the email - keep it readable in an inbox
+const MAX_TITLE_LENGTH: usize = 200;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    title: String,
+    html_content: String,
+    text_content: String,
+    idempotency_key: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleFormData {
+    // `<input type="datetime-local">` - no timezone, read as the audience's
+    scheduled_for: String,
+}
+
+// -- SAVE DRAFT -- //
+
+#[tracing::instrument(
+    name = "Save a newsletter draft",
+    skip(form, user_id, role, db_pool),
+    fields(user_id = %**user_id)
+)]
+pub async fn save_newsletter_draft(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::PublishNewsletters)?;
+    let user_id = user_id.into_inner();
+    let FormData {
+        title,
+        html_content,
+        text_content,
+        idempotency_key,
+    } = form.into_inner();
+    // the key comes from a hidden field of our own form - anything else isn't a browser using the page
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(err400)?;
+    // validated before claiming the key -> fixing the form and resubmitting isn't treated as a retry
+    let issue = match NewIssue::parse(title, html_content, text_content) {
+        Ok(issue) => issue,
+        Err(msg) => {
+            FlashMessage::error(msg).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+
+    let mut transaction = match try_processing(&db_pool, &idempotency_key, *user_id)
+        .await
+        .map_err(err500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        // the stored response is only the redirect - flash messages are cookies added afterwards, so send it again
+        NextAction::ReturnSavedResponse(saved_response) => {
+            FlashMessage::info("The draft has been saved.").send();
+            return Ok(saved_response);
+        }
+        NextAction::RequestInFlight => {
+            FlashMessage::error("This draft is still being saved - please wait a moment.").send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+    let issue_id = create_draft(
+        &mut transaction,
+        &issue.title,
+        &issue.text_content,
+        &issue.html_content,
+    )
+    .await
+    .map_err(err500)?;
+
+    // saving the response commits the transaction
+    let res = see_other(&format!("/admin/newsletters/{}", issue_id));
+    let res = save_response(transaction, &idempotency_key, *user_id, res)
+        .await
+        .map_err(err500)?;
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(res)
+}
+
+// -- SEND TEST -- //
+
+// only to the logged in admin's account email - nothing is queued
+#[tracing::instrument(
+    name = "Send a test newsletter issue",
+    skip(path, user_id, role, db_pool, email_client, base_url),
+    fields(user_id = %**user_id)
+)]
+pub async fn send_test_newsletter_issue(
+    path: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::PublishNewsletters)?;
+    let issue_id = path.into_inner();
+    let preview_url = format!("/admin/newsletters/{}", issue_id);
+    let Some(issue) = get_issue(&db_pool, issue_id).await.map_err(err500)? else {
+        FlashMessage::error("Unknown newsletter issue.").send();
+        return Ok(see_other("/admin/newsletters"));
+    };
+    let Some(email) = get_user_email(&db_pool, **user_id).await.map_err(err500)? else {
+        FlashMessage::error("Add an email address to your account to receive test emails.").send();
+        return Ok(see_other(&preview_url));
+    };
+    let recipient = SubscriberEmail::parse(email)
+        .map_err(anyhow::Error::msg)
+        .context("Stored account email is invalid")
+        .map_err(err500)?;
+
+    let sample = sample_issue_content(&issue, &base_url.0);
+    email_client
+        .send_email(
+            &recipient,
+            &format!("[TEST] {}", sample.title),
+            &sample.html_content,
+            &sample.text_content,
+        )
+        .await
+        .map_err(err500)?;
+
+    FlashMessage::info("A test email has been sent to your account email address.").send();
+    Ok(see_other(&preview_url))
+}
+
+// -- PUBLISH DRAFT -- //
+
+// publishing twice (double click, second tab) is a no-op - only a draft can be published
+#[tracing::instrument(
+    name = "Publish a newsletter draft",
+    skip(path, user_id, role, db_pool),
+    fields(user_id = %**user_id)
+)]
+pub async fn publish_newsletter_draft(
+    path: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::PublishNewsletters)?;
+    let issue_id = path.into_inner();
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the db pool")
+        .map_err(err500)?;
+    let published = publish_draft(&mut transaction, issue_id)
+        .await
+        .map_err(err500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a draft")
+        .map_err(err500)?;
+
+    if published {
+        FlashMessage::info("The newsletter issue has been published - emails will go out shortly.")
+            .send();
+    } else {
+        FlashMessage::error("This issue has already been published.").send();
+    }
+    Ok(see_other(&format!("/admin/newsletters/{}", issue_id)))
+}
+
+// -- SCHEDULE -- //
+
+// also reschedules - the scheduler (`issue_scheduler`) publishes the issue once the time has come
+#[tracing::instrument(
+    name = "Schedule a newsletter issue",
+    skip(path, form, role, db_pool, clock, audience_timezone)
+)]
+pub async fn schedule_newsletter_issue(
+    path: web::Path<Uuid>,
+    form: web::Form<ScheduleFormData>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+    clock: web::Data<dyn Clock>,
+    audience_timezone: web::Data<AudienceTimezone>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::PublishNewsletters)?;
+    let issue_id = path.into_inner();
+    let preview_url = format!("/admin/newsletters/{}", issue_id);
+    let scheduled_for = match parse_send_time(&form.scheduled_for, audience_timezone.0, clock.now())
+    {
+        Ok(scheduled_for) => scheduled_for,
+        Err(msg) => {
+            FlashMessage::error(msg).send();
+            return Ok(see_other(&preview_url));
+        }
+    };
+
+    let scheduled = schedule_issue(&db_pool, issue_id, scheduled_for)
+        .await
+        .map_err(err500)?;
+    if scheduled {
+        FlashMessage::info(format!(
+            "The issue will go out on {}.",
+            format_send_time(scheduled_for, audience_timezone.0)
+        ))
+        .send();
+    } else {
+        FlashMessage::error("This issue has already been published.").send();
+    }
+    Ok(see_other(&preview_url))
+}
+
+// back to a draft - nothing is sent
+#[tracing::instrument(
+    name = "Cancel a scheduled newsletter issue",
+    skip_all,
+    fields(newsletter_issue_id = %path)
+)]
+pub async fn cancel_scheduled_newsletter_issue(
+    path: web::Path<Uuid>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::PublishNewsletters)?;
+    let issue_id = path.into_inner();
+
+    let cancelled = cancel_scheduled_issue(&db_pool, issue_id)
+        .await
+        .map_err(err500)?;
+    if cancelled {
+        FlashMessage::info("The schedule has been cancelled - the issue is a draft again.").send();
+    } else {
+        FlashMessage::error("This issue isn't scheduled.").send();
+    }
+    Ok(see_other(&format!("/admin/newsletters/{}", issue_id)))
+}
+
+// -- HELPERS for SAVE DRAFT -- //
+
+// bodies are sent as typed - only the title is trimmed
+#[derive(Debug)]
+struct NewIssue {
+    title: String,
+    html_content: String,
+    text_content: String,
+}
+
+impl NewIssue {
+    fn parse(title: String, html_content: String, text_content: String) -> Result<Self, String> {
+        let title = title.trim().to_string();
+        if title.is_empty() {
+            return Err("The title can't be empty.".into());
+        }
+        if title.chars().count() > MAX_TITLE_LENGTH {
+            return Err(format!(
+                "The title must be at most {} characters long.",
+                MAX_TITLE_LENGTH
+            ));
+        }
+        if html_content.trim().is_empty() {
+            return Err("The HTML content can't be empty.".into());
+        }
+        if text_content.trim().is_empty() {
+            return Err("The plain text content can't be empty.".into());
+        }
+        validate_issue_content(&title, &text_content, &html_content)?;
+
+        Ok(Self {
+            title,
+            html_content,
+            text_content,
+        })
+    }
+}
+
+// -- HELPERS for SCHEDULE -- //
+
+// browsers send `YYYY-MM-DDTHH:MM`, some with seconds
+fn parse_send_time(
+    input: &str,
+    audience_timezone: Tz,
+    now: DateTime<Utc>,
+) -> Result<DateTime<Utc>, String> {
+    let input = input.trim();
+    let local = NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| "Please enter a valid date and time.".to_string())?;
+    let send_time = match audience_timezone.from_local_datetime(&local) {
+        LocalResult::Single(send_time) => send_time,
+        // clocks going back -> the time happens twice, the first one is meant
+        LocalResult::Ambiguous(earliest, _) => earliest,
+        // clocks going forward -> the time is skipped
+        LocalResult::None => {
+            return Err(format!(
+                "{} doesn't exist in {} (the clocks skip it) - please pick another time.",
+                local.format("%Y-%m-%d %H:%M"),
+                audience_timezone.name()
+            ))
+        }
+    }
+    .with_timezone(&Utc);
+    if send_time <= now {
+        return Err("The send time must be in the future.".into());
+    }
+
+    Ok(send_time)
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_send_time, NewIssue};
+    use chrono::{DateTime, Utc};
+    use chrono_tz::{Europe, UTC};
+    use claims::{assert_err, assert_ok};
+
+    fn parse(title: &str, html: &str, text: &str) -> Result<NewIssue, String> {
+        NewIssue::parse(title.into(), html.into(), text.into())
+    }
+
+    #[test]
+    fn valid_issues_have_their_title_trimmed() {
+        let issue = assert_ok!(parse("  Issue #1 ", "<p>Hi</p>", "Hi\n"));
+        assert_eq!(issue.title, "Issue #1");
+        assert_eq!(issue.html_content, "<p>Hi</p>");
+        assert_eq!(issue.text_content, "Hi\n");
+    }
+
+    #[test]
+    fn invalid_issues_are_rejected() {
+        let long_title = "a".repeat(201);
+        let test_cases = vec![
+            (parse("", "<p>Hi</p>", "Hi"), "empty title"),
+            (parse("  ", "<p>Hi</p>", "Hi"), "blank title"),
+            (parse(&long_title, "<p>Hi</p>", "Hi"), "too long title"),
+            (parse("Issue", " \n", "Hi"), "blank html"),
+            (parse("Issue", "<p>Hi</p>", ""), "empty text"),
+            (
+                parse("Issue", "<p>Hi {{ nickname }}</p>", "Hi"),
+                "unknown placeholder",
+            ),
+        ];
+        for (outcome, description) in test_cases {
+            assert_err!(outcome, "{} was accepted", description);
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2026-10-17T12:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn send_times_are_read_in_the_audience_timezone() {
+        let send_time = assert_ok!(parse_send_time("2026-10-20T09:00", Europe::Berlin, now()));
+        assert_eq!(
+            send_time,
+            "2026-10-20T07:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        let send_time = assert_ok!(parse_send_time(
+            "2026-10-20T09:00:30",
+            Europe::Berlin,
+            now()
+        ));
+        assert_eq!(
+            send_time,
+            "2026-10-20T07:00:30Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn send_times_follow_daylight_saving_time() {
+        // after the switch back to CET
+        let send_time = assert_ok!(parse_send_time("2026-11-02T09:00", Europe::Berlin, now()));
+        assert_eq!(
+            send_time,
+            "2026-11-02T08:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn repeated_local_times_pick_the_earliest() {
+        // 02:00-03:00 happens twice on 2026-10-25, first in CEST then in CET
+        let send_time = assert_ok!(parse_send_time("2026-10-25T02:30", Europe::Berlin, now()));
+        assert_eq!(
+            send_time,
+            "2026-10-25T00:30:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn skipped_local_times_are_rejected() {
+        // 02:00-03:00 doesn't happen on 2027-03-28
+        let err = assert_err!(parse_send_time("2027-03-28T02:30", Europe::Berlin, now()));
+        assert!(err.contains("doesn't exist in Europe/Berlin"), "{}", err);
+    }
+
+    #[test]
+    fn invalid_and_past_send_times_are_rejected() {
+        let test_cases = vec![
+            ("", "empty"),
+            ("next tuesday", "not a date"),
+            ("2026-02-30T09:00", "impossible date"),
+            ("2026-10-17T12:00", "now"),
+            ("2026-10-16T09:00", "in the past"),
+        ];
+        for (input, description) in test_cases {
+            assert_err!(
+                parse_send_time(input, UTC, now()),
+                "{} was accepted",
+                description
+            );
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::email_client::list_outbox_messages;
+use crate::startup::OutboxDirectory;
+use crate::utils::err500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use htmlescape::encode_minimal;
+use std::fmt::Write;
+
+// -- ADMIN OUTBOX -- //
+
+// only routed in the `local` environment with the `outbox` email provider (see `startup::run`)
+pub async fn admin_outbox(
+    outbox_directory: web::Data<OutboxDirectory>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let messages = list_outbox_messages(&outbox_directory.0)
+        .await
+        .map_err(err500)?;
+
+    let mut rows = String::new();
+    for message in &messages {
+        let mut links = String::new();
+        for link in &message.links {
+            let link = encode_minimal(link);
+            writeln!(links, r#"<a href="{link}">{link}</a><br>"#).unwrap();
+        }
+        writeln!(
+            rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&message.created_at),
+            encode_minimal(&message.to),
+            encode_minimal(&message.subject),
+            links,
+            encode_minimal(&message.eml_file),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Outbox</title>
+</head>
+<body>
+    <p>{} message(s) in <code>{}</code></p>
+    <table>
+        <tr><th>Sent at</th><th>To</th><th>Subject</th><th>Links</th><th>File</th></tr>
+        {rows}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            messages.len(),
+            encode_minimal(&outbox_directory.0.display().to_string()),
+        )))
+}
@@ -0,0 +1,69 @@
+use crate::authentication::{
+    self, check_password_policy, AuthError, Credentials, LoginThrottle, UserId,
+};
+use crate::client_ip::ClientIp;
+use crate::configuration::Argon2Settings;
+use crate::routes::admin::dashboard::get_username;
+use crate::utils::{err500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+// -- CHANGE PASSWORD -- //
+
+pub async fn change_password(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    login_throttle: web::Data<LoginThrottle>,
+    argon2: web::Data<Argon2Settings>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    if let Err(msg) = check_password_policy(&form.new_password) {
+        FlashMessage::error(msg).send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    let username = get_username(user_id, &db_pool).await.map_err(err500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+    // same counters as the login form - a hijacked session can't be used to brute-force the password
+    if let Err(err) = login_throttle
+        .validate_credentials(credentials, client_ip, &argon2, &db_pool)
+        .await
+    {
+        return match err {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/password"))
+            }
+            AuthError::UnexpectedError(_) => Err(err500(err)),
+        };
+    }
+
+    authentication::change_password(user_id, form.0.new_password, &argon2, &db_pool)
+        .await
+        .map_err(err500)?;
+    FlashMessage::info("Your password has been changed.").send();
+    Ok(see_other("/admin/password"))
+}
@@ -0,0 +1,67 @@
+use crate::authentication::{get_two_factor_status, UserId};
+use crate::utils::err500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+// -- TWO-FACTOR AUTHENTICATION -- //
+
+// status + either the enroll button or the disable form
+pub async fn two_factor_page(
+    user_id: web::ReqData<UserId>,
+    db_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let status = get_two_factor_status(&db_pool, *user_id)
+        .await
+        .map_err(err500)?;
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+
+    let body = if status.enabled {
+        format!(
+            r#"<p>Two-factor authentication is <b>enabled</b>. Unused recovery codes: {}.</p>
+    <form action="/admin/two_factor/disable" method="post">
+        <label>Authentication code
+            <input
+                type="text"
+                placeholder="6-digit code or recovery code"
+                name="code"
+                autocomplete="one-time-code"
+            >
+        </label>
+        <button type="submit">Disable two-factor authentication</button>
+    </form>"#,
+            status.unused_recovery_codes
+        )
+    } else {
+        r#"<p>Two-factor authentication is <b>disabled</b>.</p>
+    <form action="/admin/two_factor/enroll" method="post">
+        <button type="submit">Set up an authenticator app</button>
+    </form>"#
+            .to_string()
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    {body}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,169 @@
+use crate::authentication::{
+    self, get_two_factor_status, LoginThrottle, SecondFactorCheck, TotpCipher, UserId,
+};
+use crate::routes::admin::dashboard::get_username;
+use crate::utils::{err500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use chrono::Utc;
+use htmlescape::encode_minimal;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: Secret<String>,
+}
+
+// -- ENROLL -- //
+
+// generates a pending secret and shows it once - it only becomes active after `confirm_two_factor`
+pub async fn enroll_two_factor(
+    user_id: web::ReqData<UserId>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<TotpCipher>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let status = get_two_factor_status(&db_pool, *user_id)
+        .await
+        .map_err(err500)?;
+    if status.enabled {
+        FlashMessage::error("Two-factor authentication is already enabled.").send();
+        return Ok(see_other("/admin/two_factor"));
+    }
+
+    let username = get_username(*user_id, &db_pool).await.map_err(err500)?;
+    let secret = authentication::begin_two_factor_enrollment(&db_pool, &cipher, *user_id)
+        .await
+        .map_err(err500)?;
+    let otpauth_uri = encode_minimal(&secret.otpauth_uri(&username));
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Set up two-factor authentication</title>
+</head>
+<body>
+    <p>Add this account to your authenticator app - open <a id="otpauth-uri" href="{otpauth_uri}">{otpauth_uri}</a>
+    or enter the secret manually:</p>
+    <p><code id="totp-secret">{}</code></p>
+    <p>Then enter the code your app shows to finish the setup.</p>
+    <form action="/admin/two_factor/confirm" method="post">
+        <label>Authentication code
+            <input
+                type="text"
+                placeholder="6-digit code"
+                name="code"
+                autocomplete="one-time-code"
+            >
+        </label>
+        <button type="submit">Enable two-factor authentication</button>
+    </form>
+    <p><a href="/admin/two_factor">&lt;- Back</a></p>
+</body>
+</html>"#,
+            secret.to_base32(),
+        )))
+}
+
+// -- CONFIRM -- //
+
+// enables 2FA and renders the recovery codes - like API tokens, they're shown exactly once
+pub async fn confirm_two_factor(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<TotpCipher>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let recovery_codes = authentication::confirm_two_factor_enrollment(
+        &db_pool,
+        &cipher,
+        *user_id,
+        form.0.code.expose_secret(),
+        Utc::now(),
+    )
+    .await
+    .map_err(err500)?;
+    let Some(recovery_codes) = recovery_codes else {
+        FlashMessage::error(
+            "The authentication code is invalid - start the setup again and use the new secret.",
+        )
+        .send();
+        return Ok(see_other("/admin/two_factor"));
+    };
+
+    let mut codes_html = String::new();
+    for code in &recovery_codes {
+        writeln!(codes_html, "<li><code>{}</code></li>", code).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication enabled</title>
+</head>
+<body>
+    <p>Two-factor authentication is now enabled.</p>
+    <p>Store these recovery codes somewhere safe - each one can replace an authentication code once
+    and they won't be shown again:</p>
+    <ul id="recovery-codes">
+    {codes_html}
+    </ul>
+    <p><a href="/admin/two_factor">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+// -- DISABLE -- //
+
+// asks for a current code (or a recovery code) - a hijacked session alone can't turn 2FA off
+pub async fn disable_two_factor(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<TotpCipher>,
+    login_throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    // shares the counter with the login step
+    let check = login_throttle
+        .verify_second_factor(
+            &db_pool,
+            &cipher,
+            *user_id,
+            form.0.code.expose_secret(),
+            Utc::now(),
+        )
+        .await
+        .map_err(err500)?;
+    match check {
+        SecondFactorCheck::Valid => {}
+        SecondFactorCheck::Invalid => {
+            FlashMessage::error("The authentication code is invalid.").send();
+            return Ok(see_other("/admin/two_factor"));
+        }
+        SecondFactorCheck::LockedOut => {
+            FlashMessage::error("Too many invalid authentication codes - please try again later.")
+                .send();
+            return Ok(see_other("/admin/two_factor"));
+        }
+    }
+
+    authentication::disable_two_factor(&db_pool, *user_id)
+        .await
+        .map_err(err500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/two_factor"))
+}
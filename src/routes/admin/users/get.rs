@@ -0,0 +1,130 @@
+use crate::authentication::{list_users, require_permission, Permission, Role, UserId};
+use crate::utils::err500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+// -- USERS -- //
+
+// every admin account + form to invite a new one - owners only
+pub async fn users_page(
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::ManageUsers)?;
+    let user_id = user_id.into_inner();
+    let users = list_users(&db_pool).await.map_err(err500)?;
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+
+    let mut rows = String::new();
+    for user in &users {
+        let status = if user.deactivated_at.is_some() {
+            "deactivated"
+        } else {
+            "active"
+        };
+        // owners can't lock themselves out - no actions on their own row
+        let actions = if user.user_id == *user_id {
+            "(you)".to_string()
+        } else {
+            let (toggle, label) = if user.deactivated_at.is_some() {
+                ("reactivate", "Reactivate")
+            } else {
+                ("deactivate", "Deactivate")
+            };
+            // a new link for invitees whose email never arrived - until they've set a password
+            let resend = if user.deactivated_at.is_none()
+                && user.email.is_some()
+                && user.invitation_pending
+            {
+                format!(
+                    r#"<form action="/admin/users/{}/resend_invitation" method="post"><input type="submit" value="Resend invitation"></form>"#,
+                    user.user_id
+                )
+            } else {
+                String::new()
+            };
+            format!(
+                r#"<form action="/admin/users/{id}/role" method="post">{select}<input type="submit" value="Change role"></form>
+                <form action="/admin/users/{id}/{toggle}" method="post"><input type="submit" value="{label}"></form>{resend}"#,
+                id = user.user_id,
+                select = role_select(user.role),
+            )
+        };
+        writeln!(
+            rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&user.username),
+            user.email
+                .as_deref()
+                .map(encode_minimal)
+                .unwrap_or_else(|| "-".into()),
+            user.role,
+            status,
+            actions,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Users</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Username</th><th>Email</th><th>Role</th><th>Status</th><th></th></tr>
+        {rows}
+    </table>
+    <p>Owners manage users, editors publish newsletters and manage subscribers, viewers can only look.</p>
+    <h2>Invite a user</h2>
+    <form action="/admin/users" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter username" name="username">
+        </label>
+        <br>
+        <label>Email
+            <input type="text" placeholder="Where the invitation is sent" name="email">
+        </label>
+        <br>
+        <label>Role
+            {role_select}
+        </label>
+        <br>
+        <button type="submit">Send invitation</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            role_select = role_select(Role::Editor),
+        )))
+}
+
+// -- HELPERS for USERS -- //
+
+fn role_select(selected: Role) -> String {
+    let mut options = String::new();
+    for role in Role::ALL {
+        let selected = if role == selected { " selected" } else { "" };
+        write!(
+            options,
+            r#"<option value="{role}"{selected}>{role}</option>"#
+        )
+        .unwrap();
+    }
+    format!(r#"<select name="role">{options}</select>"#)
+}
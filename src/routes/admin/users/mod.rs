@@ -0,0 +1,7 @@
+mod get;
+mod post;
+
+pub use get::users_page;
+pub use post::{
+    change_user_role, deactivate_user, invite_user, reactivate_user, resend_invitation,
+};
@@ -0,0 +1,351 @@
+use crate::authentication::{self, require_permission, Permission, Role, UserId};
+use crate::configuration::Argon2Settings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::startup::{ApplicationBaseUrl, InvitationTokenTtl};
+use crate::utils::{err500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use htmlescape::encode_minimal;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_USERNAME_LENGTH: usize = 64;
+
+#[derive(serde::Deserialize)]
+pub struct InviteFormData {
+    username: String,
+    email: String,
+    role: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleFormData {
+    role: String,
+}
+
+// -- INVITE USER -- //
+
+#[tracing::instrument(
+    skip(form, role, db_pool, email_client, base_url, argon2, ttl),
+    fields(invited_user_id = tracing::field::Empty)
+)]
+pub async fn invite_user(
+    form: web::Form<InviteFormData>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    argon2: web::Data<Argon2Settings>,
+    ttl: web::Data<InvitationTokenTtl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::ManageUsers)?;
+    let new_user = match NewUser::parse(form.into_inner()) {
+        Ok(new_user) => new_user,
+        Err(msg) => {
+            FlashMessage::error(msg).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+
+    let Some(invitation) = authentication::invite_user(
+        &db_pool,
+        &new_user.username,
+        &new_user.email,
+        new_user.role,
+        &argon2,
+        ttl.0,
+    )
+    .await
+    .map_err(err500)?
+    else {
+        FlashMessage::error("That username is already taken.").send();
+        return Ok(see_other("/admin/users"));
+    };
+    tracing::Span::current().record(
+        "invited_user_id",
+        &tracing::field::display(&invitation.user_id),
+    );
+
+    // the user is already stored - a failed email is reported instead of failing the whole request,
+    // the invitation can be sent again from the user list
+    if let Err(err) = send_invitation_email(
+        email_client.as_ref(),
+        &new_user.email,
+        &new_user.username,
+        new_user.role,
+        &base_url.0,
+        &invitation.token,
+        ttl.0,
+    )
+    .await
+    {
+        tracing::error!(
+            err.cause_chain = ?err,
+            err.message = %err,
+            "Failed to send an invitation email",
+        );
+        FlashMessage::error(
+            "The user has been created, but the invitation email could not be sent - use \"Resend invitation\" to try again.",
+        )
+        .send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    FlashMessage::info("The invitation has been sent.").send();
+    Ok(see_other("/admin/users"))
+}
+
+// -- RESEND INVITATION -- //
+
+// new link (the previous one stops working) for invitees whose email never arrived
+// refused once the invitee has set a password - it would be an unrequested password reset link
+#[tracing::instrument(skip(role, db_pool, email_client, base_url, ttl))]
+pub async fn resend_invitation(
+    path: web::Path<Uuid>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    ttl: web::Data<InvitationTokenTtl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::ManageUsers)?;
+
+    let Some(invitation) = authentication::reissue_invitation(&db_pool, path.into_inner(), ttl.0)
+        .await
+        .map_err(err500)?
+    else {
+        FlashMessage::error(
+            "Only pending invitations can be resent - the user is unknown, deactivated, has no email address or has already set a password.",
+        )
+        .send();
+        return Ok(see_other("/admin/users"));
+    };
+    if let Err(err) = send_invitation_email(
+        email_client.as_ref(),
+        &invitation.email,
+        &invitation.username,
+        invitation.role,
+        &base_url.0,
+        &invitation.token,
+        ttl.0,
+    )
+    .await
+    {
+        tracing::error!(
+            err.cause_chain = ?err,
+            err.message = %err,
+            "Failed to send an invitation email",
+        );
+        FlashMessage::error("The invitation email could not be sent - please try again later.")
+            .send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    FlashMessage::info("The invitation has been sent again.").send();
+    Ok(see_other("/admin/users"))
+}
+
+// -- CHANGE ROLE -- //
+
+pub async fn change_user_role(
+    path: web::Path<Uuid>,
+    form: web::Form<RoleFormData>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::ManageUsers)?;
+    let target_user_id = path.into_inner();
+    // there's always at least one owner left
+    if target_user_id == **user_id {
+        FlashMessage::error("You can't change your own role.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let new_role = match Role::parse(&form.role) {
+        Ok(new_role) => new_role,
+        Err(msg) => {
+            FlashMessage::error(msg).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+
+    let changed = authentication::change_user_role(&db_pool, target_user_id, new_role)
+        .await
+        .map_err(err500)?;
+    if changed {
+        FlashMessage::info("The role has been changed.").send();
+    } else {
+        FlashMessage::error("Unknown user.").send();
+    }
+    Ok(see_other("/admin/users"))
+}
+
+// -- DEACTIVATE / REACTIVATE -- //
+
+pub async fn deactivate_user(
+    path: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::ManageUsers)?;
+    let target_user_id = path.into_inner();
+    if target_user_id == **user_id {
+        FlashMessage::error("You can't deactivate your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let deactivated = authentication::deactivate_user(&db_pool, target_user_id)
+        .await
+        .map_err(err500)?;
+    if deactivated {
+        FlashMessage::info("The user has been deactivated.").send();
+    } else {
+        FlashMessage::error("Unknown or already deactivated user.").send();
+    }
+    Ok(see_other("/admin/users"))
+}
+
+pub async fn reactivate_user(
+    path: web::Path<Uuid>,
+    role: web::ReqData<Role>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(role.into_inner(), Permission::ManageUsers)?;
+
+    let reactivated = authentication::reactivate_user(&db_pool, path.into_inner())
+        .await
+        .map_err(err500)?;
+    if reactivated {
+        FlashMessage::info("The user has been reactivated.").send();
+    } else {
+        FlashMessage::error("Unknown or already active user.").send();
+    }
+    Ok(see_other("/admin/users"))
+}
+
+// -- HELPERS for INVITE USER -- //
+
+#[derive(Debug)]
+struct NewUser {
+    username: String,
+    email: SubscriberEmail,
+    role: Role,
+}
+
+impl NewUser {
+    fn parse(form: InviteFormData) -> Result<Self, String> {
+        let username = form.username.trim().to_string();
+        if username.is_empty() {
+            return Err("The username can't be empty.".into());
+        }
+        if username.chars().count() > MAX_USERNAME_LENGTH {
+            return Err(format!(
+                "The username must be at most {} characters long.",
+                MAX_USERNAME_LENGTH
+            ));
+        }
+        let email = SubscriberEmail::parse(form.email.trim().to_string())
+            .map_err(|_| "Please enter a valid email address.".to_string())?;
+        let role = Role::parse(&form.role)?;
+
+        Ok(Self {
+            username,
+            email,
+            role,
+        })
+    }
+}
+
+// same link as a password reset - setting the password is what "accepts" the invitation
+async fn send_invitation_email(
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
+    username: &str,
+    role: Role,
+    base_url: &str,
+    token: &Secret<String>,
+    ttl: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    let invitation_link = format!("{}/login/reset?token={}", base_url, token.expose_secret());
+    let text_content = format!(
+        "You've been invited to manage the newsletter as {}. Your username is {}.\n\
+        Visit {} to choose a password - the link expires in {} hours.",
+        role,
+        username,
+        invitation_link,
+        ttl.num_hours()
+    );
+    let html_content = format!(
+        "You've been invited to manage the newsletter as {}. Your username is <b>{}</b>.<br />\
+        Click <a href=\"{}\">here</a> to choose a password - the link expires in {} hours.",
+        role,
+        encode_minimal(username),
+        invitation_link,
+        ttl.num_hours()
+    );
+
+    email_client
+        .send_email(
+            recipient,
+            "You've been invited to the newsletter admin",
+            &html_content,
+            &text_content,
+        )
+        .await
+}
+
+// -- TESTING -- //
+
+#[cfg(test)]
+mod tests {
+    use super::{InviteFormData, NewUser};
+    use crate::authentication::Role;
+    use claims::{assert_err, assert_ok};
+
+    fn form(username: &str, email: &str, role: &str) -> InviteFormData {
+        InviteFormData {
+            username: username.into(),
+            email: email.into(),
+            role: role.into(),
+        }
+    }
+
+    #[test]
+    fn valid_invitations_are_trimmed_and_parsed() {
+        let new_user = assert_ok!(NewUser::parse(form(
+            "  ursula ",
+            " ursula@example.com",
+            "viewer"
+        )));
+        assert_eq!(new_user.username, "ursula");
+        assert_eq!(new_user.email.as_ref(), "ursula@example.com");
+        assert_eq!(new_user.role, Role::Viewer);
+    }
+
+    #[test]
+    fn invalid_invitations_are_rejected() {
+        let long_username = "a".repeat(65);
+        let test_cases = vec![
+            (form("", "ursula@example.com", "editor"), "empty username"),
+            (
+                form("   ", "ursula@example.com", "editor"),
+                "blank username",
+            ),
+            (
+                form(&long_username, "ursula@example.com", "editor"),
+                "too long username",
+            ),
+            (form("ursula", "not-an-email", "editor"), "invalid email"),
+            (
+                form("ursula", "ursula@example.com", "admin"),
+                "unknown role",
+            ),
+        ];
+        for (form, description) in test_cases {
+            assert_err!(NewUser::parse(form), "{} was accepted", description);
+        }
+    }
+}
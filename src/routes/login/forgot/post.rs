@@ -0,0 +1,120 @@
+use crate::authentication::{create_password_reset_token, LoginThrottle};
+use crate::client_ip::ClientIp;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::startup::{ApplicationBaseUrl, PasswordResetTokenTtl};
+use crate::utils::{err500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use tracing::Instrument;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+}
+
+// -- FORGOT PASSWORD -- //
+
+// the lookup, token and email all happen after the response is sent -> same answer, same timing
+// whether the username exists, has an email address or not
+#[tracing::instrument(
+    skip(form, db_pool, email_client, base_url, ttl, login_throttle),
+    fields(username = %form.username)
+)]
+pub async fn forgot_password(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    ttl: web::Data<PasswordResetTokenTtl>,
+    login_throttle: web::Data<LoginThrottle>,
+    client_ip: ClientIp,
+) -> Result<HttpResponse, actix_web::Error> {
+    let username = form.0.username;
+    // per username and per IP - doesn't say anything about whether the account exists
+    if !login_throttle
+        .password_reset_allowed(&username, client_ip)
+        .await
+        .map_err(err500)?
+    {
+        FlashMessage::error("Too many password reset requests - please try again later.").send();
+        return Ok(see_other("/login/forgot"));
+    }
+    let ttl = ttl.0;
+    tokio::spawn(
+        async move {
+            if let Err(err) =
+                send_password_reset(&db_pool, email_client.as_ref(), &base_url.0, &username, ttl)
+                    .await
+            {
+                tracing::error!(
+                    err.cause_chain = ?err,
+                    err.message = %err,
+                    "Failed to send a password reset email",
+                );
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+
+    FlashMessage::info(
+        "If the account exists and has an email address, a password reset link is on its way.",
+    )
+    .send();
+    Ok(see_other("/login"))
+}
+
+// -- HELPERS for FORGOT PASSWORD -- //
+
+async fn send_password_reset(
+    db_pool: &PgPool,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+    username: &str,
+    ttl: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    match create_password_reset_token(db_pool, username, ttl).await? {
+        Some(request) => {
+            send_password_reset_email(email_client, &request.email, base_url, &request.token, ttl)
+                .await
+        }
+        None => {
+            tracing::info!("No password reset email sent - unknown username or no email address");
+            Ok(())
+        }
+    }
+}
+
+async fn send_password_reset_email(
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
+    base_url: &str,
+    token: &Secret<String>,
+    ttl: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    let reset_link = format!("{}/login/reset?token={}", base_url, token.expose_secret());
+    let text_content = format!(
+        "Someone asked to reset your password.\nVisit {} to choose a new one - the link expires in {} minutes.\n\
+        If this wasn't you, ignore this email.",
+        reset_link,
+        ttl.num_minutes()
+    );
+    let html_content = format!(
+        "Someone asked to reset your password.<br />\
+        Click <a href=\"{}\">here</a> to choose a new one - the link expires in {} minutes.<br />\
+        If this wasn't you, ignore this email.",
+        reset_link,
+        ttl.num_minutes()
+    );
+
+    email_client
+        .send_email(
+            recipient,
+            "Reset your password",
+            &html_content,
+            &text_content,
+        )
+        .await
+}
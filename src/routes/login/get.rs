@@ -1,3 +1,5 @@
+// `Cookie` / `HttpRequest` belong to the commented out `_flash` cookie handling below
+#[allow(unused_imports)]
 use actix_web::{cookie::Cookie, http::header::ContentType, HttpRequest, HttpResponse};
 use actix_web_flash_messages::{IncomingFlashMessages, Level};
 use std::fmt::Write;
@@ -12,10 +14,11 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
     //     }
     // };
 
+    // errors (failed login) + info (ie. logged out)
     let mut err_html = String::new();
     for msg in flash_messages
         .iter()
-        .filter(|msg| msg.level() == Level::Error)
+        .filter(|msg| msg.level() == Level::Error || msg.level() == Level::Info)
     {
         writeln!(err_html, "<p><i>{}</i></p>", msg.content()).unwrap();
     }
@@ -51,6 +54,7 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
         </label>
         <button type="submit">Login</button>
     </form>
+    <p><a href="/login/forgot">Forgot your password?</a></p>
 </body>
 </html>"#,
         ))
@@ -1,4 +1,10 @@
+mod forgot;
 mod get;
 mod post;
+mod reset;
+mod two_factor;
+pub use forgot::*;
 pub use get::login_form;
 pub use post::login;
+pub use reset::*;
+pub use two_factor::*;
@@ -1,6 +1,11 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::authentication::{
+    get_session_generation, get_two_factor_status, AuthError, Credentials, LoginThrottle,
+};
+use crate::client_ip::ClientIp;
+use crate::configuration::Argon2Settings;
 use crate::routes::error_chain_fmt;
 use crate::session_state::TypedSession;
+use crate::utils::see_other;
 use actix_web::http::header::LOCATION;
 use actix_web::{web, HttpResponse};
 use secrecy::Secret;
@@ -16,10 +21,13 @@ pub struct FormData {
     password: Secret<String>,
 }
 
-#[tracing::instrument(skip(form, db_pool, session), fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
+#[tracing::instrument(skip(form, db_pool, login_throttle, argon2, session), fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
 pub async fn login(
     form: web::Form<FormData>,
+    client_ip: ClientIp,
     db_pool: web::Data<PgPool>,
+    login_throttle: web::Data<LoginThrottle>,
+    argon2: web::Data<Argon2Settings>,
     session: TypedSession,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
@@ -29,12 +37,35 @@ pub async fn login(
 
     tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
 
-    match validate_credentials(credentials, &db_pool).await {
+    // a lockout surfaces as the same "Authentication Failed" as a wrong password
+    match login_throttle
+        .validate_credentials(credentials, client_ip, &argon2, &db_pool)
+        .await
+    {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
             // to avoid session fixation attacks  (seed users browser with 'known' session token BFORE log in - wait for auth and then IN)
-            // rotates session token whenever user logs in:
-            session.renew();
+            // rotates session token whenever user logs in - and drops whoever was logged in before,
+            // a 2FA login would otherwise leave the previous `user_id` in place next to the pending marker
+            session.renew_and_clear();
+            // stored before the 2FA check -> a pending login that outlives a password reset is rejected as well
+            let generation = get_session_generation(&db_pool, user_id)
+                .await
+                .map_err(|err| login_redirect(LoginError::UnexpectedError(err)))?
+                .unwrap_or_default();
+            session
+                .insert_session_generation(generation)
+                .map_err(|err| login_redirect(LoginError::UnexpectedError(err.into())))?;
+            // 2FA users only get a "pending" marker here - `/login/2fa` finishes the login
+            let two_factor = get_two_factor_status(&db_pool, user_id)
+                .await
+                .map_err(|err| login_redirect(LoginError::UnexpectedError(err)))?;
+            if two_factor.enabled {
+                session
+                    .insert_pending_2fa_user_id(user_id)
+                    .map_err(|err| login_redirect(LoginError::UnexpectedError(err.into())))?;
+                return Ok(see_other("/login/2fa"));
+            }
             session
                 // .insert("user_id", user_id)
                 .insert_user_id(user_id)
@@ -0,0 +1,70 @@
+use crate::authentication::is_password_reset_token_valid;
+use crate::utils::{err500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use htmlescape::encode_minimal;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: Secret<String>,
+}
+
+// landing page of the emailed link - the token only gets consumed by the `POST`
+pub async fn reset_password_form(
+    parameters: web::Query<Parameters>,
+    db_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let valid = is_password_reset_token_valid(&parameters.token, &db_pool)
+        .await
+        .map_err(err500)?;
+    if !valid {
+        FlashMessage::error("The password reset link is invalid or has expired.").send();
+        return Ok(see_other("/login/forgot"));
+    }
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+    let token = encode_minimal(parameters.token.expose_secret());
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/login/reset" method="post">
+        <input type="hidden" name="token" value="{token}">
+        <label>New password
+            <input
+                type="password"
+                placeholder="Enter new password"
+                name="new_password"
+            >
+        </label>
+        <br>
+        <label>Confirm new password
+            <input
+                type="password"
+                placeholder="Type the new password again"
+                name="new_password_check"
+            >
+        </label>
+        <br>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
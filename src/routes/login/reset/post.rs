@@ -0,0 +1,59 @@
+use crate::authentication::{self, check_password_policy};
+use crate::configuration::Argon2Settings;
+use crate::utils::{err500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    token: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+// -- RESET PASSWORD -- //
+
+// form errors send the user back to the same link - the token is only consumed once the new password is accepted
+#[tracing::instrument(skip(form, argon2, db_pool), fields(user_id = tracing::field::Empty))]
+pub async fn reset_password(
+    form: web::Form<FormData>,
+    argon2: web::Data<Argon2Settings>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form_location = format!(
+        "/login/reset?token={}",
+        urlencoding::encode(form.token.expose_secret())
+    );
+
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&form_location));
+    }
+
+    if let Err(msg) = check_password_policy(&form.new_password) {
+        FlashMessage::error(msg).send();
+        return Ok(see_other(&form_location));
+    }
+
+    let form = form.into_inner();
+    match authentication::reset_password(&form.token, form.new_password, &argon2, &db_pool)
+        .await
+        .map_err(err500)?
+    {
+        Some(user_id) => {
+            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            FlashMessage::info("Your password has been reset - log in with your new password.")
+                .send();
+            Ok(see_other("/login"))
+        }
+        None => {
+            FlashMessage::error("The password reset link is invalid or has expired.").send();
+            Ok(see_other("/login/forgot"))
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::session_state::TypedSession;
+use crate::utils::{err500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use std::fmt::Write;
+
+// second login step - only reachable with a "pending 2FA" session left behind by `POST /login`
+pub async fn login_two_factor_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_pending_2fa_user_id().map_err(err500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let mut err_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(err_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {err_html}
+    <form action="/login/2fa" method="post">
+        <label>Authentication code
+            <input
+                type="text"
+                placeholder="6-digit code or recovery code"
+                name="code"
+                autocomplete="one-time-code"
+            >
+        </label>
+        <button type="submit">Verify</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,55 @@
+use crate::authentication::{LoginThrottle, SecondFactorCheck, TotpCipher};
+use crate::session_state::TypedSession;
+use crate::utils::{err500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: Secret<String>,
+}
+
+#[tracing::instrument(skip(form, db_pool, cipher, login_throttle, session), fields(user_id=tracing::field::Empty))]
+pub async fn login_two_factor(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<TotpCipher>,
+    login_throttle: web::Data<LoginThrottle>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session.get_pending_2fa_user_id().map_err(err500)? else {
+        return Ok(see_other("/login"));
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    let check = login_throttle
+        .verify_second_factor(
+            &db_pool,
+            &cipher,
+            user_id,
+            form.0.code.expose_secret(),
+            Utc::now(),
+        )
+        .await
+        .map_err(err500)?;
+    match check {
+        SecondFactorCheck::Valid => {
+            session.complete_2fa(user_id).map_err(err500)?;
+            Ok(see_other("/admin/dashboard"))
+        }
+        SecondFactorCheck::Invalid => {
+            FlashMessage::error("Invalid authentication code.").send();
+            Ok(see_other("/login/2fa"))
+        }
+        // the pending login is dropped - logging in again doesn't reset the counter either
+        SecondFactorCheck::LockedOut => {
+            session.log_out();
+            FlashMessage::error("Too many invalid authentication codes - please try again later.")
+                .send();
+            Ok(see_other("/login"))
+        }
+    }
+}
@@ -5,6 +5,8 @@ mod login;
 mod newsletters;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_resend_confirmation;
+mod subscriptions_unsubscribe;
 
 pub use admin::*;
 pub use health_check::*;
@@ -13,3 +15,5 @@ pub use login::*;
 pub use newsletters::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_resend_confirmation::*;
+pub use subscriptions_unsubscribe::*;
@@ -1,6 +1,12 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::authentication::{
+    get_two_factor_status, get_user_role, validate_api_token, ApiScope, AuthError, Credentials,
+    LoginThrottle, Permission, Role,
+};
+use crate::client_ip::ClientIp;
+use crate::configuration::{ApiSettings, Argon2Settings};
+use crate::domain::render_markdown;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::newsletter_issues::{create_draft, publish_issue, validate_issue_content};
 use crate::routes::error_chain_fmt;
 use actix_web::{
     http::header::{HeaderMap, HeaderValue},
@@ -11,12 +17,19 @@ use anyhow::Context;
 use base64::Engine;
 use secrecy::Secret;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 // handling json data shape
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
-    content: Content,
+    // either hand-written `content` or `markdown` - not both
+    content: Option<Content>,
+    // rendered server side into both parts of `content`
+    markdown: Option<String>,
+    // `true` stores the issue for review in `/admin/newsletters` instead of sending it
+    #[serde(default)]
+    draft: bool,
 }
 
 #[derive(serde::Deserialize)]
@@ -28,77 +41,188 @@ pub struct Content {
 // -- PUBLISH -- //
 
 #[tracing::instrument(name = "Publish a newsletter",
-    skip(body, db_pool, email_client, req),
-    fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
+    skip(body, db_pool, api_settings, login_throttle, argon2, req),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty, api_token_id=tracing::field::Empty))]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     db_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    api_settings: web::Data<ApiSettings>,
+    login_throttle: web::Data<LoginThrottle>,
+    argon2: web::Data<Argon2Settings>,
     req: HttpRequest,
+    client_ip: ClientIp,
 ) -> Result<HttpResponse, PublishError> {
-    // we ensure we bubble up error when extracting headers + credentials from request
-    let credentials = basic_authentication(req.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    // validate via db credential info
-    // let user_id = validate_credentials(credentials, &db_pool).await?;
-    let user_id = validate_credentials(credentials, &db_pool)
-        .await
-        // match on `AuthError` variants but pass ENTIRE err into constructors for `PublishError` variants ->
-        // keeps top-level wrapper context preserved when logging via middleware
-        .map_err(|err| match err {
-            AuthError::InvalidCredentials(_) => PublishError::AuthError(err.into()),
-            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(err.into()),
-        })?;
-
+    let user_id = authenticate(
+        &req,
+        client_ip,
+        &db_pool,
+        &api_settings,
+        &login_throttle,
+        &argon2,
+    )
+    .await?;
     tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    // checked on every request - a token keeps its scopes when its owner is later demoted
+    let role = get_user_role(&db_pool, user_id)
+        .await?
+        .context("Authenticated user is unknown or deactivated")?;
+    if !role.can(Permission::PublishNewsletters) {
+        return Err(PublishError::InsufficientRole(role));
+    }
+
+    let BodyData {
+        title,
+        content,
+        markdown,
+        draft,
+    } = body.into_inner();
+    let content = issue_content(content, markdown).map_err(PublishError::ValidationError)?;
+    validate_issue_content(&title, &content.text, &content.html)
+        .map_err(PublishError::ValidationError)?;
+
+    // optional so existing API clients keep working - when present, retries replay the first response
+    let idempotency_key =
+        get_idempotency_key(req.headers()).map_err(PublishError::ValidationError)?;
+    // issue + its delivery tasks are persisted together -> either every confirmed subscriber is queued or none are
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => match try_processing(&db_pool, idempotency_key, user_id).await? {
+            NextAction::StartProcessing(transaction) => transaction,
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+            NextAction::RequestInFlight => return Err(PublishError::Conflict),
+        },
+        None => db_pool
+            .begin()
+            .await
+            .context("Failed to acquire Postgres connection from the db pool")?,
+    };
+    let res = if draft {
+        let issue_id = create_draft(&mut transaction, &title, &content.text, &content.html).await?;
+        HttpResponse::Created().json(serde_json::json!({ "newsletter_issue_id": issue_id }))
+    } else {
+        publish_issue(&mut transaction, &title, &content.text, &content.html).await?;
+        // actual sending happens in `issue_delivery_worker` - respond without waiting on the email API
+        HttpResponse::Accepted().finish()
+    };
+    let res = match idempotency_key {
+        // saving the response commits the transaction
+        Some(idempotency_key) => save_response(transaction, &idempotency_key, user_id, res).await?,
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a newsletter issue")?;
+            res
+        }
+    };
+
+    Ok(res)
+}
 
-    let subscribers = get_confirmed_subscribers(&db_pool).await?;
-
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    // `lazy` difference between it and `context`:
-                    // takes closure and closure is ONLY called in case of err
-                    // for scenario where context has runtime cost -> avoid 'paying' for err path when op succeeds
-                    // -- this specific scenario avoids allocating `format!` call everytime email sent onto the heap (only for failure)
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
+// -- -- HELPERS for PUBLISH -- -- //
+
+fn issue_content(content: Option<Content>, markdown: Option<String>) -> Result<Content, String> {
+    match (content, markdown) {
+        (Some(content), None) => Ok(content),
+        (None, Some(markdown)) if markdown.trim().is_empty() => {
+            Err("The markdown content can't be empty.".into())
+        }
+        (None, Some(markdown)) => {
+            let rendered = render_markdown(&markdown);
+            Ok(Content {
+                html: rendered.html_content,
+                text: rendered.text_content,
+            })
+        }
+        (None, None) => Err("Either `content` or `markdown` is required.".into()),
+        (Some(_), Some(_)) => Err("Send either `content` or `markdown`, not both.".into()),
+    }
+}
+
+// `Bearer` API token (needs the `newsletters:publish` scope) or - while still allowed - `Basic` username/password
+async fn authenticate(
+    req: &HttpRequest,
+    client_ip: ClientIp,
+    db_pool: &PgPool,
+    api_settings: &ApiSettings,
+    login_throttle: &LoginThrottle,
+    argon2: &Argon2Settings,
+) -> Result<Uuid, PublishError> {
+    let auth_error = |source: anyhow::Error| PublishError::AuthError {
+        source,
+        allow_basic_auth: api_settings.allow_basic_auth,
+    };
+    // match on `AuthError` variants but pass ENTIRE err into constructors for `PublishError` variants ->
+    // keeps top-level wrapper context preserved when logging via middleware
+    let map_auth_error = |err: AuthError| match err {
+        AuthError::InvalidCredentials(_) => auth_error(err.into()),
+        AuthError::UnexpectedError(_) => PublishError::UnexpectedError(err.into()),
+    };
+
+    match api_credentials(req.headers()).map_err(auth_error)? {
+        ApiCredentials::Bearer(token) => {
+            let token = validate_api_token(token, db_pool)
+                .await
+                .map_err(map_auth_error)?;
+            tracing::Span::current().record(
+                "api_token_id",
+                &tracing::field::display(&token.api_token_id),
+            );
+            if !token.has_scope(ApiScope::NewslettersPublish) {
+                return Err(PublishError::Forbidden(ApiScope::NewslettersPublish));
             }
-            Err(err) => {
-                tracing::warn!(
-                    err.cause_chain = ?err,
-                    "Skipping a confirmed subscriber \
-                    Their stored contact details are invalid"
-                )
+            Ok(token.user_id)
+        }
+        ApiCredentials::Basic(_) if !api_settings.allow_basic_auth => Err(auth_error(
+            anyhow::anyhow!("`Basic` authentication is disabled - use an API token"),
+        )),
+        ApiCredentials::Basic(credentials) => {
+            tracing::Span::current()
+                .record("username", &tracing::field::display(&credentials.username));
+            // same brute-force protection as the login form
+            let user_id = login_throttle
+                .validate_credentials(credentials, client_ip, argon2, db_pool)
+                .await
+                .map_err(map_auth_error)?;
+            // a password alone must not get around the second factor -> those accounts need an API token
+            let two_factor = get_two_factor_status(db_pool, user_id)
+                .await
+                .map_err(PublishError::UnexpectedError)?;
+            if two_factor.enabled {
+                return Err(auth_error(anyhow::anyhow!(
+                    "`Basic` authentication is not available with two-factor authentication enabled - use an API token"
+                )));
             }
+            Ok(user_id)
         }
     }
-    Ok(HttpResponse::Ok().finish())
 }
 
-// -- -- HELPERS for PUBLISH -- -- //
+enum ApiCredentials {
+    Bearer(Secret<String>),
+    Basic(Credentials),
+}
 
-// set request's headers check and credential confirmation
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+// set request's headers check and credential extraction
+fn api_credentials(headers: &HeaderMap) -> Result<ApiCredentials, anyhow::Error> {
     let header_value = headers
         .get("Authorization")
         .context("`Authorization` Header was missing")?
         .to_str()
         .context("`Authorization` Header was not a valid UTF8 string")?;
 
+    if let Some(token) = header_value.strip_prefix("Bearer ") {
+        return Ok(ApiCredentials::Bearer(Secret::new(
+            token.trim().to_string(),
+        )));
+    }
     let base64encoded_segment = header_value
         .strip_prefix("Basic ")
-        .context("The auth scheme was not set to `Basic`")?;
+        .context("The auth scheme was not set to `Bearer` or `Basic`")?;
+
+    basic_credentials(base64encoded_segment).map(ApiCredentials::Basic)
+}
 
+fn basic_credentials(base64encoded_segment: &str) -> Result<Credentials, anyhow::Error> {
     let decoded_bytes = base64::engine::general_purpose::STANDARD
         .decode(base64encoded_segment)
         .context("Failed to base64-decode `Basic` credentials")?;
@@ -123,33 +247,18 @@ fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Erro
     })
 }
 
-struct ConfirmedSubscriber {
-    // email: String,
-    email: SubscriberEmail,
-}
-
-// adapter between storage and domain layer
-#[tracing::instrument(name = "Get confirmed subscribers", skip(db_pool))]
-async fn get_confirmed_subscribers(
-    db_pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
-        r#"
-        SELECT email
-        FROM subscriptions
-        WHERE status = 'confirmed'
-        "#,
-    )
-    .fetch_all(db_pool)
-    .await?
-    .into_iter()
-    .map(|row| match SubscriberEmail::parse(row.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(err) => Err(anyhow::anyhow!(err)),
-    })
-    .collect();
-
-    Ok(confirmed_subscribers)
+// read optional `Idempotency-Key` header - present but invalid is a client error
+fn get_idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, String> {
+    match headers.get("Idempotency-Key") {
+        None => Ok(None),
+        Some(header_value) => {
+            let key = header_value
+                .to_str()
+                .map_err(|_| "`Idempotency-Key` Header was not a valid UTF8 string".to_string())?
+                .to_owned();
+            key.try_into().map(Some)
+        }
+    }
 }
 
 // -- ERRORS for PUBLISH -- //
@@ -157,7 +266,20 @@ async fn get_confirmed_subscribers(
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error("Authentication Failed")]
-    AuthError(#[source] anyhow::Error),
+    AuthError {
+        #[source]
+        source: anyhow::Error,
+        // decides which schemes are advertised in `WWW-Authenticate`
+        allow_basic_auth: bool,
+    },
+    #[error("The API token is missing the `{0}` scope")]
+    Forbidden(ApiScope),
+    #[error("The `{0}` role is not allowed to publish newsletters")]
+    InsufficientRole(Role),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("A request with the same idempotency key is still being processed")]
+    Conflict,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -176,12 +298,26 @@ impl ResponseError for PublishError {
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
-            PublishError::AuthError(_) => {
+            PublishError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            PublishError::Conflict => HttpResponse::new(StatusCode::CONFLICT),
+            PublishError::Forbidden(_) | PublishError::InsufficientRole(_) => {
+                HttpResponse::new(StatusCode::FORBIDDEN)
+            }
+            PublishError::AuthError {
+                allow_basic_auth, ..
+            } => {
                 let mut res = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_val = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-                res.headers_mut()
-                    // actix_web::http::header - provides collection of constants for names of standard HTTP headers (ie. WWW_AUTHENTICATE)
-                    .insert(header::WWW_AUTHENTICATE, header_val);
+                // actix_web::http::header - provides collection of constants for names of standard HTTP headers (ie. WWW_AUTHENTICATE)
+                if *allow_basic_auth {
+                    res.headers_mut().append(
+                        header::WWW_AUTHENTICATE,
+                        HeaderValue::from_static(r#"Basic realm="publish""#),
+                    );
+                }
+                res.headers_mut().append(
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Bearer realm="publish""#),
+                );
                 res
             }
         }
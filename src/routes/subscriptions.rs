@@ -1,6 +1,12 @@
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    client_ip::ClientIp,
+    configuration::ThrottleSettings,
+    domain::{
+        EmailTemplate, NewSubscriber, SubscriberEmail, SubscriberName, TemplateValue,
+        CONFIRMATION_PLACEHOLDERS,
+    },
+    email_client::EmailSender,
+    routes::confirmation_email_allowed,
     startup::ApplicationBaseUrl,
 };
 use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
@@ -9,6 +15,7 @@ use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use sqlx::{Executor, PgPool, Postgres, Transaction};
+use tracing::Instrument;
 use uuid::Uuid;
 
 // form handling / implementation
@@ -35,7 +42,7 @@ impl TryFrom<FormData> for NewSubscriber {
 #[tracing::instrument(
     name = "Adding a new subscriber",
     // tracing by default captures all args to fn, skip used to omit info in log
-    skip(form, db_pool, email_client, base_url),
+    skip(form, client_ip, db_pool, email_client, base_url, throttle),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -46,42 +53,73 @@ pub async fn subscribe(
     // retrieving connection from app state
     db_pool: web::Data<PgPool>,
     // retrieve email client from app state
-    email_client: web::Data<EmailClient>,
+    email_client: web::Data<dyn EmailSender>,
     // app env base'd
     base_url: web::Data<ApplicationBaseUrl>,
+    throttle: web::Data<ThrottleSettings>,
+    client_ip: ClientIp,
 ) -> Result<HttpResponse, SubscribeError> {
     // get subscriber data from form input
     // note: no longer have #[from] for `SubscribeError::ValidationError` - have to map explicitly because `String` doesn't impl Error trait and can't be returned in Error::source (used `None` for error case handling prior)
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    // same limits as `/subscriptions/resend_confirmation` - signing up again is another way to get a confirmation email
+    // counted for every address (known or not) so being throttled doesn't reveal who's on the list
+    if !confirmation_email_allowed(&db_pool, &throttle, &new_subscriber.email, &client_ip).await? {
+        return Err(SubscribeError::TooManyRequests);
+    }
     // `begin` acquires connection from the db's pool to kick off transaction -- provides way to convert multi-steps of db interaction into 'all-or-nothing'
     let mut transaction = db_pool
         .begin()
         .await
         .context("Failed to acquire Postgres connection from the db pool")?;
-    // get subscriber's id from inserting into db, return `500` if fails
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    // `email` is UNIQUE - insert first so concurrent sign-ups for a new address can't both get past a lookup
+    let inserted = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert new subscriber into the database")?;
+    // `None` for an address that's already confirmed - nothing to send
+    let pending_subscriber_id = match inserted {
+        Some(subscriber_id) => Some(subscriber_id),
+        // an address we've already seen is handled based on where it is in the opt-in flow
+        None => {
+            let existing = get_existing_subscription(&mut transaction, &new_subscriber.email)
+                .await
+                .context("Failed to look up existing subscription in the database")?
+                .context("Conflicting subscription is missing")?;
+            if existing.status == "confirmed" {
+                None
+            } else {
+                // pending -> fresh token + resend, unsubscribed -> restart double opt-in
+                restart_confirmation(&mut transaction, existing.subscriber_id, &new_subscriber)
+                    .await
+                    .context("Failed to reset existing subscriber to `pending_confirmation`")?;
+                Some(existing.subscriber_id)
+            }
+        }
+    };
     // rand gen'd confirmation token
     let subscription_token = generate_subscription_token();
     // store subscriber's token in db associated to sub's id, `500` if fails
-    store_token(&mut transaction, subscriber_id, &subscription_token)
-        .await
-        .context("Failed to store confirmation token for new subscriber in db")?;
+    if let Some(subscriber_id) = pending_subscriber_id {
+        store_token(&mut transaction, subscriber_id, &subscription_token)
+            .await
+            .context("Failed to store confirmation token for new subscriber in db")?;
+    }
     // finalize db transaction/commit and return conn to db pool, `500` if fails
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store new subscriber into db")?;
-    // send email via external API service, `500` if fails
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send a confirmation email to new subscriber")?;
+    // already receiving newsletters -> same response as a new sign-up, and the email goes out after the response
+    // so the provider round trip doesn't give away who's subscribed either
+    if pending_subscriber_id.is_some() {
+        spawn_confirmation_email(
+            email_client,
+            new_subscriber.email,
+            base_url.0.clone(),
+            subscription_token,
+        );
+    }
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -93,6 +131,8 @@ pub enum SubscribeError {
     // destructuring of fields (0th) within given Error tuple variant
     #[error("{0}")]
     ValidationError(String),
+    #[error("Too many confirmation emails requested, try again later")]
+    TooManyRequests,
     // `transparent` delegates both `Display` + `source` impl's to type within `UnexpectedError`
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
@@ -112,6 +152,7 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -119,42 +160,142 @@ impl ResponseError for SubscribeError {
 
 // -- -- HELPER LOGIC for SUBSCRIBE -- -- //
 
+// SEND EMAIL confirmation in the background - failures are logged, the subscriber can ask for another one
+// via `/subscriptions/resend_confirmation`
+pub fn spawn_confirmation_email(
+    email_client: web::Data<dyn EmailSender>,
+    recipient: SubscriberEmail,
+    base_url: String,
+    subscription_token: String,
+) {
+    tokio::spawn(
+        async move {
+            if let Err(err) = send_confirmation_email(
+                email_client.as_ref(),
+                &recipient,
+                &base_url,
+                &subscription_token,
+            )
+            .await
+            {
+                tracing::error!(
+                    err.cause_chain = ?err,
+                    err.message = %err,
+                    "Failed to send a confirmation email",
+                );
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+}
+
 // SEND EMAIL confirmation to new subscriber
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    skip(email_client, recipient, base_url, subscription_token)
 )]
 pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
-    let text_content = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-    let html_content = format!(
-        "Welcome to our newsletter!<br />\
-        Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
+    // same templating as newsletter issues - the link is escaped for the HTML part
+    let values = [("confirmation_url", TemplateValue::Text(confirmation_link))];
+    let text_content = EmailTemplate::parse(CONFIRMATION_TEXT, CONFIRMATION_PLACEHOLDERS)
+        .map_err(anyhow::Error::msg)?
+        .render_text(&values);
+    let html_content = EmailTemplate::parse(CONFIRMATION_HTML, CONFIRMATION_PLACEHOLDERS)
+        .map_err(anyhow::Error::msg)?
+        .render_html(&values);
 
     email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome!",
-            &html_content,
-            &text_content,
-        )
+        .send_email(recipient, "Welcome!", &html_content, &text_content)
         .await
 }
 
+const CONFIRMATION_TEXT: &str =
+    "Welcome to our newsletter!\nVisit {{ confirmation_url }} to confirm your subscription.";
+const CONFIRMATION_HTML: &str = "Welcome to our newsletter!<br />\
+    Click <a href=\"{{ confirmation_url }}\">here</a> to confirm your subscription.";
+
+struct ExistingSubscription {
+    subscriber_id: Uuid,
+    status: String,
+}
+
+// row is locked until commit -> concurrent repeat sign-ups for the same address are handled one at a time
+#[tracing::instrument(name = "Get existing subscription", skip(transaction, email))]
+async fn get_existing_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscription>, sqlx::Error> {
+    let res = sqlx::query_as!(
+        ExistingSubscription,
+        r#"
+        SELECT id AS subscriber_id, status
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    Ok(res)
+}
+
+// back to `pending_confirmation` - older tokens are dropped so only the newest link works
+// the name from the form is kept aside until the address confirms, so anyone typing in the address can't rename the subscriber
+#[tracing::instrument(
+    name = "Restart confirmation for existing subscriber",
+    skip(transaction, new_subscriber)
+)]
+async fn restart_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            pending_name = $2,
+            status = 'pending_confirmation',
+            unsubscribed_at = NULL
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        new_subscriber.name.as_ref(),
+    );
+    transaction.execute(query).await?;
+    delete_tokens(transaction, subscriber_id).await?;
+
+    Ok(())
+}
+
+// invalidates every outstanding confirmation link for a subscriber
+#[tracing::instrument(name = "Delete subscription tokens", skip(transaction))]
+pub async fn delete_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id,
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
 // INSERT SUBSCRIBER into database
+// `None` if the address is already subscribed (in any state) - waits for a concurrent insert of the same address to settle
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -162,23 +303,24 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    let query = sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let res = sqlx::query!(
         r#"
         INSERT INTO subscriptions (id, email, name, subscribed_at, status)
         VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
-    );
-
-    transaction.execute(query).await?;
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
     // note: since propogating err upstream via '?' operator DON'T `tracing::error!` log here!
 
-    Ok(subscriber_id)
+    Ok(res.map(|row| row.id))
 }
 
 // INSERT TOKEN into database
@@ -207,7 +349,7 @@ pub async fn store_token(
 }
 
 // gen random 25 char token for email confirmation link -- 10^45 possibilities
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
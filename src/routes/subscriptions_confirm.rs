@@ -1,7 +1,10 @@
 use crate::routes::error_chain_fmt;
+use crate::startup::SubscriptionTokenTtl;
+use actix_web::http::header::ContentType;
 use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
 use anyhow::Context;
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 // for ensuring `subscription_token` query param via `Query`
@@ -13,52 +16,108 @@ pub struct Paramaters {
 // -- -- CONFIRM SUBSCRIPTION -- -- //
 
 // submission response handling / orchestration
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, db_pool))]
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, db_pool, subscription_token_ttl)
+)]
 pub async fn confirm(
     parameters: web::Query<Paramaters>,
     db_pool: web::Data<PgPool>,
+    subscription_token_ttl: web::Data<SubscriptionTokenTtl>,
 ) -> Result<HttpResponse, ConfirmationError> {
-    let subscriber_id = get_subscriber_id_from_token(&db_pool, &parameters.subscription_token)
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the db pool")?;
+    let token = get_token(&mut transaction, &parameters.subscription_token)
         .await
         .context("Failed to acquire subscriber ID from database with the given token")?
         .ok_or(ConfirmationError::UnknownToken)?;
 
-    confirm_subscriber(&db_pool, subscriber_id)
+    // tokens are single-use and only valid for the configured TTL
+    if token.consumed_at.is_some() || token.created_at < Utc::now() - subscription_token_ttl.0 {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+
+    consume_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to mark subscription token as consumed in database")?;
+    confirm_subscriber(&mut transaction, token.subscriber_id)
         .await
         .context("Failed to update subscription `status` to 'confirmed' in database")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber")?;
 
     Ok(HttpResponse::Ok().finish())
 }
 
 // -- HELPERS for CONFIRM SUBSCRIPTION -- //
 
-// update `status` based off subscriber_id in db
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, db_pool))]
-pub async fn confirm_subscriber(db_pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+// update `status` based off subscriber_id in db - a name from a repeated sign-up takes over here
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, transaction)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            status = 'confirmed',
+            confirmed_at = now(),
+            name = COALESCE(pending_name, name),
+            pending_name = NULL
+        WHERE id = $1
+        "#,
         subscriber_id,
-    )
-    .execute(db_pool)
-    .await?;
+    );
+    transaction.execute(query).await?;
 
     Ok(())
 }
 
-// returns subscriber_id associated with confirmation email token
-pub async fn get_subscriber_id_from_token(
-    db_pool: &PgPool,
+struct SubscriptionToken {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+// row is locked until commit -> two concurrent clicks can't both consume the same token
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let res = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens \
-        WHERE subscription_token = $1",
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    let res = sqlx::query_as!(
+        SubscriptionToken,
+        "SELECT subscriber_id, created_at, consumed_at FROM subscription_tokens \
+        WHERE subscription_token = $1 \
+        FOR UPDATE",
         subscription_token,
     )
-    .fetch_optional(db_pool)
+    .fetch_optional(&mut **transaction)
     .await?;
 
-    Ok(res.map(|row| row.subscriber_id))
+    Ok(res)
+}
+
+#[tracing::instrument(name = "Mark subscription token as consumed", skip_all)]
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        "UPDATE subscription_tokens SET consumed_at = now() \
+        WHERE subscription_token = $1",
+        subscription_token,
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
 }
 
 // -- ERRORS for CONFIRM -- //
@@ -69,6 +128,8 @@ pub enum ConfirmationError {
     UnexpectedError(#[from] anyhow::Error),
     #[error("No subscriber associated with provided token")]
     UnknownToken,
+    #[error("Subscription token has expired or was already used")]
+    ExpiredToken,
 }
 
 impl std::fmt::Debug for ConfirmationError {
@@ -81,7 +142,43 @@ impl ResponseError for ConfirmationError {
     fn status_code(&self) -> StatusCode {
         match self {
             ConfirmationError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmationError::ExpiredToken => StatusCode::GONE,
             ConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    // expired links are an expected outcome for real users (old email, double click) - explain + offer a new link instead of a bare status
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ConfirmationError::ExpiredToken => HttpResponse::build(self.status_code())
+                .content_type(ContentType::html())
+                .body(expired_token_html()),
+            // same as the default `ResponseError` impl
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
+}
+
+// offers a fresh link via `POST /subscriptions/resend_confirmation`
+fn expired_token_html() -> String {
+    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirmation link expired</title>
+</head>
+<body>
+    <p>This confirmation link has expired or has already been used.</p>
+    <p>Enter your email below and we'll send you a new one.</p>
+    <form action="/subscriptions/resend_confirmation" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter your email" name="email">
+        </label>
+        <button type="submit">Send a new link</button>
+    </form>
+</body>
+</html>"#
+        .to_string()
 }
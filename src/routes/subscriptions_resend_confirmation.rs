@@ -0,0 +1,190 @@
+use crate::client_ip::ClientIp;
+use crate::configuration::ThrottleSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::routes::{
+    delete_tokens, error_chain_fmt, generate_subscription_token, spawn_confirmation_email,
+    store_token,
+};
+use crate::startup::ApplicationBaseUrl;
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    email: String,
+}
+
+// -- -- RESEND CONFIRMATION -- -- //
+
+// same `200` (after the same work) whether the address is pending, confirmed, unsubscribed or unknown - the endpoint can't be used to probe the list
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, db_pool, email_client, base_url, throttle),
+    fields(subscriber_email = %form.email, client_ip = %client_ip)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    client_ip: ClientIp,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    throttle: web::Data<ThrottleSettings>,
+) -> Result<HttpResponse, ResendConfirmationError> {
+    let email =
+        SubscriberEmail::parse(form.0.email).map_err(ResendConfirmationError::ValidationError)?;
+
+    // counted for every address (known or not) so being throttled doesn't reveal anything either
+    if !confirmation_email_allowed(&db_pool, &throttle, &email, &client_ip).await? {
+        return Err(ResendConfirmationError::TooManyRequests);
+    }
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the db pool")?;
+    let pending_subscriber_id = get_pending_subscriber_id(&mut transaction, &email)
+        .await
+        .context("Failed to look up pending subscriber in the database")?;
+    // rotate - older links stop working once a new one is sent
+    let subscription_token = generate_subscription_token();
+    if let Some(subscriber_id) = pending_subscriber_id {
+        delete_tokens(&mut transaction, subscriber_id)
+            .await
+            .context("Failed to delete previous confirmation tokens")?;
+        store_token(&mut transaction, subscriber_id, &subscription_token)
+            .await
+            .context("Failed to store confirmation token for pending subscriber in db")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to rotate confirmation token")?;
+
+    // sent after the response - waiting on the provider (and its retries) would make pending addresses answer slower
+    if pending_subscriber_id.is_some() {
+        spawn_confirmation_email(email_client, email, base_url.0.clone(), subscription_token);
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// -- HELPERS for RESEND CONFIRMATION -- //
+
+// shared with `subscribe` (which resends to pending addresses too) - both endpoints count against the same limits
+#[tracing::instrument(
+    name = "Check confirmation email throttle",
+    skip(db_pool, throttle, email)
+)]
+pub async fn confirmation_email_allowed(
+    db_pool: &PgPool,
+    throttle: &ThrottleSettings,
+    email: &SubscriberEmail,
+    client_ip: &ClientIp,
+) -> Result<bool, anyhow::Error> {
+    let email_key = format!("email:{}", email.as_ref().to_lowercase());
+    let ip_key = format!("ip:{}", client_ip);
+    let email_allowed = try_acquire(
+        db_pool,
+        &email_key,
+        throttle.max_attempts_per_email,
+        throttle.window(),
+    )
+    .await
+    .context("Failed to update confirmation email throttle for email address")?;
+    let ip_allowed = try_acquire(
+        db_pool,
+        &ip_key,
+        throttle.max_attempts_per_ip,
+        throttle.window(),
+    )
+    .await
+    .context("Failed to update confirmation email throttle for IP")?;
+
+    Ok(email_allowed && ip_allowed)
+}
+
+#[tracing::instrument(name = "Get pending subscriber", skip(transaction, email))]
+async fn get_pending_subscriber_id(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let res = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE email = $1 AND status = 'pending_confirmation'
+        FOR UPDATE
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    Ok(res.map(|row| row.id))
+}
+
+// fixed window counter - increments atomically and starts a fresh window once the old one has elapsed
+// returns `false` once `max_attempts` is exceeded within the current window
+#[tracing::instrument(name = "Check resend throttle", skip(db_pool))]
+async fn try_acquire(
+    db_pool: &PgPool,
+    throttle_key: &str,
+    max_attempts: i32,
+    window: chrono::Duration,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO resend_confirmation_throttle (throttle_key, window_started_at, attempts)
+        VALUES ($1, now(), 1)
+        ON CONFLICT (throttle_key) DO UPDATE
+        SET
+            attempts = CASE
+                WHEN resend_confirmation_throttle.window_started_at < $2 THEN 1
+                ELSE resend_confirmation_throttle.attempts + 1
+            END,
+            window_started_at = CASE
+                WHEN resend_confirmation_throttle.window_started_at < $2 THEN now()
+                ELSE resend_confirmation_throttle.window_started_at
+            END
+        RETURNING attempts
+        "#,
+        throttle_key,
+        Utc::now() - window,
+    )
+    .fetch_one(db_pool)
+    .await?;
+
+    Ok(row.attempts <= max_attempts)
+}
+
+// -- ERRORS for RESEND CONFIRMATION -- //
+
+#[derive(thiserror::Error)]
+pub enum ResendConfirmationError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Too many confirmation emails requested, try again later")]
+    TooManyRequests,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ResendConfirmationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ResendConfirmationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ResendConfirmationError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ResendConfirmationError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ResendConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::domain::UnsubscribeToken;
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+use actix_web::http::header::ContentType;
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// both params come from the link embedded in every newsletter (see `unsubscribe_link`)
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+// -- -- UNSUBSCRIBE -- -- //
+
+// `GET` only renders a confirmation page - link scanners / prefetchers follow links, so they must not unsubscribe anyone
+#[tracing::instrument(name = "Show unsubscribe page", skip(parameters, db_pool, hmac_secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    db_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let token = parameters.verify(&hmac_secret.0)?;
+    let status = get_subscription_status(&db_pool, parameters.subscriber_id)
+        .await
+        .context("Failed to fetch subscription status from database")?
+        .ok_or(UnsubscribeError::UnknownSubscriber)?;
+
+    let body = if status == "unsubscribed" {
+        "<p>You have already unsubscribed.</p>".to_string()
+    } else {
+        format!(
+            r#"<form action="/subscriptions/unsubscribe?subscriber_id={}&token={}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>"#,
+            parameters.subscriber_id,
+            token.as_ref(),
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(unsubscribe_page(&body)))
+}
+
+// RFC 8058 one-click target as well - mail clients `POST` `List-Unsubscribe=One-Click` here, the body is ignored
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, db_pool, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    db_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    parameters.verify(&hmac_secret.0)?;
+    let found = mark_subscriber_as_unsubscribed(&db_pool, parameters.subscriber_id)
+        .await
+        .context("Failed to update subscription `status` to 'unsubscribed' in database")?;
+    if !found {
+        return Err(UnsubscribeError::UnknownSubscriber);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(unsubscribe_page("<p>You have been unsubscribed.</p>")))
+}
+
+// -- HELPERS for UNSUBSCRIBE -- //
+
+// personalised link added to every newsletter issue by the delivery worker
+pub fn unsubscribe_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    hmac_secret: &Secret<String>,
+) -> String {
+    let token = UnsubscribeToken::generate(subscriber_id, hmac_secret);
+    format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+        base_url,
+        subscriber_id,
+        token.as_ref()
+    )
+}
+
+impl UnsubscribeParameters {
+    fn verify(&self, hmac_secret: &Secret<String>) -> Result<UnsubscribeToken, UnsubscribeError> {
+        UnsubscribeToken::parse(self.token.clone(), self.subscriber_id, hmac_secret)
+            .map_err(UnsubscribeError::InvalidToken)
+    }
+}
+
+fn unsubscribe_page(body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    {body}
+</body>
+</html>"#
+    )
+}
+
+#[tracing::instrument(name = "Get subscription status", skip(db_pool))]
+async fn get_subscription_status(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let res = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id,
+    )
+    .fetch_optional(db_pool)
+    .await?;
+
+    Ok(res.map(|row| row.status))
+}
+
+// idempotent - unsubscribing twice keeps the original `unsubscribed_at`
+// returns `false` if there's no such subscriber
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(db_pool))]
+async fn mark_subscriber_as_unsubscribed(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            status = 'unsubscribed',
+            unsubscribed_at = COALESCE(unsubscribed_at, now())
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+// -- ERRORS for UNSUBSCRIBE -- //
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error("No subscriber associated with provided token")]
+    UnknownSubscriber,
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidToken(_) | UnsubscribeError::UnknownSubscriber => {
+                StatusCode::UNAUTHORIZED
+            }
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -10,8 +10,13 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const PENDING_2FA_USER_ID_KEY: &'static str = "pending_2fa_user_id";
+    const SESSION_GENERATION_KEY: &'static str = "session_generation";
 
-    pub fn renew(&self) {
+    // new session key AND no state carried over - an earlier login (`user_id`) or pending 2FA marker
+    // in the same browser must not survive the start of another login
+    pub fn renew_and_clear(&self) {
+        self.0.clear();
         self.0.renew();
     }
 
@@ -22,6 +27,37 @@ impl TypedSession {
     pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
         self.0.get(Self::USER_ID_KEY)
     }
+
+    // `users.session_generation` at login time - a password reset bumps it, `RejectAnonymousUsers` compares the two
+    pub fn insert_session_generation(&self, generation: i32) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_GENERATION_KEY, generation)
+    }
+
+    pub fn get_session_generation(&self) -> Result<Option<i32>, SessionGetError> {
+        self.0.get(Self::SESSION_GENERATION_KEY)
+    }
+
+    // password checked, second factor still missing -> NOT logged in (`get_user_id` stays empty)
+    pub fn insert_pending_2fa_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_2FA_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_2fa_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::PENDING_2FA_USER_ID_KEY)
+    }
+
+    // second factor accepted - swap the pending marker for a real login
+    // (the session generation was already stored by the password step)
+    pub fn complete_2fa(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.renew();
+        self.0.remove(Self::PENDING_2FA_USER_ID_KEY);
+        self.insert_user_id(user_id)
+    }
+
+    // removes session state server-side (Redis) AND expires the session cookie client-side
+    pub fn log_out(self) {
+        self.0.purge()
+    }
 }
 
 // to enable req handlers to build instance of `TypedSession`
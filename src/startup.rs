@@ -1,8 +1,22 @@
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
+use crate::authentication::{LoginThrottle, RejectAnonymousUsers, TotpCipher};
+use crate::client_ip::TrustedProxies;
+use crate::clock::Clock;
+use crate::configuration::{
+    ApplicationSettings, DatabaseSettings, EmailProvider, Environment, Settings,
+};
+use crate::email_client::EmailSender;
 use crate::routes::{
-    admin_dashboard, confirm, health_check, home, login, login_form, publish_newsletter, subscribe,
+    admin_dashboard, admin_outbox, api_tokens_page, cancel_scheduled_newsletter_issue,
+    change_email, change_password, change_password_form, change_user_role, confirm,
+    confirm_two_factor, create_api_token, deactivate_user, disable_two_factor, email_form,
+    enroll_two_factor, forgot_password, forgot_password_form, health_check, home, invite_user,
+    log_out, login, login_form, login_two_factor, login_two_factor_form, newsletter_issue_preview,
+    publish_newsletter, publish_newsletter_draft, publish_newsletter_form, reactivate_user,
+    resend_confirmation, resend_invitation, reset_password, reset_password_form, revoke_api_token,
+    save_newsletter_draft, schedule_newsletter_issue, send_test_newsletter_issue, subscribe,
+    two_factor_page, unsubscribe, unsubscribe_form, users_page,
 };
+use crate::telemetry::AppRootSpanBuilder;
 
 use actix_session::{storage::RedisSessionStore, SessionMiddleware};
 use actix_web::cookie::Key;
@@ -14,6 +28,8 @@ use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tracing_actix_web::TracingLogger;
 
 // holds server as well as app port
@@ -23,25 +39,27 @@ pub struct Application {
 }
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+    pub async fn build(
+        configuration: Settings,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, anyhow::Error> {
         // let conn_pool = PgPoolOptions::new().connect_lazy_with(configuration.database.with_db());
         let conn_pool = get_connection_pool(&configuration.database)
             .await
             .expect("Failed to connect to Postgres db");
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-
-        let timeout = configuration.email_client.timeout();
-
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        // outbox page is a local development aid - never exposed in production
+        let outbox_directory = match (
+            &configuration.environment,
+            &configuration.email_client.provider,
+            &configuration.email_client.outbox,
+        ) {
+            (Environment::Local, EmailProvider::Outbox, Some(outbox)) => {
+                Some(PathBuf::from(&outbox.directory))
+            }
+            _ => None,
+        };
+        let email_client = configuration.email_client.client();
 
         let address = format!(
             "{}:{}",
@@ -53,9 +71,10 @@ impl Application {
             listener,
             conn_pool,
             email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
+            clock,
+            configuration.application,
             configuration.redis_uri,
+            outbox_directory,
         )
         .await?;
         // allows saving of bound port to Application
@@ -82,16 +101,48 @@ pub struct ApplicationBaseUrl(pub String);
 async fn run(
     listener: TcpListener,
     conn: PgPool,
-    email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
+    email_client: Arc<dyn EmailSender>,
+    clock: Arc<dyn Clock>,
+    // base url, HMAC secret + token TTL
+    application: ApplicationSettings,
     redis_uri: Secret<String>,
+    outbox_directory: Option<PathBuf>,
 ) -> Result<Server, anyhow::Error> {
     // note: new error response (from std::io::Error)
     // context for base url - dependent on env
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url.clone()));
+    let subscription_token_ttl =
+        web::Data::new(SubscriptionTokenTtl(application.subscription_token_ttl()));
+    let password_reset_token_ttl = web::Data::new(PasswordResetTokenTtl(
+        application.password_reset_token_ttl(),
+    ));
+    let invitation_token_ttl =
+        web::Data::new(InvitationTokenTtl(application.invitation_token_ttl()));
+    let audience_timezone = web::Data::new(AudienceTimezone(
+        application
+            .audience_timezone()
+            .map_err(anyhow::Error::msg)?,
+    ));
+    let trusted_proxies = web::Data::new(
+        TrustedProxies::parse(&application.trusted_proxies).map_err(anyhow::Error::msg)?,
+    );
+    let resend_confirmation_throttle = web::Data::new(application.resend_confirmation_throttle);
+    let api_settings = web::Data::new(application.api);
+    // fail at startup rather than on the first password change
+    application
+        .argon2
+        .params()
+        .map_err(|err| anyhow::anyhow!("Invalid Argon2 parameters: {}", err))?;
+    let argon2_settings = web::Data::new(application.argon2);
+    let login_throttle =
+        web::Data::new(LoginThrottle::new(&redis_uri, application.login_throttle).await?);
+    let hmac_secret = application.hmac_secret;
+    // encrypts TOTP secrets at rest - key derived from `hmac_secret`
+    let totp_cipher = web::Data::new(TotpCipher::new(&hmac_secret));
     // context for email client's API
-    let email_client = web::Data::new(email_client);
+    // `Data::from` keeps the trait object - handlers extract `web::Data<dyn EmailSender>`
+    let email_client: web::Data<dyn EmailSender> = web::Data::from(email_client);
+    let clock: web::Data<dyn Clock> = web::Data::from(clock);
     // wrap db connection (non-cloneable TCP connection with Postgres) in smart pointer (ARC) -- pointer to PgConnection
     let db_pool = web::Data::new(conn);
     // for session token and setup of session storage
@@ -101,6 +152,7 @@ async fn run(
     let message_framework = FlashMessagesFramework::builder(message_store).build();
     // instantiate our Redis session store from uri conn string
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let outbox_directory = outbox_directory.map(|dir| web::Data::new(OutboxDirectory(dir)));
     // we have to capture `conn` from outer scope to use in innner scope
     let server = HttpServer::new(move || {
         App::new()
@@ -112,20 +164,113 @@ async fn run(
                 redis_store.clone(),
                 secret_key.clone(),
             )) // session wrapper for entire app
-            .wrap(TracingLogger::default())
+            .wrap(TracingLogger::<AppRootSpanBuilder>::new())
             .route("/", web::get().to(home))
-            .route("/admin/dashboard", web::get().to(admin_dashboard))
+            // everything under `/admin` requires a logged in user
+            .service(
+                web::scope("/admin")
+                    .wrap(RejectAnonymousUsers)
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/email", web::get().to(email_form))
+                    .route("/email", web::post().to(change_email))
+                    .route("/logout", web::post().to(log_out))
+                    .route("/api_tokens", web::get().to(api_tokens_page))
+                    .route("/api_tokens", web::post().to(create_api_token))
+                    .route(
+                        "/api_tokens/{api_token_id}/revoke",
+                        web::post().to(revoke_api_token),
+                    )
+                    .route("/two_factor", web::get().to(two_factor_page))
+                    .route("/two_factor/enroll", web::post().to(enroll_two_factor))
+                    .route("/two_factor/confirm", web::post().to(confirm_two_factor))
+                    .route("/two_factor/disable", web::post().to(disable_two_factor))
+                    // drafts -> preview / test send -> publish, owners and editors only
+                    .route("/newsletters", web::get().to(publish_newsletter_form))
+                    .route("/newsletters", web::post().to(save_newsletter_draft))
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::get().to(newsletter_issue_preview),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/test",
+                        web::post().to(send_test_newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/publish",
+                        web::post().to(publish_newsletter_draft),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/schedule",
+                        web::post().to(schedule_newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/cancel",
+                        web::post().to(cancel_scheduled_newsletter_issue),
+                    )
+                    // owner-only - the handlers check `Permission::ManageUsers`
+                    .route("/users", web::get().to(users_page))
+                    .route("/users", web::post().to(invite_user))
+                    .route("/users/{user_id}/role", web::post().to(change_user_role))
+                    .route(
+                        "/users/{user_id}/deactivate",
+                        web::post().to(deactivate_user),
+                    )
+                    .route(
+                        "/users/{user_id}/reactivate",
+                        web::post().to(reactivate_user),
+                    )
+                    .route(
+                        "/users/{user_id}/resend_invitation",
+                        web::post().to(resend_invitation),
+                    )
+                    // dev-only routes
+                    .configure(|cfg| {
+                        if let Some(outbox_directory) = &outbox_directory {
+                            cfg.app_data(outbox_directory.clone())
+                                .route("/outbox", web::get().to(admin_outbox));
+                        }
+                    }),
+            )
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
+            // second step for users with two-factor authentication enabled
+            .route("/login/2fa", web::get().to(login_two_factor_form))
+            .route("/login/2fa", web::post().to(login_two_factor))
+            .route("/login/forgot", web::get().to(forgot_password_form))
+            .route("/login/forgot", web::post().to(forgot_password))
+            .route("/login/reset", web::get().to(reset_password_form))
+            .route("/login/reset", web::post().to(reset_password))
             .route("/health_check", web::get().to(health_check))
             .route("/newsletters", web::post().to(publish_newsletter))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/resend_confirmation",
+                web::post().to(resend_confirmation),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
             // register db conn as part of app state
             .app_data(db_pool.clone())
             // since EC has two data fields (base_url and sender) along with Client, share (wrapped via Ac) amongst all App instances (one per thread)
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_token_ttl.clone())
+            .app_data(password_reset_token_ttl.clone())
+            .app_data(invitation_token_ttl.clone())
+            .app_data(audience_timezone.clone())
+            .app_data(clock.clone())
+            .app_data(resend_confirmation_throttle.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(api_settings.clone())
+            .app_data(argon2_settings.clone())
+            .app_data(login_throttle.clone())
+            .app_data(totp_cipher.clone())
             // injecting secret used by HMAC's to app state
             .app_data(web::Data::new(HmacSecret(hmac_secret.clone())))
     })
@@ -135,6 +280,21 @@ async fn run(
     Ok(server)
 }
 
+// how long confirmation links stay valid - read by `/subscriptions/confirm`
+pub struct SubscriptionTokenTtl(pub chrono::Duration);
+
+// how long password reset links stay valid - read by `/login/forgot`
+pub struct PasswordResetTokenTtl(pub chrono::Duration);
+
+// how long invitation links stay valid - read by `/admin/users`
+pub struct InvitationTokenTtl(pub chrono::Duration);
+
+// timezone scheduled send times are entered and shown in - read by `/admin/newsletters`
+pub struct AudienceTimezone(pub chrono_tz::Tz);
+
+// wrapper type for the `outbox` email provider's directory - read by `/admin/outbox`
+pub struct OutboxDirectory(pub PathBuf);
+
 // helper - builds connection to pg pool
 pub async fn get_connection_pool(configuration: &DatabaseSettings) -> Result<PgPool, sqlx::Error> {
     PgPoolOptions::new()
@@ -0,0 +1,106 @@
+use crate::configuration::Settings;
+use crate::startup::get_connection_pool;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::time::Duration;
+
+// -- WORKER ENTRYPOINT -- //
+
+// expired confirmation / password reset tokens can never be used again - periodically drop them (and stale throttle counters)
+// so the tables don't grow forever
+pub async fn run_purge_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let conn_pool = get_connection_pool(&configuration.database).await?;
+    purge_loop(
+        conn_pool,
+        configuration.application.subscription_token_ttl(),
+        configuration
+            .application
+            .resend_confirmation_throttle
+            .window(),
+    )
+    .await
+}
+
+async fn purge_loop(
+    db_pool: PgPool,
+    ttl: chrono::Duration,
+    throttle_window: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match purge_stale_subscription_tokens(&db_pool, ttl).await {
+            Ok(purged) => {
+                tracing::info!(purged, "Purged stale subscription tokens");
+            }
+            // transient failure (ie. db connection) - try again next round
+            Err(err) => {
+                tracing::error!(
+                    err.cause_chain = ?err,
+                    err.message = %err,
+                    "Failed to purge stale subscription tokens",
+                );
+            }
+        }
+        if let Err(err) = purge_stale_password_reset_tokens(&db_pool).await {
+            tracing::error!(
+                err.cause_chain = ?err,
+                err.message = %err,
+                "Failed to purge stale password reset tokens",
+            );
+        }
+        if let Err(err) = purge_expired_throttle_windows(&db_pool, throttle_window).await {
+            tracing::error!(
+                err.cause_chain = ?err,
+                err.message = %err,
+                "Failed to purge expired resend throttle windows",
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+    }
+}
+
+// -- PURGE -- //
+
+// returns the number of tokens removed - consumed tokens go once they're past the TTL too,
+// until then they're kept so a second click gets the "link expired" page instead of a bare 401
+#[tracing::instrument(skip(db_pool))]
+pub async fn purge_stale_subscription_tokens(
+    db_pool: &PgPool,
+    ttl: chrono::Duration,
+) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE created_at < $1",
+        Utc::now() - ttl,
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+// expired and consumed reset links are useless - no "link expired" page to keep them around for
+#[tracing::instrument(skip(db_pool))]
+pub async fn purge_stale_password_reset_tokens(db_pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE expires_at < now() OR consumed_at IS NOT NULL"
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+// a counter whose window has elapsed is reset on the next attempt anyway - dropping it changes nothing
+#[tracing::instrument(skip(db_pool))]
+pub async fn purge_expired_throttle_windows(
+    db_pool: &PgPool,
+    window: chrono::Duration,
+) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "DELETE FROM resend_confirmation_throttle WHERE window_started_at < $1",
+        Utc::now() - window,
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
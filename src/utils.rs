@@ -0,0 +1,33 @@
+use actix_web::http::header::LOCATION;
+use actix_web::HttpResponse;
+
+// return opaque `500` for user but preserve err root cause (logging)
+pub fn err500<T>(err: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(err)
+}
+
+// `400` for malformed form submissions that a browser using our own pages wouldn't send
+pub fn err400<T>(err: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorBadRequest(err)
+}
+
+// `403` for logged in users whose role lacks the needed permission
+pub fn err403<T>(err: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorForbidden(err)
+}
+
+// `303` to `location` - used for the redirect after every form `POST` (and for bouncing logged out users)
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
@@ -11,3 +11,109 @@ async fn must_be_logged_in_to_access_admin_dashboard() {
     // Assert
     assert_is_redirect_to(&res, "/login");
 }
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act 1 - Login
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let res = app.post_login(&login_body).await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+
+    // Act 2 - Follow redirect
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    // Act 3 - Logout
+    let res = app.post_logout().await;
+    assert_is_redirect_to(&res, "/login");
+
+    // Act 4 - Follow redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<p><i>You have successfully logged out.</i></p>"#));
+
+    // Act 5 - Attempt to load admin dashboard
+    let res = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn a_logged_out_session_cookie_cannot_be_replayed() {
+    // Arrange
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let res = app.post_login(&login_body).await;
+    let session_cookie = res
+        .cookies()
+        .find(|cookie| cookie.name() == "id")
+        .unwrap()
+        .value()
+        .to_owned();
+
+    // Act - log out, then present the old cookie from a fresh client
+    app.post_logout().await;
+    let res = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(&format!("{}/admin/dashboard", &app.address))
+        .header("Cookie", format!("id={}", session_cookie))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - session was removed from Redis, not just from the browser
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn logging_out_without_a_session_redirects_to_login() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let res = app.post_logout().await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn every_admin_route_requires_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = &app.api_client;
+
+    // Act + Assert - including paths without a handler, the whole scope is protected
+    for (method, path) in [
+        (reqwest::Method::GET, "/admin/dashboard"),
+        (reqwest::Method::GET, "/admin/password"),
+        (reqwest::Method::POST, "/admin/password"),
+        (reqwest::Method::POST, "/admin/logout"),
+        (reqwest::Method::GET, "/admin/api_tokens"),
+        (reqwest::Method::POST, "/admin/api_tokens"),
+        (reqwest::Method::GET, "/admin/does-not-exist"),
+    ] {
+        let res = client
+            .request(method.clone(), format!("{}{}", &app.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(
+            res.status().as_u16(),
+            303,
+            "{} {} did not redirect an anonymous user",
+            method,
+            path
+        );
+        assert_eq!(res.headers().get("Location").unwrap(), "/login");
+    }
+}
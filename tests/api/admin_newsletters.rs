@@ -0,0 +1,544 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, spawn_app_with, TestApp,
+};
+use chrono::{TimeZone, Utc};
+use chrono_tz::Europe::Berlin;
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::clock::Clock;
+
+fn form_body(idempotency_key: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "text_content": "Newsletter body as plain text",
+        "idempotency_key": idempotency_key,
+    })
+}
+
+// `days` from the app's (test) clock, as the `datetime-local` input sends it - with the default `+00:00` audience offset
+fn schedule_body(app: &TestApp, days: i64) -> serde_json::Value {
+    let send_time = app.clock.now() + chrono::Duration::days(days);
+    serde_json::json!({ "scheduled_for": send_time.format("%Y-%m-%dT%H:%M").to_string() })
+}
+
+// saves a draft through the form - returns its id (from the redirect to its preview)
+async fn save_draft(app: &TestApp) -> String {
+    let res = app
+        .post_publish_newsletter(&form_body(&Uuid::new_v4().to_string()))
+        .await;
+    assert_eq!(res.status().as_u16(), 303);
+    res.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .strip_prefix("/admin/newsletters/")
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_newsletter_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let res = app.get_publish_newsletter().await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_save_a_draft() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let res = app
+        .post_publish_newsletter(&form_body(&Uuid::new_v4().to_string()))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn viewers_cannot_use_the_newsletter_pages() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let issue_id = save_draft(&app).await;
+    let viewer = app.add_user("viewer").await;
+    app.login_as(&viewer).await;
+
+    // Act
+    let page = app.get_publish_newsletter().await;
+    let save = app
+        .post_publish_newsletter(&form_body(&Uuid::new_v4().to_string()))
+        .await;
+    let preview = app.get_newsletter_preview(&issue_id).await;
+    let test = app
+        .post_newsletter_action(&issue_id, "test", &serde_json::json!({}))
+        .await;
+    let publish = app
+        .post_newsletter_action(&issue_id, "publish", &serde_json::json!({}))
+        .await;
+    let schedule = app
+        .post_newsletter_action(&issue_id, "schedule", &schedule_body(&app, 1))
+        .await;
+
+    // Assert
+    for (res, action) in [
+        (page, "form"),
+        (save, "save"),
+        (preview, "preview"),
+        (test, "test send"),
+        (publish, "publish"),
+        (schedule, "schedule"),
+    ] {
+        assert_eq!(res.status().as_u16(), 403, "viewer could {}", action);
+    }
+    assert!(!app
+        .get_admin_dashboard_html()
+        .await
+        .contains("/admin/newsletters"));
+}
+
+#[tokio::test]
+async fn the_form_carries_a_fresh_idempotency_key() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let dashboard = app.get_admin_dashboard_html().await;
+    let first = app.get_publish_newsletter_html().await;
+    let second = app.get_publish_newsletter_html().await;
+
+    // Assert
+    assert!(dashboard.contains(r#"<a href="/admin/newsletters">"#));
+    assert!(first.contains(r#"name="idempotency_key""#));
+    assert_ne!(first, second);
+}
+
+#[tokio::test]
+async fn invalid_submissions_are_rejected_with_a_flash_message() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let test_cases = vec![
+        ("title", "   ", "The title can't be empty."),
+        ("html_content", "", "The HTML content can't be empty."),
+        (
+            "text_content",
+            "\n",
+            "The plain text content can't be empty.",
+        ),
+        (
+            "html_content",
+            "<p>Hi {{ nickname }}</p>",
+            "The HTML content is invalid: Unknown placeholder `{{ nickname }}` - use one of `{{ name }}`, `{{ unsubscribe_url }}`, `{{ confirmed_at }}`, or write `{{{{` for a literal `{{`.",
+        ),
+    ];
+
+    for (field, value, message) in test_cases {
+        let mut body = form_body(&Uuid::new_v4().to_string());
+        body[field] = value.into();
+
+        // Act
+        let res = app.post_publish_newsletter(&body).await;
+
+        // Assert
+        assert_is_redirect_to(&res, "/admin/newsletters");
+        let html_page = app.get_publish_newsletter_html().await;
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", message)),
+            "no error for invalid {}",
+            field
+        );
+    }
+    let count = sqlx::query!("SELECT count(*) AS count FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count.count, Some(0));
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected_with_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let res = app.post_publish_newsletter(&form_body("")).await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn saved_drafts_are_previewed_but_not_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+
+    // Act
+    let issue_id = save_draft(&app).await;
+
+    // Assert
+    let preview = app.get_newsletter_preview_html(&issue_id).await;
+    assert!(preview.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(preview.contains("Status: draft"));
+    // the issue's HTML is only ever shown inside the sandboxed frame
+    assert!(preview.contains("&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+    assert!(!preview.contains("<p>Newsletter body as HTML</p>"));
+    assert!(preview.contains("Newsletter body as plain text"));
+    assert!(preview.contains(&format!("/admin/newsletters/{}/publish", issue_id)));
+    let form_page = app.get_publish_newsletter_html().await;
+    assert!(form_page.contains(&format!(r#"<a href="/admin/newsletters/{}">"#, issue_id)));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn previews_fill_placeholders_with_sample_values() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let mut body = form_body(&Uuid::new_v4().to_string());
+    body["title"] = "News for {{ name }}".into();
+    body["text_content"] = "Hi {{ name }}".into();
+    let res = app.post_publish_newsletter(&body).await;
+    let location = res.headers()["Location"].to_str().unwrap();
+    let issue_id = location.strip_prefix("/admin/newsletters/").unwrap();
+
+    // Act
+    let preview = app.get_newsletter_preview_html(issue_id).await;
+
+    // Assert
+    assert!(preview.contains("News for Ada Lovelace"));
+    assert!(preview.contains("Hi Ada Lovelace"));
+    assert!(!preview.contains("{{ name }}"));
+}
+
+#[tokio::test]
+async fn unknown_issues_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let res = app
+        .get_newsletter_preview(&Uuid::new_v4().to_string())
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn test_sends_only_go_to_the_logged_in_admin() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+    app.post_email(&serde_json::json!({
+        "email": "admin@example.com",
+        "current_password": &app.test_user.password,
+    }))
+    .await;
+    let issue_id = save_draft(&app).await;
+
+    // Act
+    let res = app
+        .post_newsletter_action(&issue_id, "test", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, &format!("/admin/newsletters/{}", issue_id));
+    assert!(app
+        .get_newsletter_preview_html(&issue_id)
+        .await
+        .contains("<p><i>A test email has been sent to your account email address.</i></p>"));
+    // sent right away - nothing is queued for subscribers
+    app.dispatch_all_pending_emails().await;
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert_eq!(body["To"], "admin@example.com");
+    assert_eq!(body["Subject"], "[TEST] Newsletter title");
+}
+
+#[tokio::test]
+async fn test_sends_need_an_account_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+    app.post_email(&serde_json::json!({
+        "email": "",
+        "current_password": &app.test_user.password,
+    }))
+    .await;
+    let issue_id = save_draft(&app).await;
+
+    // Act
+    let res = app
+        .post_newsletter_action(&issue_id, "test", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, &format!("/admin/newsletters/{}", issue_id));
+    assert!(app
+        .get_newsletter_preview_html(&issue_id)
+        .await
+        .contains("<p><i>Add an email address to your account to receive test emails.</i></p>"));
+}
+
+#[tokio::test]
+async fn published_drafts_are_delivered_to_confirmed_subscribers_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+    let issue_id = save_draft(&app).await;
+
+    // Act 1 - publish
+    let res = app
+        .post_newsletter_action(&issue_id, "publish", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&res, &format!("/admin/newsletters/{}", issue_id));
+    let preview = app.get_newsletter_preview_html(&issue_id).await;
+    assert!(preview.contains(
+        "<p><i>The newsletter issue has been published - emails will go out shortly.</i></p>"
+    ));
+    assert!(preview.contains("Status: published"));
+    assert!(!preview.contains(&format!("/admin/newsletters/{}/publish", issue_id)));
+
+    // Act 2 - publish again
+    let res = app
+        .post_newsletter_action(&issue_id, "publish", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, &format!("/admin/newsletters/{}", issue_id));
+    assert!(app
+        .get_newsletter_preview_html(&issue_id)
+        .await
+        .contains("<p><i>This issue has already been published.</i></p>"));
+    app.dispatch_all_pending_emails().await;
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert_eq!(body["Subject"], "Newsletter title");
+    // Mock verifies on `Drop` newsletter was sent only once
+}
+
+#[tokio::test]
+async fn resubmitting_the_form_saves_the_draft_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let body = form_body(&Uuid::new_v4().to_string());
+
+    // Act 1 - submit
+    let first = app.post_publish_newsletter(&body).await;
+    app.get_publish_newsletter_html().await;
+
+    // Act 2 - submit again with the same key
+    let second = app.post_publish_newsletter(&body).await;
+
+    // Assert
+    assert_eq!(first.headers()["Location"], second.headers()["Location"]);
+    let location = second.headers()["Location"].to_str().unwrap();
+    let issue_id = location.strip_prefix("/admin/newsletters/").unwrap();
+    assert!(app
+        .get_newsletter_preview_html(issue_id)
+        .await
+        .contains("<p><i>The draft has been saved.</i></p>"));
+    let count = sqlx::query!("SELECT count(*) AS count FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count.count, Some(1));
+}
+
+#[tokio::test]
+async fn scheduled_issues_go_out_once_they_are_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+    let issue_id = save_draft(&app).await;
+
+    // Act 1 - schedule two days ahead
+    let res = app
+        .post_newsletter_action(&issue_id, "schedule", &schedule_body(&app, 2))
+        .await;
+    assert_is_redirect_to(&res, &format!("/admin/newsletters/{}", issue_id));
+    assert!(app
+        .get_newsletter_preview_html(&issue_id)
+        .await
+        .contains("Status: scheduled for"));
+    assert!(app
+        .get_publish_newsletter_html()
+        .await
+        .contains(&format!(r#"<a href="/admin/newsletters/{}">"#, issue_id)));
+
+    // Act 2 - a day later nothing is due yet
+    app.clock.advance(chrono::Duration::days(1));
+    assert_eq!(app.release_due_issues().await, 0);
+    app.dispatch_all_pending_emails().await;
+
+    // Act 3 - two days later it is
+    app.clock.advance(chrono::Duration::days(1));
+    assert_eq!(app.release_due_issues().await, 1);
+    assert_eq!(app.release_due_issues().await, 0);
+
+    // Assert
+    app.dispatch_all_pending_emails().await;
+    assert!(app
+        .get_newsletter_preview_html(&issue_id)
+        .await
+        .contains("Status: published on"));
+    // Mock verifies on `Drop` newsletter was sent only once
+}
+
+#[tokio::test]
+async fn send_times_are_entered_in_the_audience_timezone() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.audience_timezone = "Europe/Berlin".into()).await;
+    app.login_test_user().await;
+    let issue_id = save_draft(&app).await;
+    let day = (app.clock.now() + chrono::Duration::days(2))
+        .with_timezone(&Berlin)
+        .date_naive();
+    // CEST or CET, depending on when the test runs
+    let send_time = Berlin
+        .from_local_datetime(&day.and_hms_opt(9, 0, 0).unwrap())
+        .unwrap();
+
+    // Act
+    let res = app
+        .post_newsletter_action(
+            &issue_id,
+            "schedule",
+            &serde_json::json!({ "scheduled_for": format!("{}T09:00", day) }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, &format!("/admin/newsletters/{}", issue_id));
+    assert!(app
+        .get_newsletter_preview_html(&issue_id)
+        .await
+        .contains(&format!(
+            "<p><i>The issue will go out on {}.</i></p>",
+            send_time.format("%Y-%m-%d %H:%M %Z (UTC%:z)")
+        )));
+    let row = sqlx::query!(
+        "SELECT scheduled_for FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id.parse::<Uuid>().unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(row.scheduled_for.unwrap(), send_time.with_timezone(&Utc));
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled_or_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+    let issue_id = save_draft(&app).await;
+    app.post_newsletter_action(&issue_id, "schedule", &schedule_body(&app, 1))
+        .await;
+
+    // Act 1 - move it a week out
+    let res = app
+        .post_newsletter_action(&issue_id, "schedule", &schedule_body(&app, 7))
+        .await;
+    assert_is_redirect_to(&res, &format!("/admin/newsletters/{}", issue_id));
+    app.clock.advance(chrono::Duration::days(2));
+    assert_eq!(app.release_due_issues().await, 0);
+
+    // Act 2 - cancel
+    let res = app
+        .post_newsletter_action(&issue_id, "cancel", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, &format!("/admin/newsletters/{}", issue_id));
+    let preview = app.get_newsletter_preview_html(&issue_id).await;
+    assert!(preview
+        .contains("<p><i>The schedule has been cancelled - the issue is a draft again.</i></p>"));
+    assert!(preview.contains("Status: draft"));
+    app.clock.advance(chrono::Duration::days(30));
+    assert_eq!(app.release_due_issues().await, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn send_times_in_the_past_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let issue_id = save_draft(&app).await;
+
+    // Act
+    let res = app
+        .post_newsletter_action(&issue_id, "schedule", &schedule_body(&app, -1))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, &format!("/admin/newsletters/{}", issue_id));
+    let preview = app.get_newsletter_preview_html(&issue_id).await;
+    assert!(preview.contains("<p><i>The send time must be in the future.</i></p>"));
+    assert!(preview.contains("Status: draft"));
+}
@@ -0,0 +1,92 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
+use uuid::Uuid;
+use zero2prod::configuration::{EmailProvider, OutboxSettings};
+
+fn outbox_directory() -> String {
+    std::env::temp_dir()
+        .join(format!("zero2prod-outbox-{}", Uuid::new_v4()))
+        .display()
+        .to_string()
+}
+
+#[tokio::test]
+async fn outbox_page_is_not_routed_for_other_providers() {
+    // Arrange - logged in, otherwise the `/admin` scope redirects before routing
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let res = app
+        .api_client
+        .get(&format!("{}/admin/outbox", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn must_be_logged_in_to_access_outbox() {
+    // Arrange
+    let directory = outbox_directory();
+    let app = spawn_app_with(|c| {
+        c.email_client.provider = EmailProvider::Outbox;
+        c.email_client.outbox = Some(OutboxSettings { directory });
+    })
+    .await;
+
+    // Act
+    let res = app
+        .api_client
+        .get(&format!("{}/admin/outbox", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn outbox_lists_sent_confirmation_emails() {
+    // Arrange
+    let directory = outbox_directory();
+    let outbox = directory.clone();
+    let app = spawn_app_with(|c| {
+        c.email_client.provider = EmailProvider::Outbox;
+        c.email_client.outbox = Some(OutboxSettings { directory: outbox });
+    })
+    .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    // the confirmation email is written after the response
+    for _ in 0..50 {
+        if std::fs::read_dir(&directory).map_or(false, |mut entries| entries.next().is_some()) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await;
+
+    // Act
+    let html = app
+        .api_client
+        .get(&format!("{}/admin/outbox", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html.contains("ursula_le_guin@gmail.com"));
+    assert!(html.contains("/subscriptions/confirm?subscription_token="));
+    std::fs::remove_dir_all(directory).unwrap();
+}
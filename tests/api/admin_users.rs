@@ -0,0 +1,504 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::authentication::ApiScope;
+
+// a second cookie jar - for acting as another user while `api_client` stays logged in
+fn other_browser() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap()
+}
+
+async fn publish_as(app: &TestApp, user: &TestUser) -> reqwest::Response {
+    app.api_client
+        .post(&format!("{}/newsletters", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&newsletter_body())
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn non_owners_cannot_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for role in ["editor", "viewer"] {
+        let user = app.add_user(role).await;
+        app.login_as(&user).await;
+
+        // Act
+        let page = app.get_users().await;
+        let invite = app
+            .post_invite_user(&serde_json::json!({
+                "username": Uuid::new_v4().to_string(),
+                "email": "ursula@example.com",
+                "role": "owner",
+            }))
+            .await;
+        let deactivate = app
+            .post_user_action(app.test_user.user_id, "deactivate", &serde_json::json!({}))
+            .await;
+
+        // Assert
+        assert_eq!(page.status().as_u16(), 403, "{} saw the users page", role);
+        assert_eq!(invite.status().as_u16(), 403, "{} invited a user", role);
+        assert_eq!(deactivate.status().as_u16(), 403, "{} deactivated", role);
+        let dashboard = app.get_admin_dashboard_html().await;
+        assert!(dashboard.contains(&format!("Your role: {}", role)));
+        assert!(!dashboard.contains("/admin/users"));
+    }
+    let count = sqlx::query!("SELECT count(*) AS count FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count.count, Some(3));
+}
+
+#[tokio::test]
+async fn owners_see_every_user_on_the_users_page() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user("editor").await;
+    app.login_test_user().await;
+
+    // Act
+    let dashboard = app.get_admin_dashboard_html().await;
+    let html_page = app.get_users_html().await;
+
+    // Assert
+    assert!(dashboard.contains(r#"<a href="/admin/users">Manage users</a>"#));
+    assert!(html_page.contains(&app.test_user.username));
+    assert!(html_page.contains(&editor.username));
+    assert!(html_page.contains(&format!("/admin/users/{}/deactivate", editor.user_id)));
+    // no actions on the owner's own row
+    assert!(!html_page.contains(&format!(
+        "/admin/users/{}/deactivate",
+        app.test_user.user_id
+    )));
+}
+
+#[tokio::test]
+async fn invited_users_pick_their_password_through_the_emailed_link() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+    let username = Uuid::new_v4().to_string();
+
+    // Act 1 - invite
+    let res = app
+        .post_invite_user(&serde_json::json!({
+            "username": &username,
+            "email": "ursula@example.com",
+            "role": "viewer",
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/users");
+    assert!(app
+        .get_users_html()
+        .await
+        .contains("<p><i>The invitation has been sent.</i></p>"));
+
+    // Act 2 - the invitee follows the link
+    let email_req = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_confirmation_links(email_req).html;
+    assert_eq!(link.path(), "/login/reset");
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    let password = Uuid::new_v4().to_string();
+    app.post_logout().await;
+    let res = app
+        .post_reset_password(&serde_json::json!({
+            "token": token,
+            "new_password": &password,
+            "new_password_check": &password,
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/login");
+
+    // Assert
+    let res = app
+        .post_login(&serde_json::json!({ "username": &username, "password": &password }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+    assert!(app
+        .get_admin_dashboard_html()
+        .await
+        .contains("Your role: viewer"));
+}
+
+#[tokio::test]
+async fn a_failed_invitation_email_keeps_the_user_and_can_be_resent() {
+    // Arrange - the email provider is down (retries included)
+    let app = spawn_app().await;
+    let outage = Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.login_test_user().await;
+    let username = Uuid::new_v4().to_string();
+
+    // Act 1 - invite
+    let res = app
+        .post_invite_user(&serde_json::json!({
+            "username": &username,
+            "email": "ursula@example.com",
+            "role": "viewer",
+        }))
+        .await;
+
+    // Assert 1 - the user exists, the admin is told the email didn't go out
+    assert_is_redirect_to(&res, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(
+        html_page.contains("The user has been created, but the invitation email could not be sent")
+    );
+    let user_id = sqlx::query!("SELECT user_id FROM users WHERE username = $1", &username)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .user_id;
+    assert!(html_page.contains(&format!("/admin/users/{}/resend_invitation", user_id)));
+
+    // Act 2 - back up, resend
+    drop(outage);
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let res = app
+        .post_user_action(user_id, "resend_invitation", &serde_json::json!({}))
+        .await;
+
+    // Assert 2 - a working link this time
+    assert_is_redirect_to(&res, "/admin/users");
+    assert!(app
+        .get_users_html()
+        .await
+        .contains("<p><i>The invitation has been sent again.</i></p>"));
+    let email_reqs = app.email_server.received_requests().await.unwrap();
+    let link = app.get_confirmation_links(email_reqs.last().unwrap()).html;
+    let res = app.api_client.get(link).send().await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn invitations_cannot_be_resent_once_a_password_is_set() {
+    // Arrange - another owner with a password and an email address
+    let app = spawn_app().await;
+    let owner = app.add_user("owner").await;
+    sqlx::query!(
+        "UPDATE users SET email = 'owner@example.com' WHERE user_id = $1",
+        owner.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+
+    // Act 1
+    let res = app
+        .post_user_action(owner.user_id, "resend_invitation", &serde_json::json!({}))
+        .await;
+
+    // Assert 1 - no email, no reset link
+    assert_is_redirect_to(&res, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>Only pending invitations can be resent"));
+    assert!(!html_page.contains(&format!("/admin/users/{}/resend_invitation", owner.user_id)));
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    let tokens = sqlx::query!(
+        "SELECT count(*) AS count FROM password_reset_tokens WHERE user_id = $1",
+        owner.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tokens.count, Some(0));
+
+    // Act 2 - an invitee who has used their link
+    let username = Uuid::new_v4().to_string();
+    app.post_invite_user(&serde_json::json!({
+        "username": &username,
+        "email": "ursula@example.com",
+        "role": "viewer",
+    }))
+    .await;
+    let email_reqs = app.email_server.received_requests().await.unwrap();
+    let link = app.get_confirmation_links(email_reqs.last().unwrap()).html;
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    let password = Uuid::new_v4().to_string();
+    let res = other_browser()
+        .post(&format!("{}/login/reset", &app.address))
+        .form(&serde_json::json!({
+            "token": token,
+            "new_password": &password,
+            "new_password_check": &password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&res, "/login");
+    let user_id = sqlx::query!("SELECT user_id FROM users WHERE username = $1", &username)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .user_id;
+    let res = app
+        .post_user_action(user_id, "resend_invitation", &serde_json::json!({}))
+        .await;
+
+    // Assert 2
+    assert_is_redirect_to(&res, "/admin/users");
+    assert!(app
+        .get_users_html()
+        .await
+        .contains("<p><i>Only pending invitations can be resent"));
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn invitations_for_taken_usernames_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+
+    // Act
+    let res = app
+        .post_invite_user(&serde_json::json!({
+            "username": &app.test_user.username,
+            "email": "ursula@example.com",
+            "role": "editor",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/admin/users");
+    assert!(app
+        .get_users_html()
+        .await
+        .contains("<p><i>That username is already taken.</i></p>"));
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = app.add_user("viewer").await;
+    let editor = app.add_user("editor").await;
+    // issued directly - the admin page wouldn't offer the scope to a viewer
+    let (_, viewer_token) = zero2prod::authentication::create_api_token(
+        &app.db_pool,
+        viewer.user_id,
+        "test token",
+        &[ApiScope::NewslettersPublish],
+        None,
+    )
+    .await
+    .unwrap();
+
+    // Act
+    let basic = publish_as(&app, &viewer).await;
+    let bearer = app
+        .post_newsletters_with_token(newsletter_body(), viewer_token.expose_secret())
+        .await;
+    let editor_res = publish_as(&app, &editor).await;
+
+    // Assert
+    assert_eq!(basic.status().as_u16(), 403);
+    assert_eq!(bearer.status().as_u16(), 403);
+    assert_eq!(editor_res.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn role_changes_apply_right_away() {
+    // Arrange
+    let app = spawn_app().await;
+    let user = app.add_user("viewer").await;
+    assert_eq!(publish_as(&app, &user).await.status().as_u16(), 403);
+    app.login_test_user().await;
+
+    // Act
+    let res = app
+        .post_user_action(
+            user.user_id,
+            "role",
+            &serde_json::json!({ "role": "editor" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/admin/users");
+    assert!(app
+        .get_users_html()
+        .await
+        .contains("<p><i>The role has been changed.</i></p>"));
+    assert_eq!(publish_as(&app, &user).await.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn deactivated_users_are_locked_out_until_reactivated() {
+    // Arrange - the editor is logged in from another browser and has an API token
+    let app = spawn_app().await;
+    let editor = app.add_user("editor").await;
+    let token = app.create_api_token(&[ApiScope::NewslettersPublish]).await;
+    sqlx::query!("UPDATE api_tokens SET user_id = $1", editor.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let browser = other_browser();
+    browser
+        .post(&format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &editor.username,
+            "password": &editor.password
+        }))
+        .send()
+        .await
+        .unwrap();
+    let dashboard_url = format!("{}/admin/dashboard", &app.address);
+    let res = browser.get(&dashboard_url).send().await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    app.login_test_user().await;
+
+    // Act 1 - deactivate
+    let res = app
+        .post_user_action(editor.user_id, "deactivate", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&res, "/admin/users");
+
+    // Assert 1 - session, password and token all stop working
+    let res = browser.get(&dashboard_url).send().await.unwrap();
+    assert_is_redirect_to(&res, "/login");
+    assert_eq!(publish_as(&app, &editor).await.status().as_u16(), 401);
+    let res = app
+        .post_newsletters_with_token(newsletter_body(), &token)
+        .await;
+    assert_eq!(res.status().as_u16(), 401);
+    assert!(app.get_users_html().await.contains("deactivated"));
+
+    // Act 2 - reactivate
+    let res = app
+        .post_user_action(editor.user_id, "reactivate", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&res, "/admin/users");
+
+    // Assert 2
+    assert_eq!(publish_as(&app, &editor).await.status().as_u16(), 202);
+    let res = app
+        .post_newsletters_with_token(newsletter_body(), &token)
+        .await;
+    assert_eq!(res.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn owners_cannot_demote_or_deactivate_themselves() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act 1 - demote
+    let res = app
+        .post_user_action(
+            app.test_user.user_id,
+            "role",
+            &serde_json::json!({ "role": "viewer" }),
+        )
+        .await;
+
+    // Assert 1
+    assert_is_redirect_to(&res, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>You can't change your own role.</i></p>"));
+
+    // Act 2 - deactivate
+    let res = app
+        .post_user_action(app.test_user.user_id, "deactivate", &serde_json::json!({}))
+        .await;
+
+    // Assert 2
+    assert_is_redirect_to(&res, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>You can't deactivate your own account.</i></p>"));
+    let row = sqlx::query!(
+        "SELECT role, deactivated_at FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(row.role, "owner");
+    assert!(row.deactivated_at.is_none());
+}
+
+#[tokio::test]
+async fn api_tokens_only_get_scopes_the_role_allows() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = app.add_user("viewer").await;
+    app.login_as(&viewer).await;
+
+    // Act
+    let html_page = app.get_api_tokens_html().await;
+    let res = app
+        .post_api_tokens(&serde_json::json!({
+            "name": "CI",
+            "scopes": "newsletters:publish",
+        }))
+        .await;
+
+    // Assert
+    assert!(html_page.contains(r#"value="subscribers:read""#));
+    assert!(!html_page.contains(r#"value="newsletters:publish""#));
+    assert_is_redirect_to(&res, "/admin/api_tokens");
+    assert!(app
+        .get_api_tokens_html()
+        .await
+        .contains("<p><i>Your role doesn't allow the newsletters:publish scope.</i></p>"));
+}
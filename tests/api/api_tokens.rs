@@ -0,0 +1,185 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use zero2prod::authentication::ApiScope;
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    })
+}
+
+fn www_authenticate(res: &reqwest::Response) -> Vec<&str> {
+    res.headers()
+        .get_all("WWW-Authenticate")
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .collect()
+}
+
+// creates a token through the admin page and scrapes it out of the one-time response
+async fn create_token_via_admin_page(app: &TestApp, name: &str) -> String {
+    let res = app
+        .post_api_tokens(&[("name", name), ("scopes", "newsletters:publish")])
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let html = res.text().await.unwrap();
+    let start = html.find(r#"<code id="api-token">"#).unwrap() + r#"<code id="api-token">"#.len();
+    let end = start + html[start..].find("</code>").unwrap();
+    html[start..end].to_owned()
+}
+
+#[tokio::test]
+async fn newsletters_can_be_published_with_an_api_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.create_api_token(&[ApiScope::NewslettersPublish]).await;
+
+    // Act
+    let res = app
+        .post_newsletters_with_token(newsletter_body(), &token)
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 202);
+    let last_used_at = sqlx::query!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .last_used_at;
+    assert!(last_used_at.is_some());
+}
+
+#[tokio::test]
+async fn unknown_api_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let res = app
+        .post_newsletters_with_token(newsletter_body(), "z2p_not-a-real-token")
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 401);
+    assert_eq!(
+        www_authenticate(&res),
+        vec![r#"Basic realm="publish""#, r#"Bearer realm="publish""#]
+    );
+}
+
+#[tokio::test]
+async fn api_tokens_without_the_publish_scope_are_forbidden() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.create_api_token(&[ApiScope::SubscribersRead]).await;
+
+    // Act
+    let res = app
+        .post_newsletters_with_token(newsletter_body(), &token)
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn expired_api_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.create_api_token(&[ApiScope::NewslettersPublish]).await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let res = app
+        .post_newsletters_with_token(newsletter_body(), &token)
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn api_tokens_can_be_created_and_revoked_from_the_admin_area() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act 1 - create, the token works right away
+    let token = create_token_via_admin_page(&app, "CI pipeline").await;
+    let res = app
+        .post_newsletters_with_token(newsletter_body(), &token)
+        .await;
+    assert_eq!(res.status().as_u16(), 202);
+
+    // Act 2 - the listing shows the token's prefix, never the token itself
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("CI pipeline"));
+    assert!(html_page.contains(&token[..12]));
+    assert!(!html_page.contains(&token));
+
+    // Act 3 - revoke
+    let api_token_id = sqlx::query!("SELECT api_token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .api_token_id;
+    let res = app
+        .api_client
+        .post(&format!(
+            "{}/admin/api_tokens/{}/revoke",
+            &app.address, api_token_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_is_redirect_to(&res, "/admin/api_tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>The API token has been revoked.</i></p>"));
+
+    // Act 4 - the revoked token no longer authenticates
+    let res = app
+        .post_newsletters_with_token(newsletter_body(), &token)
+        .await;
+    assert_eq!(res.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn creating_an_api_token_requires_a_scope() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let res = app.post_api_tokens(&[("name", "No scopes")]).await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/admin/api_tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>Select at least one scope.</i></p>"));
+}
+
+#[tokio::test]
+async fn basic_auth_is_rejected_when_disabled() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.api.allow_basic_auth = false).await;
+    let token = app.create_api_token(&[ApiScope::NewslettersPublish]).await;
+
+    // Act 1 - valid username/password
+    let res = app.post_newsletters(newsletter_body()).await;
+
+    // Assert 1 - only `Bearer` is advertised
+    assert_eq!(res.status().as_u16(), 401);
+    assert_eq!(www_authenticate(&res), vec![r#"Bearer realm="publish""#]);
+
+    // Act 2 - API tokens keep working
+    let res = app
+        .post_newsletters_with_token(newsletter_body(), &token)
+        .await;
+    assert_eq!(res.status().as_u16(), 202);
+}
@@ -0,0 +1,201 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn must_be_logged_in_to_see_change_password_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let res = app.get_change_password().await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn must_be_logged_in_to_change_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let res = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn new_password_fields_must_match() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act 1 - submit mismatched passwords
+    let res = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": Uuid::new_v4().to_string(),
+            "new_password_check": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/password");
+
+    // Act 2 - follow redirect
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains(
+        "<p><i>You entered two different new passwords - the field values must match.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn current_password_must_be_valid() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act 1 - submit a wrong current password
+    let res = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/password");
+
+    // Act 2 - follow redirect
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+}
+
+#[tokio::test]
+async fn current_password_guesses_are_throttled_like_logins() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application.login_throttle.max_failures_per_username = 3;
+        c.application.login_throttle.base_delay_milliseconds = 0;
+    })
+    .await;
+    app.login_test_user().await;
+    let new_password = Uuid::new_v4().to_string();
+    for _ in 0..3 {
+        app.post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    }
+
+    // Act - the right current password is rejected while locked out
+    let res = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+}
+
+#[tokio::test]
+async fn new_password_must_satisfy_length_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let test_cases = vec![
+        (
+            "a".repeat(11),
+            "The new password must be at least 12 characters long.",
+        ),
+        (
+            "a".repeat(129),
+            "The new password must be at most 128 characters long.",
+        ),
+    ];
+
+    for (new_password, error_message) in test_cases {
+        // Act 1 - submit a password outside the policy
+        let res = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &new_password,
+            }))
+            .await;
+        assert_is_redirect_to(&res, "/admin/password");
+
+        // Act 2 - follow redirect
+        let html_page = app.get_change_password_html().await;
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", error_message)),
+            "The page did not warn about a {} character password.",
+            new_password.len()
+        );
+    }
+}
+
+#[tokio::test]
+async fn changing_password_works() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act 1 - Login
+    let res = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+
+    // Act 2 - Change password
+    let res = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/password");
+
+    // Act 3 - Follow redirect
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+
+    // Act 4 - Logout
+    let res = app.post_logout().await;
+    assert_is_redirect_to(&res, "/login");
+
+    // Act 5 - the old password no longer works
+    let res = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/login");
+
+    // Act 6 - Login with the new password
+    let res = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+}
@@ -0,0 +1,137 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+use claims::{assert_err, assert_ok};
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::cli::{check_config, create_admin, run_migrations, send_test_email};
+
+#[tokio::test]
+async fn migrate_is_a_no_op_on_an_up_to_date_database() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let outcome = run_migrations(&app.db_pool).await;
+
+    // Assert
+    assert_ok!(outcome);
+}
+
+#[tokio::test]
+async fn create_admin_creates_an_owner_who_can_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    // Act
+    let user_id = create_admin(&app.configuration, &username, Secret::new(password.clone()))
+        .await
+        .unwrap();
+
+    // Assert
+    let row = sqlx::query!("SELECT role FROM users WHERE user_id = $1", user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(row.role, "owner");
+    let res = app
+        .post_login(&serde_json::json!({ "username": &username, "password": &password }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn create_admin_rejects_taken_usernames_and_weak_passwords() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            app.test_user.username.clone(),
+            Uuid::new_v4().to_string(),
+            "taken username",
+        ),
+        (
+            Uuid::new_v4().to_string(),
+            "short".to_string(),
+            "too short password",
+        ),
+        (
+            "  ".to_string(),
+            Uuid::new_v4().to_string(),
+            "blank username",
+        ),
+    ];
+
+    for (username, password, description) in test_cases {
+        // Act
+        let outcome = create_admin(&app.configuration, &username, Secret::new(password)).await;
+
+        // Assert
+        assert_err!(outcome, "{} was accepted", description);
+    }
+    let count = sqlx::query!("SELECT count(*) AS count FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count.count, Some(1));
+}
+
+#[tokio::test]
+async fn send_test_email_goes_through_the_configured_provider() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let outcome = send_test_email(&app.configuration, "ops@example.com").await;
+
+    // Assert
+    assert_ok!(outcome);
+    assert_err!(send_test_email(&app.configuration, "not-an-email").await);
+}
+
+#[tokio::test]
+async fn check_config_passes_for_a_working_setup() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let checks = check_config(&app.configuration).await;
+
+    // Assert
+    for check in checks {
+        assert!(
+            check.outcome.is_ok(),
+            "{} failed: {:?}",
+            check.name,
+            check.outcome
+        );
+    }
+}
+
+#[tokio::test]
+async fn check_config_reports_every_failing_check() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut configuration = app.configuration.clone();
+    configuration.redis_uri = Secret::new("redis://127.0.0.1:1".to_string());
+    configuration.application.argon2.memory_kib = 0;
+
+    // Act
+    let checks = check_config(&configuration).await;
+
+    // Assert
+    let failed: Vec<_> = checks
+        .iter()
+        .filter(|check| check.outcome.is_err())
+        .map(|check| check.name)
+        .collect();
+    assert_eq!(failed, vec!["redis", "argon2"]);
+}
@@ -1,10 +1,22 @@
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use chrono::{DateTime, Utc};
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::{Arc, Mutex};
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::authentication::{create_api_token, ApiScope, TotpSecret};
+use zero2prod::clock::Clock;
+use zero2prod::configuration::{get_configuration, DatabaseSettings, EmailProvider, Settings};
+use zero2prod::email_client::EmailSender;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::issue_scheduler::try_release_due_issues;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -35,9 +47,54 @@ pub struct TestApp {
     // for config of `ClientBuilder::cookie_store` re: reqwest -
     // this stored 'client' allows cookie propagation throughout various tests
     pub api_client: reqwest::Client,
+    // worker isn't spawned in tests - queue is drained on demand via `dispatch_all_pending_emails`
+    pub email_client: Arc<dyn EmailSender>,
+    // worker needs these to sign the unsubscribe links it adds to each email
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    // the app's (randomized) settings - for calling library entry points (ie. `cli::*`) against the same db
+    pub configuration: Settings,
+    // shared with the app - move time forward instead of sleeping
+    pub clock: Arc<TestClock>,
 }
 
 impl TestApp {
+    // run the delivery worker's task loop until the queue is empty
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    // confirmation / password reset emails go out after the response - poll until `count` have been received
+    pub async fn wait_for_emails(&self, count: usize) -> Vec<wiremock::Request> {
+        for _ in 0..50 {
+            let email_reqs = self.email_server.received_requests().await.unwrap();
+            if email_reqs.len() >= count {
+                return email_reqs;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("Expected {} email(s) to be sent", count);
+    }
+
+    // one scheduler pass at `clock`'s current time - returns the number of issues released
+    pub async fn release_due_issues(&self) -> usize {
+        try_release_due_issues(&self.db_pool, self.clock.as_ref())
+            .await
+            .unwrap()
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
             .post(&format!("{}/subscriptions", &self.address))
@@ -48,6 +105,18 @@ impl TestApp {
             .expect("Failed to execute POST request")
     }
 
+    pub async fn post_resend_confirmation(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/subscriptions/resend_confirmation",
+                &self.address
+            ))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute POST request")
+    }
+
     // method in order to get access to app port - needed to inject into links
     pub fn get_confirmation_links(&self, email_req: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
@@ -66,12 +135,35 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
 
+    // newsletter emails carry the subscriber's unsubscribe link in both bodies + the `List-Unsubscribe` header
+    pub fn get_unsubscribe_link(&self, email_req: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|header| header["Name"] == "List-Unsubscribe")
+            .expect("Missing `List-Unsubscribe` header");
+        let raw_link = header["Value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        assert!(body["HtmlBody"].as_str().unwrap().contains(raw_link));
+        assert!(body["TextBody"].as_str().unwrap().contains(raw_link));
+
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
     // for firing `POST` to `/newsletters`
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         self.api_client
@@ -84,6 +176,74 @@ impl TestApp {
             .expect("Failed to execute POST request")
     }
 
+    // same as `post_newsletters` but tagged with an `Idempotency-Key` header
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute POST request")
+    }
+
+    // same as `post_newsletters` but authenticated with an API token
+    pub async fn post_newsletters_with_token(
+        &self,
+        body: serde_json::Value,
+        token: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/newsletters", &self.address))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute POST request")
+    }
+
+    // issues a token for `test_user` directly (skips the admin pages)
+    pub async fn create_api_token(&self, scopes: &[ApiScope]) -> String {
+        let (_, token) = create_api_token(
+            &self.db_pool,
+            self.test_user.user_id,
+            "test token",
+            scopes,
+            None,
+        )
+        .await
+        .expect("Failed to create API token");
+        token.expose_secret().to_owned()
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(&format!("{}/admin/api_tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_api_tokens<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/api_tokens", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     // for firing `POST` to `/login`
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
@@ -135,6 +295,276 @@ impl TestApp {
     pub async fn get_admin_dashboard_html(&self) -> String {
         self.get_admin_dashboard().await.text().await.unwrap()
     }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_forgot_password(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/login/forgot", &self.address))
+            .form(&serde_json::json!({ "username": username }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/login/reset", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_email<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/email", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_login_two_factor(&self) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/login/2fa", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_login_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/login/2fa", &self.address))
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_two_factor_html(&self) -> String {
+        self.api_client
+            .get(&format!("{}/admin/two_factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    // `action` is one of `enroll`, `confirm`, `disable`
+    pub async fn post_two_factor<Body>(&self, action: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/two_factor/{}", &self.address, action))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // enrolls `test_user` through the admin pages (leaves them logged in)
+    // -> returns the TOTP secret and the recovery codes scraped from the one-time pages
+    // the code for the current time step is used up by the confirmation - log in with `code_at(now + 30s)`
+    pub async fn enable_two_factor(&self) -> (TotpSecret, Vec<String>) {
+        self.login_test_user().await;
+        let html = self
+            .post_two_factor("enroll", &serde_json::json!({}))
+            .await
+            .text()
+            .await
+            .unwrap();
+        let secret =
+            TotpSecret::from_base32(&scrape(&html, r#"<code id="totp-secret">"#, "</code>"))
+                .expect("Enrollment page did not contain a base32 secret");
+
+        let res = self
+            .post_two_factor(
+                "confirm",
+                &serde_json::json!({ "code": secret.code_at(Utc::now()) }),
+            )
+            .await;
+        assert_eq!(res.status().as_u16(), 200);
+        let html = res.text().await.unwrap();
+        let recovery_codes = scrape(&html, r#"<ul id="recovery-codes">"#, "</ul>")
+            .split("<code>")
+            .skip(1)
+            .map(|item| item[..item.find("</code>").unwrap()].to_owned())
+            .collect();
+
+        (secret, recovery_codes)
+    }
+
+    // logs in as `test_user` - most admin tests only care about being past the session check
+    pub async fn login_test_user(&self) {
+        self.login_as(&self.test_user).await;
+    }
+
+    pub async fn login_as(&self, user: &TestUser) {
+        self.post_login(&serde_json::json!({
+            "username": &user.username,
+            "password": &user.password
+        }))
+        .await;
+    }
+
+    // another admin next to `test_user` (who is an owner) - `role` is `owner`, `editor` or `viewer`
+    pub async fn add_user(&self, role: &str) -> TestUser {
+        let user = TestUser {
+            role: role.into(),
+            ..TestUser::generate()
+        };
+        user.store(&self.db_pool).await;
+        user
+    }
+
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/admin/newsletters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_publish_newsletter_html(&self) -> String {
+        self.get_publish_newsletter().await.text().await.unwrap()
+    }
+
+    pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/newsletters", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_newsletter_preview(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(&format!(
+                "{}/admin/newsletters/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_newsletter_preview_html(&self, newsletter_issue_id: &str) -> String {
+        self.get_newsletter_preview(newsletter_issue_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    // `action` is one of `test`, `publish`, `schedule`, `cancel`
+    pub async fn post_newsletter_action<Body>(
+        &self,
+        newsletter_issue_id: &str,
+        action: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!(
+                "{}/admin/newsletters/{}/{}",
+                &self.address, newsletter_issue_id, action
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_users_html(&self) -> String {
+        self.get_users().await.text().await.unwrap()
+    }
+
+    pub async fn get_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_invite_user<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/users", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // `action` is one of `role`, `deactivate`, `reactivate`
+    pub async fn post_user_action<Body>(
+        &self,
+        user_id: Uuid,
+        action: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!(
+                "{}/admin/users/{}/{}",
+                &self.address, user_id, action
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
 }
 
 // -- TEST USER LOGIC -- //
@@ -143,6 +573,7 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: String,
 }
 
 impl TestUser {
@@ -151,12 +582,13 @@ impl TestUser {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role: "owner".into(),
         }
     }
 
     async fn store(&self, db_pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
-        // match params of default password
+        // cheaper than the configured target (m=19456) - logging in as the test user upgrades the hash
         let password_hash = Argon2::new(
             Algorithm::Argon2id,
             Version::V0x13,
@@ -167,10 +599,11 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, role) VALUES ($1, $2, $3, $4)",
             self.user_id,
             self.username,
             password_hash,
+            self.role,
         )
         .execute(db_pool)
         .await
@@ -178,8 +611,35 @@ impl TestUser {
     }
 }
 
+// starts at the real time, only moves when told to
+pub struct TestClock(Mutex<DateTime<Utc>>);
+
+impl TestClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self(Mutex::new(now))
+    }
+
+    pub fn advance(&self, duration: chrono::Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
 // this helper creates an app process and additionally returns our needed port-bound app address and db pool's connection
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+// same as `spawn_app`, but lets a test tweak the (already randomized) config before the app is built
+pub async fn spawn_app_with<F>(customize: F) -> TestApp
+where
+    F: FnOnce(&mut Settings),
+{
     // setup tracing: first time `init` invoked `TRACING` is executed - all others will skip
     Lazy::force(&TRACING);
 
@@ -193,8 +653,12 @@ pub async fn spawn_app() -> TestApp {
         c.database.database_name = Uuid::new_v4().to_string();
         // randomize OS port
         c.application.port = 0;
-        // use mock server as email API
+        // use mock server as email API - `local.yaml` defaults to the outbox provider
+        c.email_client.provider = EmailProvider::ElasticEmail;
         c.email_client.base_url = email_server.uri();
+        // every test shares one Redis (and 127.0.0.1) - namespace the failed login counters per app
+        c.application.login_throttle.key_prefix = format!("login_throttle:{}", Uuid::new_v4());
+        customize(&mut c);
         c
     };
 
@@ -202,7 +666,8 @@ pub async fn spawn_app() -> TestApp {
     configure_database(&configuration.database).await;
 
     // launch application as background task
-    let application = Application::build(configuration.clone())
+    let clock = Arc::new(TestClock::new(Utc::now()));
+    let application = Application::build(configuration.clone(), clock.clone())
         .await
         .expect("Failed to build application for testing");
     // need to extract port value after building test app
@@ -210,6 +675,7 @@ pub async fn spawn_app() -> TestApp {
     let address = format!("http://127.0.0.1:{}", app_port);
 
     // run server
+    #[allow(clippy::let_underscore_future)]
     let _ = tokio::spawn(application.run_until_stopped());
 
     // create reqwest::Client for `api_client` and cookie propagation in tests
@@ -229,6 +695,11 @@ pub async fn spawn_app() -> TestApp {
         port: app_port,
         test_user: TestUser::generate(),
         api_client,
+        email_client: configuration.email_client.clone().client(),
+        configuration: configuration.clone(),
+        base_url: configuration.application.base_url,
+        hmac_secret: configuration.application.hmac_secret,
+        clock,
     };
 
     // create test user + credentials
@@ -260,6 +731,13 @@ async fn configure_database(configuration: &DatabaseSettings) -> PgPool {
 }
 
 // this helper checks the value of `Location` header (re: auth) for redirect responses
+// text between `start` and the next `end` - for values only rendered once in a page
+pub fn scrape(html: &str, start: &str, end: &str) -> String {
+    let from = html.find(start).expect("Start marker not found") + start.len();
+    let to = from + html[from..].find(end).expect("End marker not found");
+    html[from..to].to_owned()
+}
+
 pub fn assert_is_redirect_to(res: &reqwest::Response, location: &str) {
     assert_eq!(res.status().as_u16(), 303);
     assert_eq!(res.headers().get("Location").unwrap(), location);
@@ -270,3 +748,51 @@ pub struct ConfirmationLinks {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
 }
+
+// -- SUBSCRIBER HELPERS -- //
+
+// uses public API of app (under test) to create unconfirmed sub
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    // randomized so tests can create more than one subscriber (`email` is UNIQUE)
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = format!(
+        "name={}&email={}",
+        urlencoding::encode(&name),
+        urlencoding::encode(&email)
+    );
+
+    let _mock_guard = Mock::given(method("POST"))
+        .and(path("/emails/transactional"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        // returns `MockGuard` guard obj
+        // when `Drop`'d, `wiremock` tells `MockServer` to stop honoring specific mock behavior -> keeps mock behavior needed for test helper to `stay local`
+        .mount_as_scoped(&app.email_server)
+        // note: when `MockGuard` dropped, EAGERLY check expectations on the scope
+        .await;
+
+    // create unconfirmed subscriber in database
+    let sent_before = app.email_server.received_requests().await.unwrap().len();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // inspect req's received by Mock Elastic Email server - retrieve confirmation link
+    // (sent after the response - wait for it before the scoped mock is dropped)
+    let email_req = &app.wait_for_emails(sent_before + 1).await.pop().unwrap();
+
+    app.get_confirmation_links(email_req)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    // re use of above helper with extra step to call confirmation link
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
@@ -1,4 +1,30 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use std::time::Duration;
+use uuid::Uuid;
+use zero2prod::configuration::{Argon2Settings, Settings};
+
+// low thresholds, no delays - keeps the lockout tests fast
+fn strict_login_throttle(c: &mut Settings) {
+    c.application.login_throttle.max_failures_per_username = 3;
+    c.application.login_throttle.max_failures_per_ip = 5;
+    c.application.login_throttle.base_delay_milliseconds = 0;
+}
+
+async fn post_login_with_wrong_password(app: &TestApp, username: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": username,
+        "password": Uuid::new_v4().to_string()
+    }))
+    .await
+}
+
+async fn post_valid_login(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await
+}
 
 #[tokio::test]
 // `flash messages` - one time notifications (re: error msgs)
@@ -51,3 +77,236 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+#[tokio::test]
+async fn username_is_locked_out_after_too_many_failures() {
+    // Arrange
+    let app = spawn_app_with(strict_login_throttle).await;
+    for _ in 0..3 {
+        post_login_with_wrong_password(&app, &app.test_user.username).await;
+    }
+
+    // Act - the right password no longer works
+    let res = post_valid_login(&app).await;
+
+    // Assert - same generic error as a wrong password
+    assert_is_redirect_to(&res, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication Failed</i></p>"));
+}
+
+#[tokio::test]
+async fn ip_is_locked_out_after_too_many_failures() {
+    // Arrange - spread over different usernames so only the per-IP counter reaches its threshold
+    let app = spawn_app_with(strict_login_throttle).await;
+    for _ in 0..5 {
+        post_login_with_wrong_password(&app, &Uuid::new_v4().to_string()).await;
+    }
+
+    // Act
+    let res = post_valid_login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn successful_login_resets_the_username_counter() {
+    // Arrange
+    let app = spawn_app_with(strict_login_throttle).await;
+    for _ in 0..2 {
+        post_login_with_wrong_password(&app, &app.test_user.username).await;
+    }
+    assert_is_redirect_to(&post_valid_login(&app).await, "/admin/dashboard");
+
+    // Act - two more failures stay below the threshold again
+    for _ in 0..2 {
+        post_login_with_wrong_password(&app, &app.test_user.username).await;
+    }
+    let res = post_valid_login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn successful_logins_do_not_count_against_the_ip() {
+    // Arrange
+    let app = spawn_app_with(strict_login_throttle).await;
+
+    // Act - more successful logins than the per-IP threshold
+    for _ in 0..6 {
+        let res = post_valid_login(&app).await;
+
+        // Assert
+        assert_is_redirect_to(&res, "/admin/dashboard");
+        app.post_logout().await;
+    }
+}
+
+#[tokio::test]
+async fn concurrent_guesses_cannot_overshoot_the_threshold() {
+    // Arrange
+    let app = spawn_app_with(strict_login_throttle).await;
+    let username = &app.test_user.username;
+    tokio::join!(
+        post_login_with_wrong_password(&app, username),
+        post_login_with_wrong_password(&app, username),
+        post_login_with_wrong_password(&app, username),
+        post_login_with_wrong_password(&app, username),
+        post_login_with_wrong_password(&app, username),
+        post_login_with_wrong_password(&app, username),
+    );
+
+    // Act - none of the guesses were let through, the account is locked either way
+    let res = post_valid_login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn spoofed_forwarded_for_headers_do_not_get_around_the_ip_lockout() {
+    // Arrange - every guess claims to come from a different client
+    let app = spawn_app_with(strict_login_throttle).await;
+    for i in 0..5 {
+        app.api_client
+            .post(&format!("{}/login", &app.address))
+            .header("X-Forwarded-For", format!("203.0.113.{}", i))
+            .form(&serde_json::json!({
+                "username": Uuid::new_v4().to_string(),
+                "password": Uuid::new_v4().to_string()
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+    }
+
+    // Act
+    let res = post_valid_login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn failed_logins_are_progressively_delayed() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application.login_throttle.base_delay_milliseconds = 200;
+        c.application.login_throttle.max_delay_milliseconds = 1000;
+    })
+    .await;
+    post_login_with_wrong_password(&app, &app.test_user.username).await;
+
+    // Act - second failure waits `base_delay * 2`
+    let start = std::time::Instant::now();
+    post_login_with_wrong_password(&app, &app.test_user.username).await;
+
+    // Assert
+    assert!(start.elapsed() >= std::time::Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn lockout_also_applies_to_basic_auth_on_newsletters() {
+    // Arrange - lock the user out through the login form
+    let app = spawn_app_with(strict_login_throttle).await;
+    for _ in 0..3 {
+        post_login_with_wrong_password(&app, &app.test_user.username).await;
+    }
+
+    // Act - correct `Basic` credentials
+    let res = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 401);
+}
+
+// -- ARGON2 PARAMETER UPGRADE -- //
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
+
+#[tokio::test]
+async fn login_upgrades_hashes_with_weaker_parameters() {
+    // Arrange - test users are stored with m=15000,t=2,p=1, below the configured target
+    let app = spawn_app_with(|c| {
+        c.application.argon2 = Argon2Settings {
+            memory_kib: 19456,
+            iterations: 2,
+            parallelism: 1,
+        }
+    })
+    .await;
+    assert!(stored_password_hash(&app).await.contains("m=15000,t=2,p=1"));
+
+    // Act
+    let res = post_valid_login(&app).await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+
+    // Assert - the rehash runs after the response, poll for it
+    let mut upgraded = false;
+    for _ in 0..50 {
+        if stored_password_hash(&app).await.contains("m=19456,t=2,p=1") {
+            upgraded = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(upgraded, "password hash was not upgraded");
+    // same password still works against the new hash
+    app.post_logout().await;
+    let res = post_valid_login(&app).await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn hashes_with_current_parameters_are_left_alone() {
+    // Arrange - target matches the test user's hash
+    let app = spawn_app_with(|c| {
+        c.application.argon2 = Argon2Settings {
+            memory_kib: 15000,
+            iterations: 2,
+            parallelism: 1,
+        }
+    })
+    .await;
+    let before = stored_password_hash(&app).await;
+
+    // Act
+    post_valid_login(&app).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Assert
+    assert_eq!(stored_password_hash(&app).await, before);
+}
+
+#[tokio::test]
+async fn failed_logins_do_not_upgrade_hashes() {
+    // Arrange
+    let app = spawn_app().await;
+    let before = stored_password_hash(&app).await;
+
+    // Act
+    post_login_with_wrong_password(&app, &app.test_user.username).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Assert
+    assert_eq!(stored_password_hash(&app).await, before);
+}
@@ -1,7 +1,17 @@
 mod admin_dashboard;
+mod admin_newsletters;
+mod admin_outbox;
+mod admin_users;
+mod api_tokens;
+mod change_password;
+mod cli;
 mod health_check;
 mod helpers;
 mod login;
 mod newsletter;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_resend_confirmation;
+mod subscriptions_unsubscribe;
+mod two_factor;
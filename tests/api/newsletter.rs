@@ -1,4 +1,4 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
 use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
@@ -88,7 +88,8 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     let res = app.post_newsletters(newsletter_req_body).await;
 
     // Assert
-    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on `Drop` the newsletter email hasn't been sent
 }
 
@@ -117,10 +118,285 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let res = app.post_newsletters(newsletter_req_body).await;
 
     // Assert
-    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on `Drop` newsletter has been sent
 }
 
+#[tokio::test]
+async fn newsletters_are_personalized_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // not a valid `SubscriberName` - stands in for anything that would break the HTML part
+    sqlx::query!("UPDATE subscriptions SET name = 'Ursula <b>Le Guin</b>'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let confirmed_at = sqlx::query!("SELECT confirmed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .confirmed_at
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/emails/transactional"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_req_body = serde_json::json!({
+        "title": "News for {{ name }}",
+        "content": {
+            "text": "Hi {{ name }}, subscribed since {{ confirmed_at | date }}",
+            "html": "<p>Hi {{ name }}, subscribed since {{ confirmed_at | date }}</p>"
+        }
+    });
+    let res = app.post_newsletters(newsletter_req_body).await;
+    assert_eq!(res.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let since = confirmed_at.format("%B %-d, %Y").to_string();
+    assert_eq!(body["Subject"], "News for Ursula <b>Le Guin</b>");
+    assert_eq!(
+        body["TextBody"].as_str().unwrap().lines().next().unwrap(),
+        format!("Hi Ursula <b>Le Guin</b>, subscribed since {}", since)
+    );
+    assert!(body["HtmlBody"].as_str().unwrap().starts_with(&format!(
+        "<p>Hi Ursula &lt;b&gt;Le Guin&lt;/b&gt;, subscribed since {}</p>",
+        since
+    )));
+}
+
+#[tokio::test]
+async fn literal_braces_can_be_escaped() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(method("POST"))
+        .and(path("/emails/transactional"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_req_body = serde_json::json!({
+        "title": "Templating tips",
+        "content": {
+            "text": "Write {{{{ user }} in Jinja.",
+            "html": "<p>Write <code>{{{{ user }}</code> in Jinja.</p>"
+        }
+    });
+    let res = app.post_newsletters(newsletter_req_body).await;
+    assert_eq!(res.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Write {{ user }} in Jinja."));
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Write <code>{{ user }}</code> in Jinja.</p>"));
+}
+
+#[tokio::test]
+async fn markdown_newsletters_are_rendered_to_html_and_plain_text() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // a fixed name - a random one may contain characters that get html-escaped
+    sqlx::query!("UPDATE subscriptions SET name = 'Ada Lovelace'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/emails/transactional"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "markdown": "Hi **{{ name }}** - read [the post](https://example.com/post).\n\n<script>alert(1)</script>"
+    });
+    let res = app.post_newsletters(newsletter_req_body).await;
+    assert_eq!(res.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("Hi <strong>Ada Lovelace</strong>"));
+    assert!(html.contains(r#"<a href="https://example.com/post" style=""#));
+    assert!(!html.contains("<script>"));
+    assert!(!html.contains("alert(1)"));
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(
+        text.starts_with("Hi Ada Lovelace - read the post [1].\n\n[1] https://example.com/post")
+    );
+}
+
+#[tokio::test]
+async fn newsletters_are_queued_and_not_sent_inline() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .named("No inline delivery")
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+    let res = app.post_newsletters(newsletter_req_body).await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 202);
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 1);
+}
+
+#[tokio::test]
+async fn a_failed_delivery_does_not_abort_the_rest_of_the_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    // first send is rejected outright (not retried), every following one succeeds
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+    let res = app.post_newsletters(newsletter_req_body).await;
+    assert_eq!(res.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // the failed delivery stays queued for a later retry
+    let remaining = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS backed_off FROM issue_delivery_queue"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].n_retries, 1);
+    assert_eq!(remaining[0].backed_off, Some(true));
+    // Mock verifies on `Drop` both subscribers were attempted
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_and_recorded_once_out_of_retries() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // first attempt + 5 retries
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(6)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+    let res = app.post_newsletters(newsletter_req_body).await;
+    assert_eq!(res.status().as_u16(), 202);
+
+    // Act
+    for _ in 0..6 {
+        app.dispatch_all_pending_emails().await;
+        // skip the back off
+        sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+
+    // Assert
+    let remaining = sqlx::query!("SELECT count(*) AS count FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, Some(0));
+    let failed = sqlx::query!("SELECT n_retries, last_error FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.n_retries, 5);
+    assert!(!failed.last_error.is_empty());
+}
+
 #[tokio::test]
 async fn newsletters_returns_400_for_invalid_data() {
     // Arrange
@@ -138,6 +414,29 @@ async fn newsletters_returns_400_for_invalid_data() {
             serde_json::json!({"title": "Newsletter!"}),
             "missing content",
         ),
+        (
+            serde_json::json!({
+                        "title": "Newsletter!",
+                        "content": {
+                            "text": "Hi {{ nickname }}",
+                            "html": "<p>Hi {{ name }}</p>",
+                        } }),
+            "unknown placeholder",
+        ),
+        (
+            serde_json::json!({
+                        "title": "Newsletter!",
+                        "content": {
+                            "text": "Newsletter body as plain text",
+                            "html": "<p>Newsletter body as HTML</p>",
+                        },
+                        "markdown": "Newsletter body as *markdown*" }),
+            "both content and markdown",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!", "markdown": " \n "}),
+            "empty markdown",
+        ),
     ];
 
     for (invalid_body, err_msg) in test_cases {
@@ -180,47 +479,141 @@ async fn requests_missing_authorization_are_rejected() {
     );
 }
 
-// -- HELPERS for TESTS -- //
-
-// uses public API of app (under test) to create unconfirmed sub
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=mj%20hohams&email=mj_hohams%40gmail.com";
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
 
-    let _mock_guard = Mock::given(method("POST"))
-        .and(path("/emails/transactional"))
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
         .expect(1)
-        // returns `MockGuard` guard obj
-        // when `Drop`'d, `wiremock` tells `MockServer` to stop honoring specific mock behavior -> keeps mock behavior needed for test helper to `stay local`
-        .mount_as_scoped(&app.email_server)
-        // note: when `MockGuard` dropped, EAGERLY check expectations on the scope
+        .mount(&app.email_server)
         .await;
 
-    // create unconfirmed subscriber in database
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
 
-    // inspect req's received by Mock Elastic Email server - retrieve confirmation link
-    let email_req = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
+    // Act 1 - submit newsletter
+    let res = app
+        .post_newsletters_with_idempotency_key(newsletter_req_body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(res.status().as_u16(), 202);
+
+    // Act 2 - retry with the same key
+    let res = app
+        .post_newsletters_with_idempotency_key(newsletter_req_body, &idempotency_key)
+        .await;
+    assert_eq!(res.status().as_u16(), 202);
 
-    app.get_confirmation_links(&email_req)
+    // Assert
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on `Drop` newsletter was sent only once
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    // re use of above helper with extra step to call confirmation link
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
-        .await
+#[tokio::test]
+async fn drafts_submitted_through_the_api_are_stored_but_not_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        },
+        "draft": true
+    });
+
+    // Act
+    let res = app.post_newsletters(newsletter_req_body).await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 201);
+    let body: serde_json::Value = res.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
         .unwrap()
-        .error_for_status()
+        .parse()
         .unwrap();
+    let row = sqlx::query!(
+        "SELECT status, published_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(row.status, "draft");
+    assert!(row.published_at.is_none());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_submissions_are_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - submit two newsletter forms concurrently
+    let res1 =
+        app.post_newsletters_with_idempotency_key(newsletter_req_body.clone(), &idempotency_key);
+    let res2 = app.post_newsletters_with_idempotency_key(newsletter_req_body, &idempotency_key);
+    let (res1, res2) = tokio::join!(res1, res2);
+
+    // Assert
+    assert_eq!(res1.status(), res2.status());
+    assert_eq!(res1.text().await.unwrap(), res2.text().await.unwrap());
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on `Drop` newsletter was sent only once
+}
+
+#[tokio::test]
+async fn invalid_idempotency_key_is_rejected_with_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+
+    // Act
+    let res = app
+        .post_newsletters_with_idempotency_key(newsletter_req_body, &"a".repeat(50))
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 400);
 }
@@ -0,0 +1,467 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use std::time::Duration;
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = 'admin@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn mount_email_mock(app: &TestApp) {
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+// requests a reset for `test_user` and returns the token from the emailed link
+async fn request_reset_token(app: &TestApp) -> String {
+    let sent_before = app.email_server.received_requests().await.unwrap().len();
+    let res = app.post_forgot_password(&app.test_user.username).await;
+    assert_is_redirect_to(&res, "/login");
+    let email_reqs = app.wait_for_emails(sent_before + 1).await;
+    // same single-link email layout as the confirmation emails
+    let link = app.get_confirmation_links(email_reqs.last().unwrap()).html;
+    assert_eq!(link.path(), "/login/reset");
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+fn new_password_form(token: &str, password: &str) -> serde_json::Value {
+    serde_json::json!({
+        "token": token,
+        "new_password": password,
+        "new_password_check": password,
+    })
+}
+
+#[tokio::test]
+async fn forgot_password_emails_a_reset_link() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mount_email_mock(&app).await;
+
+    // Act
+    let token = request_reset_token(&app).await;
+
+    // Assert
+    let res = app
+        .api_client
+        .get(&format!("{}/login/reset?token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let stored = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+}
+
+#[tokio::test]
+async fn forgot_password_answers_the_same_for_unknown_users() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_mock(&app).await;
+
+    // Act - known username without an email address + unknown username
+    let no_email = app.post_forgot_password(&app.test_user.username).await;
+    let unknown = app.post_forgot_password(&Uuid::new_v4().to_string()).await;
+
+    // Assert
+    assert_is_redirect_to(&no_email, "/login");
+    assert_is_redirect_to(&unknown, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("If the account exists and has an email address"));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn reset_requests_are_throttled_per_username() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application
+            .login_throttle
+            .max_password_resets_per_username = 2;
+    })
+    .await;
+    set_test_user_email(&app).await;
+    mount_email_mock(&app).await;
+    request_reset_token(&app).await;
+    request_reset_token(&app).await;
+
+    // Act
+    let res = app.post_forgot_password(&app.test_user.username).await;
+
+    // Assert - no third email
+    assert_is_redirect_to(&res, "/login/forgot");
+    let html_page = app
+        .api_client
+        .get(&format!("{}/login/forgot", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Too many password reset requests - please try again later."));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn reset_requests_are_throttled_per_ip() {
+    // Arrange - different usernames so only the per-IP counter fills up
+    let app = spawn_app_with(|c| {
+        c.application.login_throttle.max_password_resets_per_ip = 2;
+    })
+    .await;
+    for _ in 0..2 {
+        let res = app.post_forgot_password(&Uuid::new_v4().to_string()).await;
+        assert_is_redirect_to(&res, "/login");
+    }
+
+    // Act
+    let res = app.post_forgot_password(&Uuid::new_v4().to_string()).await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login/forgot");
+}
+
+#[tokio::test]
+async fn reset_link_sets_a_new_password() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mount_email_mock(&app).await;
+    let token = request_reset_token(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let res = app
+        .post_reset_password(&new_password_form(&token, &new_password))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page
+        .contains("<p><i>Your password has been reset - log in with your new password.</i></p>"));
+    let res = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/login");
+    let res = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn reset_links_are_single_use() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mount_email_mock(&app).await;
+    let token = request_reset_token(&app).await;
+    let res = app
+        .post_reset_password(&new_password_form(&token, &Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirect_to(&res, "/login");
+
+    // Act
+    let res = app
+        .post_reset_password(&new_password_form(&token, &Uuid::new_v4().to_string()))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login/forgot");
+    let res = app
+        .api_client
+        .get(&format!("{}/login/reset?token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&res, "/login/forgot");
+}
+
+#[tokio::test]
+async fn expired_reset_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mount_email_mock(&app).await;
+    let token = request_reset_token(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let res = app
+        .post_reset_password(&new_password_form(&token, &Uuid::new_v4().to_string()))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login/forgot");
+    let res = app
+        .api_client
+        .get(&format!("{}/login/forgot", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let html_page = res.text().await.unwrap();
+    assert!(html_page.contains("<p><i>The password reset link is invalid or has expired.</i></p>"));
+}
+
+#[tokio::test]
+async fn requesting_a_new_link_invalidates_the_previous_one() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mount_email_mock(&app).await;
+    let first_token = request_reset_token(&app).await;
+    let second_token = request_reset_token(&app).await;
+
+    // Act
+    let res = app
+        .post_reset_password(&new_password_form(
+            &first_token,
+            &Uuid::new_v4().to_string(),
+        ))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login/forgot");
+    let res = app
+        .post_reset_password(&new_password_form(
+            &second_token,
+            &Uuid::new_v4().to_string(),
+        ))
+        .await;
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn invalid_new_passwords_do_not_consume_the_link() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mount_email_mock(&app).await;
+    let token = request_reset_token(&app).await;
+
+    // Act
+    let res = app
+        .post_reset_password(&new_password_form(&token, "short"))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, &format!("/login/reset?token={}", token));
+    let res = app
+        .api_client
+        .get(&format!("{}/login/reset?token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap();
+    let html_page = res.text().await.unwrap();
+    assert!(
+        html_page.contains("<p><i>The new password must be at least 12 characters long.</i></p>")
+    );
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_out_every_existing_session() {
+    // Arrange - logged in with `api_client`, reset done from another browser
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mount_email_mock(&app).await;
+    app.login_test_user().await;
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+    let token = request_reset_token(&app).await;
+    let other_browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+
+    // Act
+    let res = other_browser
+        .post(&format!("{}/login/reset", &app.address))
+        .form(&new_password_form(&token, &Uuid::new_v4().to_string()))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&res, "/login");
+
+    // Assert
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn admins_can_set_the_email_used_for_password_resets() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_mock(&app).await;
+    app.login_test_user().await;
+
+    // Act 1 - invalid address
+    let res = app
+        .post_email(&serde_json::json!({
+            "email": "not-an-email",
+            "current_password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/email");
+
+    // Act 2 - valid address
+    let res = app
+        .post_email(&serde_json::json!({
+            "email": "admin@example.com",
+            "current_password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/email");
+
+    // Assert
+    let html_page = app
+        .api_client
+        .get(&format!("{}/admin/email", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>Your email address has been updated.</i></p>"));
+    assert!(html_page.contains(r#"value="admin@example.com""#));
+    request_reset_token(&app).await;
+}
+
+async fn get_test_user_email(app: &TestApp) -> Option<String> {
+    sqlx::query!(
+        "SELECT email FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .email
+}
+
+#[tokio::test]
+async fn changing_the_email_requires_the_current_password() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+
+    // Act 1 - wrong password
+    let res = app
+        .post_email(&serde_json::json!({
+            "email": "attacker@example.com",
+            "current_password": Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    // Assert 1
+    assert_is_redirect_to(&res, "/admin/email");
+    let html_page = app
+        .api_client
+        .get(&format!("{}/admin/email", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+    assert_eq!(
+        get_test_user_email(&app).await.as_deref(),
+        Some("admin@example.com")
+    );
+
+    // Act 2 - no password at all
+    let res = app
+        .post_email(&serde_json::json!({ "email": "attacker@example.com" }))
+        .await;
+
+    // Assert 2
+    assert_eq!(res.status().as_u16(), 400);
+    assert_eq!(
+        get_test_user_email(&app).await.as_deref(),
+        Some("admin@example.com")
+    );
+}
+
+#[tokio::test]
+async fn the_previous_address_is_told_about_an_email_change() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.login_test_user().await;
+
+    // Act
+    let res = app
+        .post_email(&serde_json::json!({
+            "email": "new@example.com",
+            "current_password": &app.test_user.password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/admin/email");
+    assert_eq!(
+        get_test_user_email(&app).await.as_deref(),
+        Some("new@example.com")
+    );
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert_eq!(body["To"], "admin@example.com");
+    assert_eq!(body["Subject"], "Your account email address was changed");
+    // the new address isn't given away
+    assert!(!body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("new@example.com"));
+}
@@ -1,6 +1,7 @@
-use crate::helpers::spawn_app;
-use wiremock::matchers::{method, path};
+use crate::helpers::{create_confirmed_subscriber, spawn_app, spawn_app_with};
+use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::routes::unsubscribe_link;
 
 #[tokio::test]
 async fn subscribe_returns_200_for_valid_form_data() {
@@ -108,7 +109,8 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     app.post_subscriptions(body.into()).await;
 
     // Assert
-    // on `drop` expect asserted
+    // sent after the response - `expect` is asserted on `drop`
+    app.wait_for_emails(1).await;
 }
 
 #[tokio::test]
@@ -128,8 +130,8 @@ async fn subscribe_sends_confirmation_email_with_link() {
     app.post_subscriptions(body.into()).await;
 
     // Assert
-    let email_req = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_req);
+    let email_req = &app.wait_for_emails(1).await[0];
+    let confirmation_links = app.get_confirmation_links(email_req);
     // links should be identical
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
@@ -150,3 +152,219 @@ async fn subscribe_fails_if_fatal_db_error() {
     // Assert
     assert_eq!(res.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_again_while_pending_resends_confirmation_with_a_fresh_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=mj%20hohams&email=mj%5Fhohams%40gmail.com";
+
+    Mock::given(method("POST"))
+        .and(path("/emails/transactional"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first_res = app.post_subscriptions(body.into()).await;
+    // the emails go out in the background - wait so they arrive in order
+    app.wait_for_emails(1).await;
+    let second_res = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(first_res.status().as_u16(), 200);
+    assert_eq!(second_res.status().as_u16(), 200);
+    let email_reqs = app.wait_for_emails(2).await;
+    let first_link = app.get_confirmation_links(&email_reqs[0]).html;
+    let second_link = app.get_confirmation_links(&email_reqs[1]).html;
+    assert_ne!(first_link, second_link);
+    // only the latest link confirms
+    let res = reqwest::get(first_link).await.unwrap();
+    assert_eq!(res.status().as_u16(), 401);
+    let res = reqwest::get(second_link).await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_when_confirmed_returns_200_without_sending_an_email() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    let body = format!("name=mj%20hohams&email={}", urlencoding::encode(&email));
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let res = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 200);
+    // an email would go out after the response - give it time to show up
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_restarts_double_opt_in() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let mut link = reqwest::Url::parse(&unsubscribe_link(
+        &app.base_url,
+        subscriber.id,
+        &app.hmac_secret,
+    ))
+    .unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/emails/transactional"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = format!(
+        "name=mj%20hohams&email={}",
+        urlencoding::encode(&subscriber.email)
+    );
+    let sent_before = app.email_server.received_requests().await.unwrap().len();
+
+    // Act
+    let res = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT name, status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+    assert!(saved.unsubscribed_at.is_none());
+
+    // new confirmation link completes the opt-in again
+    let email_req = app.wait_for_emails(sent_before + 1).await.pop().unwrap();
+    let confirmation_link = app.get_confirmation_links(&email_req).html;
+    let res = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+    // the name from the new sign-up only applies once confirmed
+    assert_eq!(saved.name, "mj hohams");
+}
+
+#[tokio::test]
+async fn subscribing_again_while_pending_keeps_the_stored_name_until_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(method("POST"))
+        .and(path("/emails/transactional"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=mj%20hohams&email=mj%5Fhohams%40gmail.com".into())
+        .await;
+
+    // Act
+    app.post_subscriptions("name=someone%20else&email=mj%5Fhohams%40gmail.com".into())
+        .await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "mj hohams");
+    app.wait_for_emails(2).await;
+}
+
+#[tokio::test]
+async fn subscribing_shares_the_resend_confirmation_throttle() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application
+            .resend_confirmation_throttle
+            .max_attempts_per_email = 2;
+    })
+    .await;
+    let body = "name=mj%20hohams&email=mj%5Fhohams%40gmail.com";
+    Mock::given(method("POST"))
+        .and(path("/emails/transactional"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first = app.post_subscriptions(body.into()).await;
+    let second = app.post_subscriptions(body.into()).await;
+    let third = app.post_subscriptions(body.into()).await;
+    let resend = app.post_resend_confirmation("mj_hohams@gmail.com").await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(third.status().as_u16(), 429);
+    assert_eq!(resend.status().as_u16(), 429);
+    app.wait_for_emails(2).await;
+}
+
+#[tokio::test]
+async fn concurrent_sign_ups_for_a_new_address_both_succeed() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=mj%20hohams&email=mj%5Fhohams%40gmail.com";
+    Mock::given(method("POST"))
+        .and(path("/emails/transactional"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (first, second) = tokio::join!(
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into())
+    );
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let count = sqlx::query!("SELECT count(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count.count, Some(1));
+}
@@ -1,6 +1,7 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{create_unconfirmed_subscriber, spawn_app};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::token_purge_worker::purge_stale_subscription_tokens;
 
 #[tokio::test]
 async fn link_returned_by_subscribe_returns_200_if_called() {
@@ -15,8 +16,8 @@ async fn link_returned_by_subscribe_returns_200_if_called() {
         .await;
 
     app.post_subscriptions(body.into()).await;
-    let email_req = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_req);
+    let email_req = &app.wait_for_emails(1).await[0];
+    let confirmation_links = app.get_confirmation_links(email_req);
 
     // Act
     let res = reqwest::get(confirmation_links.html).await.unwrap();
@@ -52,8 +53,8 @@ async fn clicking_confirmation_link_confirms_subscriber() {
         .await;
 
     app.post_subscriptions(body.into()).await;
-    let email_req = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_req);
+    let email_req = &app.wait_for_emails(1).await[0];
+    let confirmation_links = app.get_confirmation_links(email_req);
 
     // Act
     reqwest::get(confirmation_links.html)
@@ -63,7 +64,7 @@ async fn clicking_confirmation_link_confirms_subscriber() {
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+    let saved = sqlx::query!("SELECT email, name, status, confirmed_at FROM subscriptions",)
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription during testing");
@@ -71,4 +72,99 @@ async fn clicking_confirmation_link_confirms_subscriber() {
     assert_eq!(saved.email, "mj_hohams@gmail.com");
     assert_eq!(saved.name, "mj hohams");
     assert_eq!(saved.status, "confirmed");
+    assert!(saved.confirmed_at.is_some());
+}
+
+#[tokio::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let res = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 410);
+    let saved = sqlx::query!("SELECT consumed_at FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.consumed_at.is_some());
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_are_rejected_with_a_resend_page() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    // default TTL is 24 hours
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '25 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let res = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 410);
+    let html = res.text().await.unwrap();
+    assert!(html.contains("expired"));
+    assert!(html.contains(r#"<form action="/subscriptions/resend_confirmation" method="post">"#));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn unknown_confirmation_tokens_are_rejected_with_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let res = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=not-a-real-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn purge_removes_only_stale_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = now() - interval '25 hours' \
+        WHERE subscription_token = (SELECT subscription_token FROM subscription_tokens LIMIT 1)"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let purged = purge_stale_subscription_tokens(&app.db_pool, chrono::Duration::hours(24))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(purged, 1);
+    let remaining = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
 }
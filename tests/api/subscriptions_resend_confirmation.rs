@@ -0,0 +1,268 @@
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, spawn_app_with, TestApp,
+};
+use std::time::{Duration, Instant};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn resend_sends_a_new_link_to_pending_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let old_links = create_unconfirmed_subscriber(&app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let sent_before = app.email_server.received_requests().await.unwrap().len();
+
+    // Act
+    let res = app.post_resend_confirmation(&email).await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 200);
+    let email_req = app.wait_for_emails(sent_before + 1).await.pop().unwrap();
+    let new_links = app.get_confirmation_links(&email_req);
+    assert_ne!(old_links.html, new_links.html);
+    // token was rotated
+    let res = reqwest::get(old_links.html).await.unwrap();
+    assert_eq!(res.status().as_u16(), 401);
+    let res = reqwest::get(new_links.html).await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resend_responds_identically_for_unknown_and_confirmed_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let confirmed_email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let confirmed_res = app.post_resend_confirmation(&confirmed_email).await;
+    let unknown_res = app.post_resend_confirmation("nobody@example.com").await;
+
+    // Assert
+    assert_eq!(confirmed_res.status().as_u16(), 200);
+    assert_eq!(unknown_res.status().as_u16(), 200);
+    assert_eq!(
+        confirmed_res.text().await.unwrap(),
+        unknown_res.text().await.unwrap()
+    );
+    // an email would go out after the response - give it time to show up
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+#[tokio::test]
+async fn resend_does_not_wait_for_the_email_provider() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    // a slow provider - a pending address shouldn't answer any later than an unknown one
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let started = Instant::now();
+    let res = app.post_resend_confirmation(&email).await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn resend_returns_400_for_an_invalid_email() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let res = app
+        .post_resend_confirmation("definitely-not-an-email")
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn resend_is_throttled_per_address() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application
+            .resend_confirmation_throttle
+            .max_attempts_per_email = 3;
+    })
+    .await;
+    // signing up uses one of the 3 attempts - `/subscriptions` shares the limit
+    create_unconfirmed_subscriber(&app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let responses = vec![
+        app.post_resend_confirmation(&email).await,
+        app.post_resend_confirmation(&email).await,
+        // different case is still the same address
+        app.post_resend_confirmation(&email.to_uppercase()).await,
+    ];
+
+    // Assert
+    let statuses: Vec<u16> = responses.iter().map(|r| r.status().as_u16()).collect();
+    assert_eq!(statuses, vec![200, 200, 429]);
+    // sign-up + 2 resends - sent after the responses
+    app.wait_for_emails(3).await;
+}
+
+#[tokio::test]
+async fn resend_is_throttled_per_ip() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application
+            .resend_confirmation_throttle
+            .max_attempts_per_ip = 2;
+    })
+    .await;
+
+    // Act
+    let first = app.post_resend_confirmation("first@example.com").await;
+    let second = app.post_resend_confirmation("second@example.com").await;
+    let third = app.post_resend_confirmation("third@example.com").await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(third.status().as_u16(), 429);
+}
+
+// `X-Forwarded-For` set by the client itself - the test server isn't behind a trusted proxy
+async fn post_resend_confirmation_via(
+    app: &TestApp,
+    email: &str,
+    forwarded_for: &str,
+) -> reqwest::Response {
+    app.api_client
+        .post(&format!(
+            "{}/subscriptions/resend_confirmation",
+            &app.address
+        ))
+        .header("X-Forwarded-For", forwarded_for)
+        .form(&[("email", email)])
+        .send()
+        .await
+        .expect("Failed to execute POST request")
+}
+
+#[tokio::test]
+async fn spoofed_forwarded_for_headers_do_not_get_around_the_ip_throttle() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application
+            .resend_confirmation_throttle
+            .max_attempts_per_ip = 2;
+    })
+    .await;
+
+    // Act
+    let first = post_resend_confirmation_via(&app, "first@example.com", "203.0.113.1").await;
+    let second = post_resend_confirmation_via(&app, "second@example.com", "203.0.113.2").await;
+    let third = post_resend_confirmation_via(&app, "third@example.com", "203.0.113.3").await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(third.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn forwarded_for_headers_from_trusted_proxies_are_believed() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application.trusted_proxies = vec!["127.0.0.1".into()];
+        c.application
+            .resend_confirmation_throttle
+            .max_attempts_per_ip = 2;
+    })
+    .await;
+
+    // Act
+    let first = post_resend_confirmation_via(&app, "first@example.com", "203.0.113.1").await;
+    let second = post_resend_confirmation_via(&app, "second@example.com", "203.0.113.1").await;
+    let other_client = post_resend_confirmation_via(&app, "third@example.com", "203.0.113.2").await;
+    let third = post_resend_confirmation_via(&app, "fourth@example.com", "203.0.113.1").await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(other_client.status().as_u16(), 200);
+    assert_eq!(third.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn throttle_resets_once_the_window_has_elapsed() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application
+            .resend_confirmation_throttle
+            .max_attempts_per_email = 1;
+    })
+    .await;
+    app.post_resend_confirmation("someone@example.com").await;
+    assert_eq!(
+        app.post_resend_confirmation("someone@example.com")
+            .await
+            .status()
+            .as_u16(),
+        429
+    );
+    sqlx::query!(
+        "UPDATE resend_confirmation_throttle SET window_started_at = now() - interval '2 hours'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let res = app.post_resend_confirmation("someone@example.com").await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 200);
+}
@@ -0,0 +1,308 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+// publish an issue to the (single) confirmed subscriber and return the request sent to the email API
+async fn deliver_newsletter(app: &TestApp) -> wiremock::Request {
+    let _mock_guard = Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let res = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .await;
+    assert_eq!(res.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn newsletters_include_a_one_click_unsubscribe_link() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let email_req = deliver_newsletter(&app).await;
+
+    // Assert
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.iter().any(|header| {
+        header["Name"] == "List-Unsubscribe-Post" && header["Value"] == "List-Unsubscribe=One-Click"
+    }));
+    // also checks the link is in both bodies
+    app.get_unsubscribe_link(&email_req);
+}
+
+#[tokio::test]
+async fn no_footer_is_added_when_the_issue_links_to_unsubscribe_itself() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/emails/transactional"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let res = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Had enough? {{ unsubscribe_url }}",
+                "html": "<p><a href=\"{{ unsubscribe_url }}\">Had enough?</a></p>"
+            }
+        }))
+        .await;
+    assert_eq!(res.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - the author's link only, once per body
+    // the confirmation email went out first
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(!html_body.contains("Unsubscribe"));
+    assert!(!text_body.contains("Unsubscribe"));
+    assert_eq!(text_body.matches("/subscriptions/unsubscribe?").count(), 1);
+    assert_eq!(html_body.matches("/subscriptions/unsubscribe?").count(), 1);
+}
+
+#[tokio::test]
+async fn get_on_unsubscribe_link_does_not_unsubscribe() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email_req = deliver_newsletter(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&email_req);
+
+    // Act
+    let res = reqwest::get(unsubscribe_link).await.unwrap();
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(res.text().await.unwrap().contains("<form"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn one_click_post_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email_req = deliver_newsletter(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&email_req);
+
+    // Act - same request a mail client sends for RFC 8058
+    let res = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    assert!(saved.unsubscribed_at.is_some());
+}
+
+#[tokio::test]
+async fn unsubscribing_twice_keeps_the_original_timestamp() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email_req = deliver_newsletter(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&email_req);
+    let client = reqwest::Client::new();
+    client
+        .post(unsubscribe_link.clone())
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let first = sqlx::query!("SELECT unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let res = client.post(unsubscribe_link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 200);
+    let second = sqlx::query!("SELECT unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(first.unsubscribed_at, second.unsubscribed_at);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email_req = deliver_newsletter(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&email_req);
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let res = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on `Drop` nothing was sent
+}
+
+#[tokio::test]
+async fn subscribers_who_unsubscribe_after_publishing_are_skipped() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email_req = deliver_newsletter(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&email_req);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let res = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .await;
+    assert_eq!(res.status().as_u16(), 202);
+
+    // Act - unsubscribe while the issue is still queued
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let remaining = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(remaining.is_empty());
+    // Mock verifies on `Drop` nothing was sent
+}
+
+#[tokio::test]
+async fn a_tampered_token_is_rejected_with_401() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email_req = deliver_newsletter(&app).await;
+    let mut unsubscribe_link = app.get_unsubscribe_link(&email_req);
+    // valid token, but for a different subscriber
+    let token = unsubscribe_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    unsubscribe_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("subscriber_id", &Uuid::new_v4().to_string())
+        .append_pair("token", &token);
+
+    // Act
+    let get_res = reqwest::get(unsubscribe_link.clone()).await.unwrap();
+    let post_res = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(get_res.status().as_u16(), 401);
+    assert_eq!(post_res.status().as_u16(), 401);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribe_without_parameters_is_rejected_with_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let res = reqwest::get(&format!("{}/subscriptions/unsubscribe", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 400);
+}
@@ -0,0 +1,300 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use chrono::{Duration, Utc};
+use zero2prod::authentication::TotpSecret;
+
+// the enrollment used up the current time step - the next one is still inside the accepted window
+fn next_code(secret: &TotpSecret) -> String {
+    secret.code_at(Utc::now() + Duration::seconds(30))
+}
+
+// password step only - leaves a pending 2FA session behind
+async fn log_out_and_enter_password(app: &TestApp) {
+    app.post_logout().await;
+    let res = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/login/2fa");
+}
+
+#[tokio::test]
+async fn login_requires_a_second_step_once_two_factor_is_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = app.enable_two_factor().await;
+
+    // Act 1 - password only
+    log_out_and_enter_password(&app).await;
+
+    // Assert 1 - not logged in yet
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+    assert_eq!(app.get_login_two_factor().await.status().as_u16(), 200);
+
+    // Act 2 - second factor
+    let res = app.post_login_two_factor(&next_code(&secret)).await;
+
+    // Assert 2
+    assert_is_redirect_to(&res, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn invalid_codes_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.enable_two_factor().await;
+    log_out_and_enter_password(&app).await;
+
+    // Act
+    let res = app.post_login_two_factor("000000").await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login/2fa");
+    let html_page = app.get_login_two_factor().await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>Invalid authentication code.</i></p>"));
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn codes_cannot_be_replayed() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = app.enable_two_factor().await;
+    let code = next_code(&secret);
+    log_out_and_enter_password(&app).await;
+    let res = app.post_login_two_factor(&code).await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+
+    // Act - same code for a new login
+    log_out_and_enter_password(&app).await;
+    let res = app.post_login_two_factor(&code).await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login/2fa");
+}
+
+#[tokio::test]
+async fn recovery_codes_work_exactly_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, recovery_codes) = app.enable_two_factor().await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    // Act 1 - typed in upper case, still accepted
+    log_out_and_enter_password(&app).await;
+    let res = app
+        .post_login_two_factor(&recovery_codes[0].to_uppercase())
+        .await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Unused recovery codes: 9."));
+
+    // Act 2 - reused
+    log_out_and_enter_password(&app).await;
+    let res = app.post_login_two_factor(&recovery_codes[0]).await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/login/2fa");
+}
+
+#[tokio::test]
+async fn too_many_invalid_codes_drop_the_pending_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = app.enable_two_factor().await;
+    log_out_and_enter_password(&app).await;
+
+    // Act
+    for _ in 0..4 {
+        let res = app.post_login_two_factor("000000").await;
+        assert_is_redirect_to(&res, "/login/2fa");
+    }
+    let res = app.post_login_two_factor("000000").await;
+
+    // Assert - back to the password step, a valid code alone doesn't help anymore
+    assert_is_redirect_to(&res, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page
+        .contains("<p><i>Too many invalid authentication codes - please try again later.</i></p>"));
+    let res = app.post_login_two_factor(&next_code(&secret)).await;
+    assert_is_redirect_to(&res, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn invalid_codes_are_counted_across_logins() {
+    // Arrange - a few wrong codes, then start over with the password
+    let app = spawn_app().await;
+    let (secret, _) = app.enable_two_factor().await;
+    log_out_and_enter_password(&app).await;
+    for _ in 0..4 {
+        app.post_login_two_factor("000000").await;
+    }
+    log_out_and_enter_password(&app).await;
+
+    // Act - the fresh pending login doesn't come with fresh guesses
+    let res = app.post_login_two_factor("000000").await;
+
+    // Assert - locked out, even for a valid code
+    assert_is_redirect_to(&res, "/login");
+    log_out_and_enter_password(&app).await;
+    let res = app.post_login_two_factor(&next_code(&secret)).await;
+    assert_is_redirect_to(&res, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn basic_auth_is_rejected_once_two_factor_is_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.enable_two_factor().await;
+
+    // Act - the right username and password
+    let res = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(res.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_pending_two_factor_login_replaces_an_existing_session() {
+    // Arrange - logged in as another user in the same browser
+    let app = spawn_app().await;
+    app.enable_two_factor().await;
+    app.post_logout().await;
+    let editor = app.add_user("editor").await;
+    app.login_as(&editor).await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", editor.username)));
+
+    // Act - password step for the 2FA user
+    let res = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+
+    // Assert - no longer logged in as anyone
+    assert_is_redirect_to(&res, "/login/2fa");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn second_step_requires_a_pending_login() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let get = app.get_login_two_factor().await;
+    let post = app.post_login_two_factor("123456").await;
+
+    // Assert
+    assert_is_redirect_to(&get, "/login");
+    assert_is_redirect_to(&post, "/login");
+}
+
+#[tokio::test]
+async fn enrollment_is_not_enabled_without_a_valid_code() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    app.post_two_factor("enroll", &serde_json::json!({})).await;
+
+    // Act
+    let res = app
+        .post_two_factor("confirm", &serde_json::json!({ "code": "000000" }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&res, "/admin/two_factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("The authentication code is invalid"));
+    assert!(html_page.contains("Two-factor authentication is <b>disabled</b>."));
+    // the password alone still logs in
+    app.post_logout().await;
+    let res = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn totp_secrets_are_stored_encrypted() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let (secret, recovery_codes) = app.enable_two_factor().await;
+
+    // Assert
+    let row = sqlx::query!(
+        "SELECT totp_secret_encrypted, totp_enabled_at FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(row.totp_enabled_at.is_some());
+    let stored = row.totp_secret_encrypted.unwrap();
+    assert!(!stored.to_uppercase().contains(&secret.to_base32()[..16]));
+    let code_hashes = sqlx::query!("SELECT code_hash FROM totp_recovery_codes")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(code_hashes.len(), 10);
+    assert!(code_hashes
+        .iter()
+        .all(|row| !recovery_codes.contains(&row.code_hash)));
+}
+
+#[tokio::test]
+async fn two_factor_can_be_disabled_with_a_valid_code() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = app.enable_two_factor().await;
+
+    // Act 1 - wrong code
+    let res = app
+        .post_two_factor("disable", &serde_json::json!({ "code": "000000" }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/two_factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>The authentication code is invalid.</i></p>"));
+    assert!(html_page.contains("Two-factor authentication is <b>enabled</b>."));
+
+    // Act 2 - valid code
+    let res = app
+        .post_two_factor(
+            "disable",
+            &serde_json::json!({ "code": next_code(&secret) }),
+        )
+        .await;
+    assert_is_redirect_to(&res, "/admin/two_factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Two-factor authentication has been disabled.</i></p>"));
+
+    // Assert - back to password-only logins
+    app.post_logout().await;
+    let res = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+}